@@ -0,0 +1,402 @@
+use crate::halley::versions::common::config::{ConfigNode, ConfigNodeMap};
+use nom::{
+    bytes::complete::{tag, take},
+    multi::length_data,
+    number::complete::{le_u16, le_u32, le_u64, le_u8},
+    sequence::tuple,
+    IResult,
+};
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AudioFormat {
+    Vorbis,
+    Opus,
+    Wav,
+    /// Not recognised, e.g. raw PCM or a corrupt clip. Unpacked without an extension of its own.
+    Unknown,
+}
+
+impl AudioFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            AudioFormat::Vorbis => ".ogg",
+            AudioFormat::Opus => ".opus",
+            AudioFormat::Wav => ".wav",
+            AudioFormat::Unknown => "",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioFormat::Vorbis => "vorbis",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Wav => "wav",
+            AudioFormat::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AudioClipInfo {
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub loop_start: Option<u64>,
+    pub loop_end: Option<u64>,
+}
+
+pub fn get_audio_ext_from_path(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or_default();
+    match ext {
+        "ogg" => ".ogg",
+        "opus" => ".opus",
+        "wav" => ".wav",
+        _ => "",
+    }
+}
+
+/// Detects the payload format from its magic bytes.
+pub fn sniff_audio_format(data: &[u8]) -> AudioFormat {
+    if data.starts_with(b"OggS") {
+        match read_ogg_packets(data, 1).first() {
+            Some(packet) if packet.starts_with(b"OpusHead") => AudioFormat::Opus,
+            _ => AudioFormat::Vorbis,
+        }
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WAVE" {
+        AudioFormat::Wav
+    } else {
+        AudioFormat::Unknown
+    }
+}
+
+pub fn get_audio_clip_info(data: &[u8], format: AudioFormat) -> AudioClipInfo {
+    match format {
+        AudioFormat::Vorbis => get_vorbis_info(data),
+        AudioFormat::Opus => get_opus_info(data),
+        AudioFormat::Wav => get_wav_info(data),
+        AudioFormat::Unknown => AudioClipInfo::default(),
+    }
+}
+
+/// Properties describing the clip, shown next to the stored ones on unpack. Nothing is known about
+/// a clip in an unknown format.
+pub fn get_audio_clip_properties(data: &[u8]) -> ConfigNodeMap {
+    let format = sniff_audio_format(data);
+    let mut props = ConfigNodeMap::new();
+    if format == AudioFormat::Unknown {
+        return props;
+    }
+    let info = get_audio_clip_info(data, format);
+
+    props.insert(
+        "audio_format".to_string(),
+        ConfigNode::String(format.name().to_string()),
+    );
+    let entries = [
+        ("channels", info.channels.map(u64::from)),
+        ("sample_rate", info.sample_rate.map(u64::from)),
+        ("loop_start", info.loop_start),
+        ("loop_end", info.loop_end),
+    ];
+    for (key, value) in entries {
+        if let Some(value) = value {
+            let node = match i32::try_from(value) {
+                Ok(v) => ConfigNode::Int(v),
+                Err(_) => ConfigNode::Int64(value as i64),
+            };
+            props.insert(key.to_string(), node);
+        }
+    }
+    props
+}
+
+fn get_vorbis_info(data: &[u8]) -> AudioClipInfo {
+    let packets = read_ogg_packets(data, 2);
+    let mut info = AudioClipInfo::default();
+
+    if let Some(ident) = packets.first() {
+        let header: IResult<&[u8], _> =
+            tuple((tag(b"\x01vorbis"), le_u32, le_u8, le_u32))(ident.as_slice());
+        if let Ok((_, (_, _version, channels, sample_rate))) = header {
+            info.channels = Some(channels as u32);
+            info.sample_rate = Some(sample_rate);
+        }
+    }
+
+    if let Some(comments) = packets.get(1) {
+        let comments = parse_vorbis_comments(comments, b"\x03vorbis");
+        set_loop_points_from_comments(&mut info, &comments);
+    }
+    info
+}
+
+fn get_opus_info(data: &[u8]) -> AudioClipInfo {
+    let packets = read_ogg_packets(data, 2);
+    let mut info = AudioClipInfo::default();
+
+    if let Some(head) = packets.first() {
+        let header: IResult<&[u8], _> =
+            tuple((tag(b"OpusHead"), le_u8, le_u8, le_u16, le_u32))(head.as_slice());
+        if let Ok((_, (_, _version, channels, _pre_skip, _input_sample_rate))) = header {
+            info.channels = Some(channels as u32);
+            // Opus always decodes at 48kHz, the header only records the original input rate
+            info.sample_rate = Some(48000);
+        }
+    }
+
+    if let Some(tags) = packets.get(1) {
+        let comments = parse_vorbis_comments(tags, b"OpusTags");
+        set_loop_points_from_comments(&mut info, &comments);
+    }
+    info
+}
+
+fn get_wav_info(data: &[u8]) -> AudioClipInfo {
+    let mut info = AudioClipInfo::default();
+    let mut i = &data[12.min(data.len())..];
+
+    let chunk =
+        |i| -> IResult<&[u8], (&[u8], &[u8])> { tuple((take(4usize), length_data(le_u32)))(i) };
+
+    while let Ok((rest, (id, body))) = chunk(i) {
+        match id {
+            b"fmt " => {
+                let fmt: IResult<&[u8], _> = tuple((le_u16, le_u16, le_u32))(body);
+                if let Ok((_, (_format, channels, sample_rate))) = fmt {
+                    info.channels = Some(channels as u32);
+                    info.sample_rate = Some(sample_rate);
+                }
+            }
+            b"smpl" => {
+                // 36 byte header, followed by 24 byte loop records (id, type, start, end, ...)
+                let first_loop: IResult<&[u8], _> = tuple((
+                    take(28usize),
+                    le_u32,
+                    take(4usize),
+                    take(8usize),
+                    le_u32,
+                    le_u32,
+                ))(body);
+                if let Ok((_, (_, num_loops, _, _, start, end))) = first_loop {
+                    if num_loops > 0 {
+                        info.loop_start = Some(start as u64);
+                        info.loop_end = Some(end as u64);
+                    }
+                }
+            }
+            _ => {}
+        }
+        // chunks are padded to an even size
+        i = if body.len() % 2 == 1 && !rest.is_empty() {
+            &rest[1..]
+        } else {
+            rest
+        };
+    }
+    info
+}
+
+fn set_loop_points_from_comments(info: &mut AudioClipInfo, comments: &[(String, String)]) {
+    let get = |key: &str| {
+        comments
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.trim().parse::<u64>().ok())
+    };
+    info.loop_start = get("LOOPSTART");
+    info.loop_end = get("LOOPEND").or_else(|| {
+        let length = get("LOOPLENGTH")?;
+        Some(info.loop_start.unwrap_or(0) + length)
+    });
+}
+
+fn parse_vorbis_comments(packet: &[u8], magic: &[u8]) -> Vec<(String, String)> {
+    let comment = |i| -> IResult<&[u8], Option<(String, String)>> {
+        let (i, c) = length_data(le_u32)(i)?;
+        let c = String::from_utf8_lossy(c);
+        Ok((
+            i,
+            c.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        ))
+    };
+
+    let header: IResult<&[u8], _> = tuple((tag(magic), length_data(le_u32), le_u32))(packet);
+    let Ok((mut i, (_, _vendor, count))) = header else {
+        return vec![];
+    };
+
+    let mut comments = vec![];
+    for _ in 0..count {
+        match comment(i) {
+            Ok((rest, c)) => {
+                comments.extend(c);
+                i = rest;
+            }
+            Err(_) => break,
+        }
+    }
+    comments
+}
+
+/// Reassembles the first `max_packets` packets of the first logical stream in an Ogg container.
+fn read_ogg_packets(data: &[u8], max_packets: usize) -> Vec<Vec<u8>> {
+    let page_header = |i| -> IResult<&[u8], (u32, Vec<u8>)> {
+        let (i, (_, _version, _header_type, _granule, serial, _seq, _crc, segments)) =
+            tuple((
+                tag(b"OggS"),
+                le_u8,
+                le_u8,
+                le_u64,
+                le_u32,
+                le_u32,
+                le_u32,
+                length_data(le_u8),
+            ))(i)?;
+        Ok((i, (serial, segments.to_vec())))
+    };
+
+    let mut packets = vec![];
+    let mut current = vec![];
+    let mut stream = None;
+    let mut i = data;
+
+    while packets.len() < max_packets {
+        let Ok((rest, (serial, segments))) = page_header(i) else {
+            break;
+        };
+        let page_size: usize = segments.iter().map(|s| *s as usize).sum();
+        if rest.len() < page_size {
+            break;
+        }
+        let (body, rest) = rest.split_at(page_size);
+        i = rest;
+
+        if *stream.get_or_insert(serial) != serial {
+            continue;
+        }
+
+        let mut pos = 0;
+        for segment in segments {
+            let segment = segment as usize;
+            current.extend_from_slice(&body[pos..pos + segment]);
+            pos += segment;
+            if segment < 255 {
+                packets.push(std::mem::take(&mut current));
+                if packets.len() == max_packets {
+                    break;
+                }
+            }
+        }
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ogg_page(packets: &[&[u8]]) -> Vec<u8> {
+        let mut segments = vec![];
+        let mut body = vec![];
+        for packet in packets {
+            let mut n = packet.len();
+            while n >= 255 {
+                segments.push(255);
+                n -= 255;
+            }
+            segments.push(n as u8);
+            body.extend_from_slice(packet);
+        }
+        let mut page = b"OggS".to_vec();
+        page.extend([0, 0]);
+        page.extend(0_u64.to_le_bytes());
+        page.extend(7_u32.to_le_bytes());
+        page.extend(0_u32.to_le_bytes());
+        page.extend(0_u32.to_le_bytes());
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        page.extend(body);
+        page
+    }
+
+    fn comments_packet(magic: &[u8], comments: &[&str]) -> Vec<u8> {
+        let mut packet = magic.to_vec();
+        packet.extend(0_u32.to_le_bytes());
+        packet.extend((comments.len() as u32).to_le_bytes());
+        for c in comments {
+            packet.extend((c.len() as u32).to_le_bytes());
+            packet.extend(c.as_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn test_sniff_vorbis() {
+        let mut ident = b"\x01vorbis".to_vec();
+        ident.extend(0_u32.to_le_bytes());
+        ident.push(2);
+        ident.extend(44100_u32.to_le_bytes());
+        ident.extend([0; 13]);
+        let comments = comments_packet(b"\x03vorbis", &["LOOPSTART=1000", "LOOPLENGTH=500"]);
+        let data = [ogg_page(&[&ident]), ogg_page(&[&comments])].concat();
+
+        assert_eq!(sniff_audio_format(&data), AudioFormat::Vorbis);
+        assert_eq!(
+            get_audio_clip_info(&data, AudioFormat::Vorbis),
+            AudioClipInfo {
+                channels: Some(2),
+                sample_rate: Some(44100),
+                loop_start: Some(1000),
+                loop_end: Some(1500),
+            }
+        );
+    }
+
+    #[test]
+    fn test_sniff_opus() {
+        let mut head = b"OpusHead".to_vec();
+        head.extend([1, 1]);
+        head.extend(312_u16.to_le_bytes());
+        head.extend(22050_u32.to_le_bytes());
+        let data = [
+            ogg_page(&[&head]),
+            ogg_page(&[&comments_packet(b"OpusTags", &[])]),
+        ]
+        .concat();
+
+        assert_eq!(sniff_audio_format(&data), AudioFormat::Opus);
+        let info = get_audio_clip_info(&data, AudioFormat::Opus);
+        assert_eq!(info.channels, Some(1));
+        assert_eq!(info.sample_rate, Some(48000));
+    }
+
+    #[test]
+    fn test_sniff_wav_and_unknown() {
+        let mut data = b"RIFF".to_vec();
+        data.extend(0_u32.to_le_bytes());
+        data.extend(b"WAVE");
+        data.extend(b"fmt ");
+        data.extend(16_u32.to_le_bytes());
+        data.extend(1_u16.to_le_bytes());
+        data.extend(2_u16.to_le_bytes());
+        data.extend(48000_u32.to_le_bytes());
+        data.extend([0; 8]);
+
+        assert_eq!(sniff_audio_format(&data), AudioFormat::Wav);
+        let info = get_audio_clip_info(&data, AudioFormat::Wav);
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.sample_rate, Some(48000));
+
+        assert_eq!(get_audio_clip_properties(&data).len(), 3);
+
+        let unknown = [0, 1, 2, 3];
+        assert_eq!(sniff_audio_format(&unknown), AudioFormat::Unknown);
+        assert_eq!(AudioFormat::Unknown.ext(), "");
+        assert!(get_audio_clip_properties(&unknown).is_empty());
+    }
+}
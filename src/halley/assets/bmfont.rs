@@ -0,0 +1,239 @@
+use super::serialization::{
+    deserialize, get_format_from_ext, get_serialization_ext_from_path, serialize,
+};
+use crate::halley::versions::common::hpk::{find_asset_data, HalleyPack, Parsable};
+use anyhow::anyhow;
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Write, path::Path};
+
+/// Font in the AngelCode BMFont text format, limited to a single page.
+#[derive(Debug, Default, PartialEq)]
+pub struct BmFont {
+    pub face: String,
+    pub size: i32,
+    pub line_height: i32,
+    pub base: i32,
+    pub scale_w: i32,
+    pub scale_h: i32,
+    pub page_file: String,
+    pub chars: Vec<BmChar>,
+    pub kernings: Vec<BmKerning>,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct BmChar {
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub xoffset: i32,
+    pub yoffset: i32,
+    pub xadvance: i32,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct BmKerning {
+    pub first: i32,
+    pub second: i32,
+    pub amount: i32,
+}
+
+/// Implemented by the font models of each pack version.
+pub trait BmFontConvertible {
+    fn image_name(&self) -> &str;
+    fn to_bmfont(&self) -> BmFont;
+    fn apply_bmfont(&mut self, font: &BmFont);
+}
+
+impl BmFont {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing=0,0",
+            self.face, self.size
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "common lineHeight={} base={} scaleW={} scaleH={} pages=1 packed=0",
+            self.line_height, self.base, self.scale_w, self.scale_h
+        )
+        .unwrap();
+        writeln!(out, "page id=0 file=\"{}\"", self.page_file).unwrap();
+        writeln!(out, "chars count={}", self.chars.len()).unwrap();
+        for c in &self.chars {
+            writeln!(
+                out,
+                "char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} page=0 chnl=15",
+                c.id, c.x, c.y, c.width, c.height, c.xoffset, c.yoffset, c.xadvance
+            )
+            .unwrap();
+        }
+        if !self.kernings.is_empty() {
+            writeln!(out, "kernings count={}", self.kernings.len()).unwrap();
+            for k in &self.kernings {
+                writeln!(
+                    out,
+                    "kerning first={} second={} amount={}",
+                    k.first, k.second, k.amount
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut font = BmFont::default();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let (tag, attrs) = parse_line(line);
+            let get = |key: &str| -> Result<i32, anyhow::Error> {
+                attrs
+                    .get(key)
+                    .and_then(|v| v.parse::<i32>().ok())
+                    .ok_or_else(|| {
+                        anyhow!("Missing or invalid {} on line {}", key, line_number + 1)
+                    })
+            };
+            match tag {
+                "info" => {
+                    font.face = attrs.get("face").cloned().unwrap_or_default();
+                    font.size = get("size")?;
+                }
+                "common" => {
+                    font.line_height = get("lineHeight")?;
+                    font.base = get("base")?;
+                    font.scale_w = get("scaleW")?;
+                    font.scale_h = get("scaleH")?;
+                    if get("pages").unwrap_or(1) > 1 {
+                        return Err(anyhow!("Only single page BMFont files are supported"));
+                    }
+                }
+                "page" => font.page_file = attrs.get("file").cloned().unwrap_or_default(),
+                "char" => font.chars.push(BmChar {
+                    id: get("id")?,
+                    x: get("x")?,
+                    y: get("y")?,
+                    width: get("width")?,
+                    height: get("height")?,
+                    xoffset: get("xoffset")?,
+                    yoffset: get("yoffset")?,
+                    xadvance: get("xadvance")?,
+                }),
+                "kerning" => font.kernings.push(BmKerning {
+                    first: get("first")?,
+                    second: get("second")?,
+                    amount: get("amount")?,
+                }),
+                _ => {}
+            }
+        }
+        Ok(font)
+    }
+}
+
+/// Writes `<font>.fnt` and the atlas PNG it references into `out_dir`.
+pub fn export_bmfont<T: Parsable + BmFontConvertible>(
+    pack: &dyn HalleyPack,
+    font_type: i32,
+    image_type: i32,
+    font_name: &str,
+    out_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    let data = find_asset_data(pack, font_type, font_name)
+        .ok_or_else(|| anyhow!("Font {} not found", font_name))?;
+    let (_, font) = T::parse(&data).map_err(|err| anyhow!(err.to_string()))?;
+    let bmfont = font.to_bmfont();
+
+    let image_data = find_asset_data(pack, image_type, font.image_name())
+        .ok_or_else(|| anyhow!("Font image {} not found", font.image_name()))?;
+    let image = image::load_from_memory(&image_data).map_err(|err| {
+        anyhow!(
+            "Font image {} can't be converted to PNG: {}",
+            font.image_name(),
+            err
+        )
+    })?;
+
+    std::fs::create_dir_all(out_dir)?;
+    image.save_with_format(out_dir.join(&bmfont.page_file), image::ImageFormat::Png)?;
+
+    let file_name = font_name.rsplit('/').next().unwrap_or(font_name);
+    std::fs::write(out_dir.join(format!("{}.fnt", file_name)), bmfont.to_text())?;
+    Ok(())
+}
+
+/// Applies a BMFont file to an unpacked font document. If `image_path` is given, the atlas
+/// PNG referenced by the BMFont is copied over it.
+pub fn import_bmfont<T: Serialize + DeserializeOwned + BmFontConvertible>(
+    font_path: &Path,
+    bmfont_path: &Path,
+    image_path: Option<&Path>,
+) -> Result<(), anyhow::Error> {
+    let format = get_format_from_ext(get_serialization_ext_from_path(font_path));
+    let mut font: T = deserialize(&std::fs::read_to_string(font_path)?, format)?;
+    let bmfont = BmFont::parse(&std::fs::read_to_string(bmfont_path)?)?;
+
+    font.apply_bmfont(&bmfont);
+    std::fs::write(font_path, serialize(&font, format)?)?;
+
+    if let Some(image_path) = image_path {
+        let page_path = bmfont_path.with_file_name(&bmfont.page_file);
+        std::fs::copy(page_path, image_path)?;
+    }
+    Ok(())
+}
+
+fn parse_line(line: &str) -> (&str, IndexMap<String, String>) {
+    let line = line.trim();
+    let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut attrs = IndexMap::new();
+
+    while let Some((key, after)) = rest.trim_start().split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(' ').unwrap_or((after, "")),
+        };
+        attrs.insert(key.trim().to_string(), value.to_string());
+        rest = after;
+    }
+    (tag, attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bmfont_text_round_trip() {
+        let font = BmFont {
+            face: "Ubuntu Mono".to_string(),
+            size: 32,
+            line_height: 38,
+            base: 30,
+            scale_w: 512,
+            scale_h: 256,
+            page_file: "ubuntu.png".to_string(),
+            chars: vec![BmChar {
+                id: 65,
+                x: 10,
+                y: 20,
+                width: 14,
+                height: 22,
+                xoffset: 1,
+                yoffset: -2,
+                xadvance: 16,
+            }],
+            kernings: vec![BmKerning {
+                first: 65,
+                second: 86,
+                amount: -2,
+            }],
+        };
+        assert_eq!(font, BmFont::parse(&font.to_text()).unwrap());
+    }
+}
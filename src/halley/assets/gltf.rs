@@ -0,0 +1,1060 @@
+use crate::halley::versions::v2020::{
+    material::MaterialAttribute,
+    mesh::{
+        get_attribute_components, Mesh, MeshBounds, MeshManifest, MeshPart, MeshPartManifest,
+        PrimitiveType,
+    },
+    mesh_animation::{Bone, MeshAnimation, TrackInterpolation, TrackTarget},
+};
+use anyhow::anyhow;
+use base64::{engine::general_purpose, Engine as _};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+static GLB_MAGIC: &[u8] = b"glTF";
+static GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+static GLB_CHUNK_BIN: u32 = 0x004E4942;
+static DATA_URI_PREFIX: &str = "data:application/octet-stream;base64,";
+
+static COMPONENT_FLOAT: u32 = 5126;
+static COMPONENT_UNSIGNED_INT: u32 = 5125;
+static COMPONENT_UNSIGNED_SHORT: u32 = 5123;
+static TARGET_ARRAY_BUFFER: u32 = 34962;
+static TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Gltf {
+    asset: GltfAsset,
+    #[serde(default)]
+    scene: usize,
+    #[serde(default)]
+    scenes: Vec<GltfScene>,
+    #[serde(default)]
+    nodes: Vec<GltfNode>,
+    #[serde(default)]
+    meshes: Vec<GltfMesh>,
+    #[serde(default)]
+    materials: Vec<GltfMaterial>,
+    #[serde(default)]
+    accessors: Vec<GltfAccessor>,
+    #[serde(default)]
+    buffer_views: Vec<GltfBufferView>,
+    #[serde(default)]
+    buffers: Vec<GltfBuffer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skins: Vec<GltfSkin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    animations: Vec<GltfAnimation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct GltfAsset {
+    version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generator: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfScene {
+    nodes: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct GltfNode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    children: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mesh: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    skin: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    translation: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotation: Option<[f32; 4]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfMesh {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    primitives: Vec<GltfPrimitive>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfPrimitive {
+    attributes: IndexMap<String, usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    indices: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<usize>,
+    #[serde(default = "default_mode")]
+    mode: u32,
+}
+
+fn default_mode() -> u32 {
+    4
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfMaterial {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GltfAccessor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    accessor_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max: Option<Vec<f32>>,
+    #[serde(default, skip_serializing)]
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GltfBufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    byte_stride: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GltfBuffer {
+    byte_length: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GltfSkin {
+    joints: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inverse_bind_matrices: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfAnimation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    channels: Vec<GltfChannel>,
+    samplers: Vec<GltfSampler>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfChannel {
+    sampler: usize,
+    target: GltfChannelTarget,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfChannelTarget {
+    node: usize,
+    path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfSampler {
+    input: usize,
+    output: usize,
+    interpolation: String,
+}
+
+fn get_accessor_type(components: usize) -> &'static str {
+    match components {
+        1 => "SCALAR",
+        2 => "VEC2",
+        3 => "VEC3",
+        9 => "MAT3",
+        16 => "MAT4",
+        _ => "VEC4",
+    }
+}
+
+fn get_accessor_components(accessor_type: &str) -> usize {
+    match accessor_type {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" | "MAT2" => 4,
+        "MAT3" => 9,
+        "MAT4" => 16,
+        _ => 0,
+    }
+}
+
+fn get_primitive_mode(primitive_type: PrimitiveType) -> Result<u32, anyhow::Error> {
+    match primitive_type {
+        PrimitiveType::Point => Ok(0),
+        PrimitiveType::Line => Ok(1),
+        PrimitiveType::Triangle => Ok(4),
+        PrimitiveType::Undefined => Err(anyhow!("Mesh parts of undefined primitive type")),
+    }
+}
+
+/// glTF attribute name and component count for the float attributes glTF has a semantic for.
+/// Every other attribute is stored as an application specific `_NAME` attribute.
+fn get_standard_semantic(attribute: &MaterialAttribute) -> Option<(String, usize)> {
+    let (_, is_int) = get_attribute_components(&attribute.attribute_type);
+    if is_int {
+        return None;
+    }
+    match attribute.semantic.to_uppercase().as_str() {
+        "POSITION" => Some(("POSITION".to_string(), 3)),
+        "NORMAL" => Some(("NORMAL".to_string(), 3)),
+        "TANGENT" => Some(("TANGENT".to_string(), 4)),
+        "TEXCOORD" => Some((format!("TEXCOORD_{}", attribute.semantic_index), 2)),
+        "COLOR" | "COLOUR" => Some((format!("COLOR_{}", attribute.semantic_index), 4)),
+        _ => None,
+    }
+}
+
+fn get_custom_semantic(attribute: &MaterialAttribute) -> String {
+    format!("_{}", attribute.name.to_uppercase())
+}
+
+fn default_component(attribute: &MaterialAttribute, component: usize) -> f64 {
+    match (attribute.semantic.to_uppercase().as_str(), component) {
+        ("POSITION", 3) => 1.0,
+        _ => 0.0,
+    }
+}
+
+/// Changes the number of components per vertex, padding new components with `pad`.
+fn resize_components(
+    values: &[f64],
+    from: usize,
+    to: usize,
+    pad: impl Fn(usize) -> f64,
+) -> Vec<f64> {
+    values
+        .chunks(from)
+        .flat_map(|v| {
+            (0..to)
+                .map(|c| v.get(c).copied().unwrap_or_else(|| pad(c)))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct GltfBuilder {
+    gltf: Gltf,
+    buffer: Vec<u8>,
+}
+
+impl GltfBuilder {
+    fn add_accessor(
+        &mut self,
+        values: &[f64],
+        components: usize,
+        component_type: u32,
+        target: Option<u32>,
+    ) -> usize {
+        while !self.buffer.len().is_multiple_of(4) {
+            self.buffer.push(0);
+        }
+        let byte_offset = self.buffer.len();
+        for v in values {
+            match component_type {
+                t if t == COMPONENT_UNSIGNED_SHORT => self.buffer.extend((*v as u16).to_le_bytes()),
+                t if t == COMPONENT_UNSIGNED_INT => {
+                    self.buffer.extend((*v as i32 as u32).to_le_bytes())
+                }
+                _ => self.buffer.extend((*v as f32).to_le_bytes()),
+            }
+        }
+        self.gltf.buffer_views.push(GltfBufferView {
+            buffer: 0,
+            byte_offset,
+            byte_length: self.buffer.len() - byte_offset,
+            byte_stride: None,
+            target,
+        });
+
+        let (min, max) = match component_type == COMPONENT_FLOAT && components <= 4 {
+            true => {
+                let mut min = vec![f32::MAX; components];
+                let mut max = vec![f32::MIN; components];
+                for (n, v) in values.iter().enumerate() {
+                    min[n % components] = min[n % components].min(*v as f32);
+                    max[n % components] = max[n % components].max(*v as f32);
+                }
+                (Some(min), Some(max))
+            }
+            false => (None, None),
+        };
+
+        self.gltf.accessors.push(GltfAccessor {
+            buffer_view: Some(self.gltf.buffer_views.len() - 1),
+            byte_offset: 0,
+            component_type,
+            normalized: false,
+            count: values.len() / components.max(1),
+            accessor_type: get_accessor_type(components).to_string(),
+            min: min.filter(|_| !values.is_empty()),
+            max: max.filter(|_| !values.is_empty()),
+            sparse: None,
+        });
+        self.gltf.accessors.len() - 1
+    }
+
+    fn add_part(&mut self, part: &MeshPart) -> Result<GltfPrimitive, anyhow::Error> {
+        let mut attributes = IndexMap::new();
+        for attribute in &part.vertex_attributes {
+            let (components, is_int) = get_attribute_components(&attribute.attribute_type);
+            if components == 0 {
+                continue;
+            }
+            let values = part.read_attribute(attribute)?;
+            let standard = get_standard_semantic(attribute);
+
+            if let Some((name, standard_components)) = &standard {
+                let resized = resize_components(&values, components, *standard_components, |_| 0.0);
+                let accessor = self.add_accessor(
+                    &resized,
+                    *standard_components,
+                    COMPONENT_FLOAT,
+                    Some(TARGET_ARRAY_BUFFER),
+                );
+                attributes.insert(name.clone(), accessor);
+            }
+            if standard.is_none_or(|(_, c)| c < components) {
+                let component_type = match is_int {
+                    true => COMPONENT_UNSIGNED_INT,
+                    false => COMPONENT_FLOAT,
+                };
+                let accessor = self.add_accessor(
+                    &values,
+                    components,
+                    component_type,
+                    Some(TARGET_ARRAY_BUFFER),
+                );
+                attributes.insert(get_custom_semantic(attribute), accessor);
+            }
+        }
+
+        let indices: Vec<f64> = part.indices.iter().map(|i| *i as f64).collect();
+        let indices = self.add_accessor(
+            &indices,
+            1,
+            COMPONENT_UNSIGNED_SHORT,
+            Some(TARGET_ELEMENT_ARRAY_BUFFER),
+        );
+
+        self.gltf.materials.push(GltfMaterial {
+            name: Some(part.material_name.clone()),
+        });
+
+        Ok(GltfPrimitive {
+            attributes,
+            indices: Some(indices),
+            material: Some(self.gltf.materials.len() - 1),
+            mode: get_primitive_mode(part.primitive_type)?,
+        })
+    }
+
+    /// Adds the mesh as a single glTF mesh, returning the node that holds it.
+    fn add_mesh(&mut self, mesh: &Mesh) -> Result<usize, anyhow::Error> {
+        let primitives = mesh
+            .parts
+            .iter()
+            .map(|p| self.add_part(p))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        self.gltf.meshes.push(GltfMesh {
+            name: None,
+            primitives,
+        });
+        self.gltf.nodes.push(GltfNode {
+            mesh: Some(self.gltf.meshes.len() - 1),
+            ..Default::default()
+        });
+        Ok(self.gltf.nodes.len() - 1)
+    }
+
+    fn finish(self, scene_nodes: Vec<usize>) -> Result<Vec<u8>, anyhow::Error> {
+        let mut gltf = self.gltf;
+        gltf.asset = GltfAsset {
+            version: "2.0".to_string(),
+            generator: Some("halleypack".to_string()),
+        };
+        gltf.scenes.push(GltfScene { nodes: scene_nodes });
+        gltf.buffers.push(GltfBuffer {
+            byte_length: self.buffer.len(),
+            uri: Some(format!(
+                "{}{}",
+                DATA_URI_PREFIX,
+                general_purpose::STANDARD.encode(&self.buffer)
+            )),
+        });
+
+        Ok(serde_json::to_vec_pretty(&gltf)?)
+    }
+}
+
+/// Converts a mesh to a glTF 2.0 file with an embedded buffer. Each part becomes a primitive
+/// of a single glTF mesh, with a material named after the part's material.
+pub fn mesh_to_gltf(mesh: &Mesh) -> Result<Vec<u8>, anyhow::Error> {
+    let mut builder = GltfBuilder::default();
+    let node = builder.add_mesh(mesh)?;
+    builder.finish(vec![node])
+}
+
+type Matrix = [f32; 16];
+
+/// Column major transform of a bone's bind pose relative to its parent.
+fn compose_transform(bone: &Bone) -> Matrix {
+    let (x, y, z, w) = bone.rotation;
+    let rotation = [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + z * w),
+            2.0 * (x * z - y * w),
+        ],
+        [
+            2.0 * (x * y - z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + x * w),
+        ],
+        [
+            2.0 * (x * z + y * w),
+            2.0 * (y * z - x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ];
+    let scale = [bone.scale.0, bone.scale.1, bone.scale.2];
+    let mut m = [0.0; 16];
+    for (c, column) in rotation.iter().enumerate() {
+        for (r, v) in column.iter().enumerate() {
+            m[c * 4 + r] = v * scale[c];
+        }
+    }
+    m[12] = bone.translation.0;
+    m[13] = bone.translation.1;
+    m[14] = bone.translation.2;
+    m[15] = 1.0;
+    m
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [0.0; 16];
+    for c in 0..4 {
+        for r in 0..4 {
+            m[c * 4 + r] = (0..4).map(|k| a[k * 4 + r] * b[c * 4 + k]).sum();
+        }
+    }
+    m
+}
+
+fn invert_affine(m: &Matrix) -> Matrix {
+    let a = |r: usize, c: usize| m[c * 4 + r];
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        a(r0, c0) * a(r1, c1) - a(r0, c1) * a(r1, c0)
+    };
+    let det: f32 = (0..3).map(|c| a(0, c) * cofactor(0, c)).sum();
+    let det = if det == 0.0 { 1.0 } else { det };
+
+    let mut inv = [0.0; 16];
+    for r in 0..3 {
+        for c in 0..3 {
+            inv[c * 4 + r] = cofactor(c, r) / det;
+        }
+    }
+    for r in 0..3 {
+        inv[12 + r] = -(0..3).map(|k| inv[k * 4 + r] * m[12 + k]).sum::<f32>();
+    }
+    inv[15] = 1.0;
+    inv
+}
+
+fn get_global_transform(animation: &MeshAnimation, bone: usize, depth: usize) -> Matrix {
+    let local = compose_transform(&animation.bones[bone]);
+    match usize::try_from(animation.bones[bone].parent) {
+        Ok(parent) if parent < animation.bones.len() && depth < animation.bones.len() => {
+            multiply(&get_global_transform(animation, parent, depth + 1), &local)
+        }
+        _ => local,
+    }
+}
+
+fn find_semantic<'a>(part: &'a MeshPart, semantics: &[&str]) -> Option<&'a MaterialAttribute> {
+    part.vertex_attributes
+        .iter()
+        .find(|a| semantics.iter().any(|s| a.semantic.eq_ignore_ascii_case(s)))
+}
+
+impl GltfBuilder {
+    /// Adds `JOINTS_0` and `WEIGHTS_0` to the primitives of the last mesh, if every part has
+    /// bone indices and weights.
+    fn add_skin_attributes(&mut self, mesh: &Mesh) -> Result<bool, anyhow::Error> {
+        let mut skin_attributes = vec![];
+        for part in &mesh.parts {
+            let joints = find_semantic(part, &["BLENDINDICES", "JOINTS"]);
+            let weights = find_semantic(part, &["BLENDWEIGHT", "BLENDWEIGHTS", "WEIGHTS"]);
+            match (joints, weights) {
+                (Some(joints), Some(weights)) => skin_attributes.push((part, joints, weights)),
+                _ => return Ok(false),
+            }
+        }
+
+        for (n, (part, joints, weights)) in skin_attributes.into_iter().enumerate() {
+            let mut accessors = vec![];
+            for (attribute, name, component_type) in [
+                (joints, "JOINTS_0", COMPONENT_UNSIGNED_SHORT),
+                (weights, "WEIGHTS_0", COMPONENT_FLOAT),
+            ] {
+                let (components, _) = get_attribute_components(&attribute.attribute_type);
+                let values =
+                    resize_components(&part.read_attribute(attribute)?, components, 4, |_| 0.0);
+                let accessor =
+                    self.add_accessor(&values, 4, component_type, Some(TARGET_ARRAY_BUFFER));
+                accessors.push((name, accessor));
+            }
+            let primitive = &mut self.gltf.meshes.last_mut().unwrap().primitives[n];
+            primitive
+                .attributes
+                .extend(accessors.into_iter().map(|(k, v)| (k.to_string(), v)));
+        }
+        Ok(true)
+    }
+
+    fn add_animation(
+        &mut self,
+        animation: &MeshAnimation,
+        first_bone: usize,
+        mesh_node: usize,
+    ) -> Result<(), anyhow::Error> {
+        let mut channels = vec![];
+        let mut samplers = vec![];
+        for track in &animation.tracks {
+            let (path, components) = match track.target {
+                TrackTarget::Translation => ("translation", 3),
+                TrackTarget::Rotation => ("rotation", 4),
+                TrackTarget::Scale => ("scale", 3),
+                TrackTarget::Weights => ("weights", 1),
+            };
+            let node = match track.target {
+                TrackTarget::Weights => mesh_node,
+                _ => usize::try_from(track.bone)
+                    .ok()
+                    .filter(|b| *b < animation.bones.len())
+                    .map(|b| first_bone + b)
+                    .ok_or_else(|| anyhow!("Track of unknown bone {}", track.bone))?,
+            };
+            let expected = match track.interpolation {
+                TrackInterpolation::CubicSpline => components * 3,
+                _ => components,
+            };
+            if let Some(k) = track
+                .keyframes
+                .iter()
+                .find(|k| k.value.len() % expected != 0)
+            {
+                return Err(anyhow!(
+                    "Keyframe at {} of bone {} has {} values, a multiple of {} expected",
+                    k.time,
+                    animation.bone_name(track.bone),
+                    k.value.len(),
+                    expected
+                ));
+            }
+
+            let times: Vec<f64> = track.keyframes.iter().map(|k| k.time as f64).collect();
+            let values: Vec<f64> = track
+                .keyframes
+                .iter()
+                .flat_map(|k| k.value.iter().map(|v| *v as f64))
+                .collect();
+            let input = self.add_accessor(&times, 1, COMPONENT_FLOAT, None);
+            let output = self.add_accessor(&values, components, COMPONENT_FLOAT, None);
+
+            samplers.push(GltfSampler {
+                input,
+                output,
+                interpolation: match track.interpolation {
+                    TrackInterpolation::Step => "STEP",
+                    TrackInterpolation::Linear => "LINEAR",
+                    TrackInterpolation::CubicSpline => "CUBICSPLINE",
+                }
+                .to_string(),
+            });
+            channels.push(GltfChannel {
+                sampler: samplers.len() - 1,
+                target: GltfChannelTarget {
+                    node,
+                    path: path.to_string(),
+                },
+            });
+        }
+
+        self.gltf.animations.push(GltfAnimation {
+            name: Some(animation.name.clone()),
+            channels,
+            samplers,
+        });
+        Ok(())
+    }
+}
+
+/// Converts an animation and the mesh it plays on to a glTF 2.0 file. Bones become nodes, and
+/// the mesh is skinned to them when its vertices have bone indices and weights.
+pub fn mesh_animation_to_gltf(
+    animation: &MeshAnimation,
+    mesh: &Mesh,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut builder = GltfBuilder::default();
+    let mesh_node = builder.add_mesh(mesh)?;
+
+    let first_bone = builder.gltf.nodes.len();
+    let mut scene_nodes = vec![mesh_node];
+    for bone in &animation.bones {
+        builder.gltf.nodes.push(GltfNode {
+            name: Some(bone.name.clone()),
+            translation: Some([bone.translation.0, bone.translation.1, bone.translation.2]),
+            rotation: Some([
+                bone.rotation.0,
+                bone.rotation.1,
+                bone.rotation.2,
+                bone.rotation.3,
+            ]),
+            scale: Some([bone.scale.0, bone.scale.1, bone.scale.2]),
+            ..Default::default()
+        });
+    }
+    for (n, bone) in animation.bones.iter().enumerate() {
+        match usize::try_from(bone.parent) {
+            Ok(parent) if parent < animation.bones.len() && parent != n => builder.gltf.nodes
+                [first_bone + parent]
+                .children
+                .push(first_bone + n),
+            _ => scene_nodes.push(first_bone + n),
+        }
+    }
+
+    if !animation.bones.is_empty() && builder.add_skin_attributes(mesh)? {
+        let matrices: Vec<f64> = (0..animation.bones.len())
+            .flat_map(|b| invert_affine(&get_global_transform(animation, b, 0)))
+            .map(|v| v as f64)
+            .collect();
+        let inverse_bind_matrices = builder.add_accessor(&matrices, 16, COMPONENT_FLOAT, None);
+        builder.gltf.skins.push(GltfSkin {
+            joints: (first_bone..first_bone + animation.bones.len()).collect(),
+            inverse_bind_matrices: Some(inverse_bind_matrices),
+        });
+        builder.gltf.nodes[mesh_node].skin = Some(0);
+    }
+
+    builder.add_animation(animation, first_bone, mesh_node)?;
+    builder.finish(scene_nodes)
+}
+
+struct GltfDocument {
+    gltf: Gltf,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl GltfDocument {
+    fn load(
+        data: &[u8],
+        read_file: &impl Fn(&str) -> Result<Vec<u8>, anyhow::Error>,
+    ) -> Result<Self, anyhow::Error> {
+        let (json, mut bin) = match data.starts_with(GLB_MAGIC) {
+            true => parse_glb(data)?,
+            false => (data, None),
+        };
+        let gltf: Gltf = serde_json::from_slice(json)?;
+
+        let buffers = gltf
+            .buffers
+            .iter()
+            .map(|b| match &b.uri {
+                Some(uri) if uri.starts_with("data:") => {
+                    let (_, encoded) = uri
+                        .split_once(";base64,")
+                        .ok_or_else(|| anyhow!("Unsupported buffer uri"))?;
+                    Ok(general_purpose::STANDARD.decode(encoded)?)
+                }
+                Some(uri) => read_file(uri),
+                None => bin
+                    .take()
+                    .map(|b| b.to_vec())
+                    .ok_or_else(|| anyhow!("Buffer without data")),
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        Ok(GltfDocument { gltf, buffers })
+    }
+
+    /// Reads every component of an accessor, normalised integers mapped to `0..1`.
+    fn read_accessor(&self, index: usize) -> Result<(Vec<f64>, usize), anyhow::Error> {
+        let accessor = self
+            .gltf
+            .accessors
+            .get(index)
+            .ok_or_else(|| anyhow!("Missing accessor {}", index))?;
+        if accessor.sparse.is_some() {
+            return Err(anyhow!("Sparse accessors are not supported"));
+        }
+        let components = get_accessor_components(&accessor.accessor_type);
+        let (size, max): (usize, f64) = match accessor.component_type {
+            5120 => (1, i8::MAX as f64),
+            5121 => (1, u8::MAX as f64),
+            5122 => (2, i16::MAX as f64),
+            5123 => (2, u16::MAX as f64),
+            5124..=5126 => (4, u32::MAX as f64),
+            t => return Err(anyhow!("Unsupported component type {}", t)),
+        };
+
+        let view = accessor
+            .buffer_view
+            .and_then(|v| self.gltf.buffer_views.get(v))
+            .ok_or_else(|| anyhow!("Accessor {} has no buffer view", index))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| anyhow!("Missing buffer {}", view.buffer))?;
+        let stride = view.byte_stride.unwrap_or(size * components);
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        for n in 0..accessor.count {
+            for c in 0..components {
+                let pos = view.byte_offset + accessor.byte_offset + n * stride + c * size;
+                let b = buffer
+                    .get(pos..pos + size)
+                    .ok_or_else(|| anyhow!("Accessor {} is outside its buffer", index))?;
+                let value = match accessor.component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5124 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                values.push(match accessor.normalized {
+                    true => (value / max).max(-1.0),
+                    false => value,
+                });
+            }
+        }
+        Ok((values, components))
+    }
+}
+
+fn parse_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), anyhow::Error> {
+    let read_u32 = |pos: usize| -> Result<u32, anyhow::Error> {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| anyhow!("Truncated glb file"))
+    };
+    let mut json = None;
+    let mut bin = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let length = read_u32(pos)? as usize;
+        let chunk = data
+            .get(pos + 8..pos + 8 + length)
+            .ok_or_else(|| anyhow!("Truncated glb file"))?;
+        match read_u32(pos + 4)? {
+            t if t == GLB_CHUNK_JSON => json = Some(chunk),
+            t if t == GLB_CHUNK_BIN => bin = Some(chunk),
+            _ => {}
+        }
+        pos += 8 + length;
+    }
+    Ok((json.ok_or_else(|| anyhow!("glb file without json"))?, bin))
+}
+
+fn read_part(
+    document: &GltfDocument,
+    primitive: &GltfPrimitive,
+    part: MeshPartManifest,
+) -> Result<MeshPart, anyhow::Error> {
+    let mode = get_primitive_mode(part.primitive_type)?;
+    if primitive.mode != mode {
+        return Err(anyhow!(
+            "Part with material {} must use primitive mode {}, found {}",
+            part.material_name,
+            mode,
+            primitive.mode
+        ));
+    }
+
+    let mut num_vertices = None;
+    let mut attributes = vec![];
+    for attribute in &part.vertex_attributes {
+        let (components, _) = get_attribute_components(&attribute.attribute_type);
+        if components == 0 {
+            continue;
+        }
+        let size = components as i32 * 4;
+        if attribute.offset < 0 || attribute.offset + size > part.vertex_size {
+            return Err(anyhow!(
+                "Attribute {} doesn't fit in a vertex of {} bytes",
+                attribute.name,
+                part.vertex_size
+            ));
+        }
+
+        let standard = get_standard_semantic(attribute)
+            .and_then(|(name, _)| primitive.attributes.get(&name).map(|a| (name, *a)));
+        let custom = primitive
+            .attributes
+            .get(&get_custom_semantic(attribute))
+            .map(|a| document.read_accessor(*a))
+            .transpose()?;
+
+        let values = match standard {
+            Some((_, accessor)) => {
+                let (values, found) = document.read_accessor(accessor)?;
+                let mut values = resize_components(&values, found, components, |c| {
+                    default_component(attribute, c)
+                });
+                if let Some((custom, custom_components)) = custom {
+                    if custom_components == components && custom.len() == values.len() {
+                        for (n, v) in values.iter_mut().enumerate() {
+                            if n % components >= found {
+                                *v = custom[n];
+                            }
+                        }
+                    }
+                }
+                values
+            }
+            None => match custom {
+                Some((values, found)) if found == components => values,
+                Some((_, found)) => {
+                    return Err(anyhow!(
+                        "Attribute {} has {} components, {} expected",
+                        get_custom_semantic(attribute),
+                        found,
+                        components
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "Part with material {} is missing attribute {}",
+                        part.material_name,
+                        attribute.name
+                    ))
+                }
+            },
+        };
+
+        let count = values.len() / components;
+        if *num_vertices.get_or_insert(count) != count {
+            return Err(anyhow!(
+                "Attributes of part with material {} have different vertex counts",
+                part.material_name
+            ));
+        }
+        attributes.push((attribute, values));
+    }
+    let num_vertices = num_vertices.unwrap_or_default();
+
+    let indices = match primitive.indices {
+        Some(accessor) => document
+            .read_accessor(accessor)?
+            .0
+            .into_iter()
+            .map(|i| i as usize)
+            .collect(),
+        None => (0..num_vertices).collect::<Vec<_>>(),
+    };
+    let indices = indices
+        .into_iter()
+        .map(|i| match i < num_vertices {
+            true => u16::try_from(i).map_err(|_| {
+                anyhow!(
+                    "Part with material {} has too many vertices for 16 bit indices",
+                    part.material_name
+                )
+            }),
+            false => Err(anyhow!("Index {} out of range", i)),
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    let mut mesh_part = MeshPart {
+        vertex_data: vec![0; num_vertices * part.vertex_size as usize],
+        material_name: part.material_name,
+        textures: part.textures,
+        primitive_type: part.primitive_type,
+        vertex_size: part.vertex_size,
+        vertex_attributes: vec![],
+        num_vertices: num_vertices as u32,
+        indices,
+    };
+    for (attribute, values) in &attributes {
+        mesh_part.write_attribute(attribute, values);
+    }
+    mesh_part.vertex_attributes = part.vertex_attributes;
+    Ok(mesh_part)
+}
+
+fn grow_bounds(bounds: &mut MeshBounds, part: &MeshPart) -> Result<(), anyhow::Error> {
+    let position = part
+        .vertex_attributes
+        .iter()
+        .find(|a| a.semantic.eq_ignore_ascii_case("POSITION"));
+    if let Some(position) = position {
+        let (components, _) = get_attribute_components(&position.attribute_type);
+        for p in part.read_attribute(position)?.chunks(components) {
+            let get = |c: usize| p.get(c).copied().unwrap_or_default() as f32;
+            bounds.min = (
+                bounds.min.0.min(get(0)),
+                bounds.min.1.min(get(1)),
+                bounds.min.2.min(get(2)),
+            );
+            bounds.max = (
+                bounds.max.0.max(get(0)),
+                bounds.max.1.max(get(1)),
+                bounds.max.2.max(get(2)),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds a mesh from its manifest and glTF file, checking the geometry against the vertex
+/// layout of each part. Bytes of a vertex that aren't covered by an attribute are zeroed, and
+/// the bounds grow to contain any vertex moved outside of them.
+pub fn mesh_from_gltf(
+    manifest: MeshManifest,
+    read_file: impl Fn(&str) -> Result<Vec<u8>, anyhow::Error>,
+) -> Result<Mesh, anyhow::Error> {
+    let document = GltfDocument::load(&read_file(&manifest.file)?, &read_file)?;
+    let primitives: Vec<&GltfPrimitive> = document
+        .gltf
+        .meshes
+        .iter()
+        .flat_map(|m| m.primitives.iter())
+        .collect();
+    if primitives.len() != manifest.parts.len() {
+        return Err(anyhow!(
+            "{} has {} primitives, {} parts expected",
+            manifest.file,
+            primitives.len(),
+            manifest.parts.len()
+        ));
+    }
+
+    let mut bounds = manifest.bounds;
+    let parts = manifest
+        .parts
+        .into_iter()
+        .zip(primitives)
+        .map(|(part, primitive)| {
+            let part = read_part(&document, primitive, part)?;
+            grow_bounds(&mut bounds, &part)?;
+            Ok(part)
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    Ok(Mesh { parts, bounds })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::{
+        common::hpk::write_to_vec, v2020::material::ShaderParameterType,
+    };
+
+    fn attribute(
+        name: &str,
+        attribute_type: ShaderParameterType,
+        offset: i32,
+        semantic: &str,
+    ) -> MaterialAttribute {
+        MaterialAttribute {
+            name: name.to_string(),
+            attribute_type,
+            location: 0,
+            offset,
+            semantic: semantic.to_string(),
+            semantic_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_mesh_gltf_round_trip() {
+        let mut part = MeshPart {
+            material_name: "Halley/StandardMesh".to_string(),
+            textures: vec!["rock.png".to_string()],
+            primitive_type: PrimitiveType::Triangle,
+            vertex_size: 28,
+            vertex_attributes: vec![
+                attribute("position", ShaderParameterType::Float4, 0, "POSITION"),
+                attribute("texCoord0", ShaderParameterType::Float2, 16, "TEXCOORD"),
+                attribute("boneIndex", ShaderParameterType::Int, 24, "BLENDINDICES"),
+            ],
+            num_vertices: 3,
+            vertex_data: vec![0; 3 * 28],
+            indices: vec![0, 1, 2],
+        };
+        let positions = [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.5, 2.0, 0.0, 1.0, -1.0, 1.0];
+        let texcoords = [0.0, 0.0, 1.0, 0.0, 0.5, 1.0];
+        let bones = [0.0, -1.0, 7.0];
+        let attributes = std::mem::take(&mut part.vertex_attributes);
+        for (attribute, values) in attributes.iter().zip([&positions[..], &texcoords, &bones]) {
+            part.write_attribute(attribute, values);
+        }
+        part.vertex_attributes = attributes;
+        let mesh = Mesh {
+            parts: vec![part],
+            bounds: MeshBounds {
+                min: (0.0, 0.0, -1.0),
+                max: (1.0, 2.0, 1.0),
+            },
+        };
+
+        let original = write_to_vec(&mesh).unwrap();
+        let gltf = mesh_to_gltf(&mesh).unwrap();
+        let manifest = MeshManifest::from(mesh);
+        let mesh = mesh_from_gltf(manifest, |_| Ok(gltf.clone())).unwrap();
+        assert_eq!(original, write_to_vec(&mesh).unwrap());
+    }
+
+    #[test]
+    fn test_invert_bind_pose() {
+        let bone = Bone {
+            name: "arm".to_string(),
+            parent: -1,
+            translation: (1.0, -2.0, 3.0),
+            rotation: (0.0, 0.38268343, 0.0, 0.9238795),
+            scale: (2.0, 2.0, 0.5),
+        };
+        let m = compose_transform(&bone);
+        let identity = multiply(&m, &invert_affine(&m));
+        for (n, v) in identity.iter().enumerate() {
+            let expected = if n % 5 == 0 { 1.0 } else { 0.0 };
+            assert!((v - expected).abs() < 1e-5, "{:?}", identity);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt::Write;
+
+/// Node of a graph visualisation. Nodes with pins are drawn with a port per input and output
+/// pin, and edges attach to those ports when they name a pin.
+pub struct GraphNode {
+    pub id: String,
+    pub label: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+pub struct GraphEdge {
+    pub from: String,
+    pub from_pin: Option<usize>,
+    pub to: String,
+    pub to_pin: Option<usize>,
+    pub label: Option<String>,
+}
+
+pub trait GraphConvertible {
+    fn to_graph(&self) -> (Vec<GraphNode>, Vec<GraphEdge>);
+}
+
+fn escape_dot(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c {
+            '"' | '{' | '}' | '|' | '<' | '>' | '\\' => vec!['\\', c],
+            '\n' => vec!['\\', 'n'],
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn escape_mermaid(s: &str) -> String {
+    s.replace('"', "#quot;").replace('\n', "<br/>")
+}
+
+fn get_pins(prefix: &str, pins: &[String]) -> String {
+    pins.iter()
+        .enumerate()
+        .map(|(n, p)| format!("<{}{}> {}", prefix, n, escape_dot(p)))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn get_port(prefix: &str, pin: Option<usize>) -> String {
+    pin.map(|p| format!(":{}{}", prefix, p)).unwrap_or_default()
+}
+
+pub fn to_dot(name: &str, nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph \"{}\" {{", escape_dot(name)).unwrap();
+    writeln!(out, "    rankdir=LR;").unwrap();
+    writeln!(out, "    node [shape=record];").unwrap();
+
+    for node in nodes {
+        let mut fields = vec![];
+        if !node.inputs.is_empty() {
+            fields.push(format!("{{{}}}", get_pins("i", &node.inputs)));
+        }
+        fields.push(escape_dot(&node.label));
+        if !node.outputs.is_empty() {
+            fields.push(format!("{{{}}}", get_pins("o", &node.outputs)));
+        }
+        writeln!(
+            out,
+            "    \"{}\" [label=\"{}\"];",
+            escape_dot(&node.id),
+            fields.join("|")
+        )
+        .unwrap();
+    }
+
+    for edge in edges {
+        let label = edge
+            .label
+            .as_ref()
+            .map(|l| format!(" [label=\"{}\"]", escape_dot(l)))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "    \"{}\"{} -> \"{}\"{}{};",
+            escape_dot(&edge.from),
+            get_port("o", edge.from_pin),
+            escape_dot(&edge.to),
+            get_port("i", edge.to_pin),
+            label
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Writes a Mermaid flowchart. Mermaid has no ports, so pin names are listed in the node
+/// label and pin numbers are added to the edge labels.
+pub fn to_mermaid(nodes: &[GraphNode], edges: &[GraphEdge]) -> String {
+    let mut out = String::new();
+    writeln!(out, "flowchart LR").unwrap();
+
+    let node_id = |id: &str| {
+        nodes.iter().position(|n| n.id == id).map_or_else(
+            || {
+                format!(
+                    "missing_{}",
+                    id.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+                )
+            },
+            |n| format!("n{}", n),
+        )
+    };
+
+    for (n, node) in nodes.iter().enumerate() {
+        let mut label = node.label.clone();
+        if !node.inputs.is_empty() {
+            label.push_str(&format!("\nin: {}", node.inputs.join(", ")));
+        }
+        if !node.outputs.is_empty() {
+            label.push_str(&format!("\nout: {}", node.outputs.join(", ")));
+        }
+        writeln!(out, "    n{}[\"{}\"]", n, escape_mermaid(&label)).unwrap();
+    }
+
+    for edge in edges {
+        let pins = match (edge.from_pin, edge.to_pin) {
+            (None, None) => None,
+            (from, to) => Some(format!(
+                "{} → {}",
+                from.map_or("-".to_string(), |p| p.to_string()),
+                to.map_or("-".to_string(), |p| p.to_string())
+            )),
+        };
+        let label = [pins, edge.label.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let arrow = if label.is_empty() {
+            "-->".to_string()
+        } else {
+            format!("-->|\"{}\"|", escape_mermaid(&label))
+        };
+        writeln!(
+            out,
+            "    {} {} {}",
+            node_id(&edge.from),
+            arrow,
+            node_id(&edge.to)
+        )
+        .unwrap();
+    }
+    out
+}
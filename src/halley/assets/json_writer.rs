@@ -0,0 +1,175 @@
+use super::serialization::{as_f32, f32_text};
+use serde::Serialize;
+use serde_json::ser::{Formatter, PrettyFormatter};
+use serde_yaml::Value;
+use std::{io, vec};
+
+/// Pretty-printed JSON5, written so that values read back exactly: floats always carry a decimal
+/// point or an exponent, so whole ones aren't taken for integers, and non-finite floats use the
+/// JSON5 literals `NaN`, `Infinity` and `-Infinity`.
+pub fn to_string_pretty<T: ?Sized + Serialize>(value: &T) -> Result<String, anyhow::Error> {
+    // serde_json writes non-finite floats as nulls, so the text of every null is taken, in
+    // order, from a serde_yaml value of the same data, which keeps them.
+    let mut nulls = vec![];
+    collect_nulls(&serde_yaml::to_value(value)?, &mut nulls);
+    let formatter = Json5Formatter {
+        pretty: PrettyFormatter::new(),
+        nulls: nulls.into_iter(),
+    };
+    let mut output = vec![];
+    value.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut output,
+        formatter,
+    ))?;
+    Ok(String::from_utf8(output)?)
+}
+
+fn collect_nulls(value: &Value, nulls: &mut Vec<&'static str>) {
+    match value {
+        Value::Null => nulls.push("null"),
+        Value::Number(n) => match n.as_f64() {
+            Some(v) if v.is_nan() => nulls.push("NaN"),
+            Some(v) if v == f64::INFINITY => nulls.push("Infinity"),
+            Some(v) if v == f64::NEG_INFINITY => nulls.push("-Infinity"),
+            _ => {}
+        },
+        Value::Sequence(items) => items.iter().for_each(|v| collect_nulls(v, nulls)),
+        Value::Mapping(mapping) => mapping.values().for_each(|v| collect_nulls(v, nulls)),
+        Value::Tagged(tagged) => collect_nulls(&tagged.value, nulls),
+        Value::Bool(_) | Value::String(_) => {}
+    }
+}
+
+/// serde_json's pretty layout, with each float written with the shortest text that reads back
+/// as it.
+struct Json5Formatter {
+    pretty: PrettyFormatter<'static>,
+    nulls: vec::IntoIter<&'static str>,
+}
+
+impl Formatter for Json5Formatter {
+    fn write_null<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.nulls.next().unwrap_or("null").as_bytes())
+    }
+
+    fn write_f32<W: ?Sized + io::Write>(&mut self, writer: &mut W, value: f32) -> io::Result<()> {
+        writer.write_all(f32_text(value).as_bytes())
+    }
+
+    fn write_f64<W: ?Sized + io::Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        let text = match as_f32(value) {
+            Some(value) => f32_text(value),
+            None => format!("{:?}", value),
+        };
+        writer.write_all(text.as_bytes())
+    }
+
+    fn begin_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.pretty.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.pretty.begin_object_key(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.pretty.end_object_value(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::indexmap;
+
+    #[test]
+    fn test_json_writer_output() {
+        let value = indexmap! {
+            "whole" => vec![1.0_f32, -0.0, 1e-40],
+            "special" => vec![f32::NAN, f32::INFINITY, f32::NEG_INFINITY],
+            "empty" => vec![],
+        };
+        let json = to_string_pretty(&value).unwrap();
+        assert_eq!(
+            json,
+            "{\n  \"whole\": [\n    1.0,\n    -0.0,\n    1e-40\n  ],\n  \"special\": [\n    NaN,\n    \
+             Infinity,\n    -Infinity\n  ],\n  \"empty\": []\n}"
+        );
+        let back: indexmap::IndexMap<String, Vec<f32>> = json5::from_str(&json).unwrap();
+        assert_eq!(back["whole"][0].to_bits(), 1.0_f32.to_bits());
+        assert_eq!(back["whole"][1].to_bits(), (-0.0_f32).to_bits());
+        assert!(back["special"][0].is_nan());
+    }
+
+    #[test]
+    fn test_json_writer_escapes_strings() {
+        let text = "quote \" backslash \\ newline \n tab \t bell \u{7} accent é";
+        let json = to_string_pretty(&vec![text]).unwrap();
+        assert_eq!(
+            json,
+            "[\n  \"quote \\\" backslash \\\\ newline \\n tab \\t bell \\u0007 accent é\"\n]"
+        );
+        let back: Vec<String> = json5::from_str(&json).unwrap();
+        assert_eq!(back, vec![text]);
+    }
+
+    #[test]
+    fn test_json_writer_keeps_nulls_apart_from_non_finite_floats() {
+        let value = vec![
+            None,
+            Some(f32::NAN),
+            None,
+            Some(f32::NEG_INFINITY),
+            Some(0.5),
+        ];
+        let json = to_string_pretty(&value).unwrap();
+        assert_eq!(json, "[\n  null,\n  NaN,\n  null,\n  -Infinity,\n  0.5\n]");
+    }
+
+    #[test]
+    fn test_json_writer_deep_nesting() {
+        let mut value = serde_json::json!([1, { "leaf": "x" }]);
+        for depth in 0..64 {
+            value = match depth % 2 {
+                0 => serde_json::json!([value]),
+                _ => serde_json::json!({ "child": value }),
+            };
+        }
+        let json = to_string_pretty(&value).unwrap();
+        assert!(json.contains(&format!("\n{}\"leaf\": \"x\"", "  ".repeat(66))));
+        let back: serde_json::Value = json5::from_str(&json).unwrap();
+        assert_eq!(back, value);
+    }
+}
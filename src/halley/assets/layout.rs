@@ -0,0 +1,206 @@
+use super::serialization::{deserialize, serialize, Format};
+use crate::halley::versions::common::hpk::HpkSection;
+use path_slash::PathExt as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub static LAYOUT_FILE: &str = "pack.layout.toml";
+
+/// Sections and assets of an unpacked pack in their original order, written on every unpack.
+/// Packing a folder with a layout rebuilds the sections and assets in that order.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PackLayout {
+    pub sections: Vec<LayoutSection>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayoutSection {
+    pub asset_type: i32,
+    /// Index stored with the section, for versions that store one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_index: Option<i32>,
+    /// Section folder, relative to the pack folder.
+    pub folder: String,
+    /// Asset files in their original order, relative to the section folder.
+    pub assets: Vec<String>,
+}
+
+impl PackLayout {
+    pub fn load(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let filename = path.join(LAYOUT_FILE);
+        if !filename.exists() {
+            return Ok(None);
+        }
+        let layout = deserialize(&fs::read_to_string(filename)?, Some(Format::Toml5))?;
+        Ok(Some(layout))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(
+            path.join(LAYOUT_FILE),
+            serialize(self, Some(Format::Toml5))?,
+        )?;
+        Ok(())
+    }
+}
+
+impl LayoutSection {
+    /// Orders the asset files found in `section_dir` as listed, followed by the files that are
+    /// not listed, sorted by path. Listed files are matched by asset name, so that an asset keeps
+    /// its place when its file ending changes. Returns the ordered files, each with the index of
+    /// the entry it matched in `assets`, and the listed files that match none of them.
+    pub fn order_files(
+        &self,
+        section: &dyn HpkSection,
+        section_dir: &Path,
+        mut files: Vec<PathBuf>,
+    ) -> (Vec<(PathBuf, Option<usize>)>, Vec<String>) {
+        let asset_name =
+            |file: &Path| section.get_asset_name(file, section.get_data_ext_from_path(file));
+        let relative = |file: &PathBuf| file.strip_prefix(section_dir).unwrap().to_path_buf();
+        files.sort_by_key(|file| relative(file).to_slash_lossy().into_owned());
+
+        let mut by_file = HashMap::new();
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (n, file) in files.iter().enumerate() {
+            let relative = relative(file);
+            by_file.insert(relative.to_slash_lossy().into_owned(), n);
+            by_name.entry(asset_name(&relative)).or_default().push(n);
+        }
+
+        let mut taken = vec![false; files.len()];
+        let mut order = vec![];
+        let mut missing = vec![];
+        for (k, listed) in self.assets.iter().enumerate() {
+            // The exact file first, in case other files have the same asset name.
+            let found = by_file
+                .get(listed)
+                .copied()
+                .filter(|n| !taken[*n])
+                .or_else(|| {
+                    by_name
+                        .get(&asset_name(Path::new(listed)))?
+                        .iter()
+                        .copied()
+                        .find(|n| !taken[*n])
+                });
+            match found {
+                Some(n) => {
+                    taken[n] = true;
+                    order.push((n, Some(k)));
+                }
+                None => missing.push(listed.clone()),
+            }
+        }
+        order.extend((0..files.len()).filter(|n| !taken[*n]).map(|n| (n, None)));
+
+        let mut files: Vec<Option<PathBuf>> = files.into_iter().map(Some).collect();
+        let ordered = order
+            .into_iter()
+            .filter_map(|(n, k)| Some((files[n].take()?, k)))
+            .collect();
+        (ordered, missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::v2023::hpk::{AssetTypeV2023, HpkSectionV2023};
+
+    fn order(
+        asset_type: AssetTypeV2023,
+        listed: &[&str],
+        found: &[&str],
+    ) -> (Vec<(String, Option<usize>)>, Vec<String>) {
+        let section = HpkSectionV2023::new(asset_type as i32).unwrap();
+        let layout = LayoutSection {
+            asset_type: section.asset_type(),
+            section_index: Some(5),
+            folder: "config".to_string(),
+            assets: listed.iter().map(|f| f.to_string()).collect(),
+        };
+        let dir = Path::new("pack/config");
+        let files = found.iter().map(|f| dir.join(f)).collect();
+        let (ordered, missing) = layout.order_files(&section, dir, files);
+        let ordered = ordered
+            .iter()
+            .map(|(f, k)| {
+                (
+                    f.strip_prefix(dir).unwrap().to_slash_lossy().into_owned(),
+                    *k,
+                )
+            })
+            .collect();
+        (ordered, missing)
+    }
+
+    #[test]
+    fn test_order_files() {
+        let (ordered, missing) = order(
+            AssetTypeV2023::CONFIG,
+            &[
+                "b.config.toml",
+                "sub/a.config.toml",
+                "gone.config.toml",
+                "a.config.toml",
+            ],
+            &[
+                "a.config.toml",
+                "z.config.toml",
+                "sub/a.config.toml",
+                "c.config.toml",
+                "b.config.toml",
+            ],
+        );
+        assert_eq!(
+            ordered,
+            vec![
+                ("b.config.toml".to_string(), Some(0)),
+                ("sub/a.config.toml".to_string(), Some(1)),
+                ("a.config.toml".to_string(), Some(3)),
+                ("c.config.toml".to_string(), None),
+                ("z.config.toml".to_string(), None),
+            ]
+        );
+        assert_eq!(missing, vec!["gone.config.toml"]);
+    }
+
+    #[test]
+    fn test_order_files_with_changed_endings() {
+        // Unpacked again in another format.
+        let (ordered, missing) = order(
+            AssetTypeV2023::CONFIG,
+            &["b.config.toml", "a.config.toml"],
+            &["a.config.yaml", "b.config.yaml"],
+        );
+        assert_eq!(
+            ordered,
+            vec![
+                ("b.config.yaml".to_string(), Some(0)),
+                ("a.config.yaml".to_string(), Some(1)),
+            ]
+        );
+        assert!(missing.is_empty());
+
+        // An audio clip replaced with one in another format, next to a file of the same name.
+        let (ordered, missing) = order(
+            AssetTypeV2023::AUDIOCLIP,
+            &["music.ogg", "intro.ogg", "intro.wav"],
+            &["intro.wav", "intro.ogg", "music.wav"],
+        );
+        assert_eq!(
+            ordered,
+            vec![
+                ("music.wav".to_string(), Some(0)),
+                ("intro.ogg".to_string(), Some(1)),
+                ("intro.wav".to_string(), Some(2)),
+            ]
+        );
+        assert!(missing.is_empty());
+    }
+}
@@ -1,6 +1,16 @@
+pub mod audio;
+pub mod bmfont;
 pub mod compression;
+pub mod gltf;
+pub mod graph;
+pub mod json_writer;
+pub mod layout;
+pub mod obj;
 pub mod palette;
 pub mod property_file;
+pub mod round_trip;
+pub mod schema;
 pub mod serialization;
+pub mod svg;
 pub mod unpack;
 pub mod utils;
@@ -0,0 +1,110 @@
+use crate::halley::versions::{
+    v2020::{
+        material::MaterialAttribute,
+        mesh::{get_attribute_components, Mesh, MeshPart, PrimitiveType},
+    },
+    v2023::navmesh::NavmeshSet,
+};
+use anyhow::anyhow;
+use std::fmt::Write;
+
+fn find_attribute<'a>(
+    part: &'a MeshPart,
+    semantic: &str,
+    semantic_index: i32,
+) -> Option<&'a MaterialAttribute> {
+    part.vertex_attributes
+        .iter()
+        .find(|a| a.semantic.eq_ignore_ascii_case(semantic) && a.semantic_index == semantic_index)
+}
+
+fn read_components(
+    part: &MeshPart,
+    attribute: Option<&MaterialAttribute>,
+    count: usize,
+) -> Result<Option<Vec<Vec<f64>>>, anyhow::Error> {
+    attribute
+        .map(|a| {
+            let (components, _) = get_attribute_components(&a.attribute_type);
+            let values = part.read_attribute(a)?;
+            Ok(values
+                .chunks(components)
+                .map(|v| {
+                    (0..count)
+                        .map(|c| v.get(c).copied().unwrap_or_default())
+                        .collect()
+                })
+                .collect())
+        })
+        .transpose()
+}
+
+/// Converts a mesh to Wavefront OBJ. Only triangle meshes are supported, and only positions,
+/// the first set of texture coordinates and normals are exported.
+pub fn mesh_to_obj(mesh: &Mesh) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+    let mut base = 1;
+
+    for (n, part) in mesh.parts.iter().enumerate() {
+        if part.primitive_type != PrimitiveType::Triangle {
+            return Err(anyhow!("Only triangle meshes can be exported to OBJ"));
+        }
+        let positions = read_components(part, find_attribute(part, "POSITION", 0), 3)?
+            .ok_or_else(|| anyhow!("Part {} has no position attribute", n))?;
+        let texcoords = read_components(part, find_attribute(part, "TEXCOORD", 0), 2)?;
+        let normals = read_components(part, find_attribute(part, "NORMAL", 0), 3)?;
+
+        writeln!(out, "o part_{}", n)?;
+        writeln!(out, "usemtl {}", part.material_name)?;
+        for p in &positions {
+            writeln!(out, "v {} {} {}", p[0], p[1], p[2])?;
+        }
+        for t in texcoords.iter().flatten() {
+            writeln!(out, "vt {} {}", t[0], 1.0 - t[1])?;
+        }
+        for n in normals.iter().flatten() {
+            writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+
+        let vertex = |i: u16| {
+            let i = i as usize + base;
+            match (texcoords.is_some(), normals.is_some()) {
+                (true, true) => format!("{}/{}/{}", i, i, i),
+                (true, false) => format!("{}/{}", i, i),
+                (false, true) => format!("{}//{}", i, i),
+                (false, false) => format!("{}", i),
+            }
+        };
+        for f in part.indices.chunks_exact(3) {
+            writeln!(out, "f {} {} {}", vertex(f[0]), vertex(f[1]), vertex(f[2]))?;
+        }
+        base += positions.len();
+    }
+    Ok(out)
+}
+
+/// Converts a navmesh set to Wavefront OBJ, with one object per region. The navmesh plane is
+/// mapped to X and Z, and each sub world is placed one unit higher on Y than the previous one.
+pub fn navmesh_set_to_obj(set: &NavmeshSet) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+    let mut base = 1;
+
+    for navmesh in &set.navmeshes {
+        writeln!(out, "o region_{}", navmesh.region)?;
+        let height = navmesh.sub_world as f32;
+        for polygon in &navmesh.polygons {
+            for v in &polygon.vertices {
+                writeln!(out, "v {} {} {}", v.0, height, v.1)?;
+            }
+        }
+        for polygon in &navmesh.polygons {
+            let face = (base..base + polygon.vertices.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(out, "f {}", face)?;
+            base += polygon.vertices.len();
+        }
+    }
+    Ok(out)
+}
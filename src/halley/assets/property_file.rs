@@ -1,3 +1,6 @@
+use super::serialization::{
+    deserialize, get_format_from_ext, get_serialization_ext, serialize, Format,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
     ffi::{OsStr, OsString},
@@ -5,7 +8,23 @@ use std::{
     path::{Path, PathBuf},
 };
 
-pub static EXT: &str = ".pro.toml";
+/// Key of the properties derived from the asset data, written on unpack for reference only and
+/// dropped again on repack.
+pub static DERIVED_KEY: &str = "$derived";
+
+static PREFIX: &str = ".pro";
+static EXTS: [&str; 3] = [".pro.toml", ".pro.yaml", ".pro.json"];
+
+pub fn is_property_file(filename: &str) -> bool {
+    EXTS.iter().any(|ext| filename.ends_with(ext))
+}
+
+/// Property file of `asset_path`, in whichever format it was written.
+pub fn find(asset_path: &Path) -> Option<PathBuf> {
+    EXTS.iter()
+        .map(|ext| append_to_path(asset_path, ext))
+        .find(|p| p.exists())
+}
 
 pub fn read_with_file_data<T: DeserializeOwned + std::fmt::Debug>(
     asset_path: &Path,
@@ -16,23 +35,37 @@ pub fn read_with_file_data<T: DeserializeOwned + std::fmt::Debug>(
 }
 
 pub fn read<T: DeserializeOwned>(asset_path: &Path) -> Result<T, anyhow::Error> {
-    let filename = append_to_path(asset_path, EXT);
-    let data_str = if filename.exists() {
-        std::fs::read_to_string(filename)?
-    } else {
-        "".to_string()
+    let (data_str, format) = match find(asset_path) {
+        Some(filename) => {
+            let ext = filename.to_str().unwrap().rsplit_once('.').unwrap().1;
+            (
+                std::fs::read_to_string(&filename)?,
+                get_format_from_ext(&format!(".{}", ext)),
+            )
+        }
+        None => ("".to_string(), None),
     };
 
-    let data: T = toml::from_str(&data_str)?;
+    let data: T = match format {
+        Some(Format::Toml5) | None => toml::from_str(&data_str)?,
+        format => deserialize(&data_str, format)?,
+    };
     Ok(data)
 }
 
-pub fn write<T: Serialize>(asset_path: &Path, data: &T) -> Result<(), anyhow::Error> {
-    let filename = append_to_path(asset_path, EXT);
-    let data_str = toml::to_string_pretty(data)?;
-    if data_str.is_empty() {
+pub fn write<T: Serialize>(
+    asset_path: &Path,
+    data: &T,
+    format: Option<Format>,
+) -> Result<(), anyhow::Error> {
+    let data_str = serialize(data, format)?;
+    // Nothing to write for empty properties, which are an empty document in TOML and `{}` in
+    // JSON and YAML.
+    if matches!(data_str.trim(), "" | "{}") {
         return Ok(());
     }
+    let ext = format!("{}{}", PREFIX, get_serialization_ext(format));
+    let filename = append_to_path(asset_path, ext);
     let parent = filename.parent().unwrap();
     if !parent.exists() {
         create_dir_all(parent)?;
@@ -46,3 +79,30 @@ fn append_to_path(p: impl Into<OsString>, s: impl AsRef<OsStr>) -> PathBuf {
     p.push(s);
     p.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::{indexmap, IndexMap};
+
+    #[test]
+    fn test_write_shortens_floats_and_skips_empty() {
+        let dir = std::env::temp_dir().join(format!("halleypack_props_{}", std::process::id()));
+        let asset_path = dir.join("asset.toml");
+        for (format, expected) in [
+            (None, "scale = 0.1\n"),
+            (Some(Format::Yaml), "scale: 0.1\n"),
+            (Some(Format::Json5), "{\n  \"scale\": 0.1\n}"),
+        ] {
+            write(&asset_path, &indexmap! { "scale" => 0.1_f32 }, format).unwrap();
+            let filename = find(&asset_path).unwrap();
+            assert_eq!(std::fs::read_to_string(&filename).unwrap(), expected);
+            std::fs::remove_file(filename).unwrap();
+
+            let empty: IndexMap<String, f32> = IndexMap::new();
+            write(&asset_path, &empty, format).unwrap();
+            assert!(find(&asset_path).is_none());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
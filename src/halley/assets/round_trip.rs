@@ -0,0 +1,423 @@
+use super::{
+    compression, property_file,
+    schema::strip_schema_reference,
+    serialization::{deserialize, serialize, Format},
+    unpack::{unpack_halley_pk_with, UnpackOptions, UnpackedAsset},
+    utils::get_companion_dir,
+};
+use crate::halley::versions::common::hpk::{HalleyPack, HpkAsset, HpkSection};
+use anyhow::anyhow;
+use base64::{engine::general_purpose, Engine as _};
+use flate2::Crc;
+use path_slash::PathExt as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+pub static MANIFEST_FILE: &str = "pack.manifest.toml";
+pub static RAW_DIR: &str = "pack.raw";
+static ASSET_DB_FILE: &str = "asset_db.bin";
+
+/// Everything about a pack that its unpacked files and `PackLayout` don't capture, written by
+/// `unpack_halley_pk_lossless`. Repacking a folder with a manifest reproduces the original file
+/// exactly, and keeps the unedited assets identical when only some of them change.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RoundTripManifest {
+    /// IV of the original file, in base64.
+    pub iv: String,
+    /// The sections of the layout, at the same positions.
+    pub sections: Vec<RoundTripSection>,
+    /// Parts of the data block not covered by any asset, such as padding.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gaps: Vec<DataGap>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RoundTripSection {
+    /// The assets of the layout section, at the same positions.
+    pub assets: Vec<RoundTripAsset>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoundTripAsset {
+    pub pos: usize,
+    pub size: usize,
+    /// CRC-32 of the asset's unpacked files, which tells whether it was edited.
+    pub crc: u32,
+    /// File in `pack.raw` with the original stored bytes, kept when repacking the unpacked files
+    /// does not reproduce them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DataGap {
+    pub pos: usize,
+    /// Bytes of the gap, in base64.
+    pub data: String,
+}
+
+/// Unpacks `pack` like `unpack_halley_pk`, and writes a `RoundTripManifest` so that repacking
+/// the folder gives back the original file. Config positions are always written, so that configs
+/// repack to their original bytes without a raw copy.
+pub fn unpack_halley_pk_lossless(
+    pack: &dyn HalleyPack,
+    path: &Path,
+    options: &UnpackOptions,
+) -> Result<(), anyhow::Error> {
+    let raw_dir = path.join(RAW_DIR);
+    if raw_dir.exists() {
+        fs::remove_dir_all(&raw_dir)?;
+    }
+    fs::create_dir_all(&raw_dir)?;
+
+    let mut manifest = RoundTripManifest {
+        iv: general_purpose::STANDARD.encode(pack.iv()),
+        sections: pack
+            .sections()
+            .iter()
+            .map(|_| RoundTripSection::default())
+            .collect(),
+        gaps: get_gaps(pack),
+    };
+
+    let options = UnpackOptions {
+        config_positions: true,
+        ..options.clone()
+    };
+    unpack_halley_pk_with(pack, path, &options, &mut |unpacked| {
+        let UnpackedAsset {
+            section_index,
+            section,
+            asset,
+            file_path,
+        } = unpacked;
+        let stored = &pack.data()[asset.pos()..asset.pos() + asset.size()];
+        let assets = &mut manifest.sections[section_index].assets;
+
+        let raw = match repack_asset(section, asset, &file_path) {
+            Ok(repacked) if repacked == stored => None,
+            _ => {
+                let raw = format!("{}_{}.bin", section_index, assets.len());
+                fs::write(raw_dir.join(&raw), stored)?;
+                Some(raw)
+            }
+        };
+        assets.push(RoundTripAsset {
+            pos: asset.pos(),
+            size: asset.size(),
+            crc: get_asset_files_crc(section, &file_path)?,
+            raw,
+        });
+        Ok(())
+    })?;
+
+    if let Some(asset_db) = pack.compressed_asset_db() {
+        fs::write(raw_dir.join(ASSET_DB_FILE), asset_db)?;
+    }
+    fs::write(
+        path.join(MANIFEST_FILE),
+        serialize(&manifest, Some(Format::Toml5))?,
+    )?;
+    Ok(())
+}
+
+impl RoundTripManifest {
+    pub fn load(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let filename = path.join(MANIFEST_FILE);
+        if !filename.exists() {
+            return Ok(None);
+        }
+        let manifest = deserialize(&fs::read_to_string(filename)?, Some(Format::Toml5))?;
+        Ok(Some(manifest))
+    }
+
+    /// Restores the IV, asset table and data layout of the original file in a pack built from
+    /// the unpacked files. `section_files` holds each section's asset files in the order the
+    /// assets were added, each with the index of its entry in the layout section, if any.
+    pub fn restore(
+        &self,
+        pack: &mut dyn HalleyPack,
+        path: &Path,
+        section_files: &[Vec<(PathBuf, Option<usize>)>],
+    ) -> Result<(), anyhow::Error> {
+        let iv = general_purpose::STANDARD.decode(&self.iv)?;
+        pack.set_iv(
+            iv.try_into()
+                .map_err(|_| anyhow!("Invalid IV {}", self.iv))?,
+        );
+        let raw_dir = path.join(RAW_DIR);
+        pack.set_compressed_asset_db(fs::read(raw_dir.join(ASSET_DB_FILE)).ok());
+
+        let mut blocks = vec![];
+        for (s, section) in pack.sections().iter().enumerate() {
+            let files = &section_files[s];
+            for (n, asset) in section.assets().into_iter().enumerate() {
+                let (file, listed) = &files[n];
+                let entry = listed.and_then(|k| self.sections.get(s)?.assets.get(k));
+                let current = &pack.data()[asset.pos()..asset.pos() + asset.size()];
+
+                let unchanged = match entry {
+                    Some(entry) => get_asset_files_crc(&**section, file)? == entry.crc,
+                    None => false,
+                };
+                let bytes = match entry.and_then(|e| e.raw.as_ref()) {
+                    Some(raw) if unchanged => fs::read(raw_dir.join(raw))?,
+                    _ => current.to_vec(),
+                };
+                blocks.push(DataBlock {
+                    section: s,
+                    asset: n,
+                    original: entry.map(|e| (e.pos, e.size)),
+                    unchanged,
+                    bytes,
+                });
+            }
+        }
+        // Assets keep their original order in the data, new ones go at the end.
+        blocks.sort_by_key(|b| b.original.map_or(usize::MAX, |(pos, _)| pos));
+
+        let mut data = vec![];
+        let mut gaps = self.gaps.iter().peekable();
+        let mut shared = HashMap::new();
+        let mut positions = HashMap::new();
+        for block in &blocks {
+            match block.original {
+                Some((original_pos, _)) => {
+                    while let Some(gap) = gaps.next_if(|g| g.pos <= original_pos) {
+                        data.extend(general_purpose::STANDARD.decode(&gap.data)?);
+                    }
+                }
+                None => {
+                    for gap in gaps.by_ref() {
+                        data.extend(general_purpose::STANDARD.decode(&gap.data)?);
+                    }
+                }
+            }
+            // Assets that pointed at the same bytes keep doing so.
+            let pos = match (block.unchanged, block.original) {
+                (true, Some(original)) => *shared.entry(original).or_insert_with(|| {
+                    data.extend(&block.bytes);
+                    data.len() - block.bytes.len()
+                }),
+                _ => {
+                    data.extend(&block.bytes);
+                    data.len() - block.bytes.len()
+                }
+            };
+            positions.insert((block.section, block.asset), (pos, block.bytes.len()));
+        }
+        for gap in gaps {
+            data.extend(general_purpose::STANDARD.decode(&gap.data)?);
+        }
+
+        for (s, section) in pack.sections_mut().iter_mut().enumerate() {
+            for (n, asset) in section.assets_mut().into_iter().enumerate() {
+                let (pos, size) = positions[&(s, n)];
+                asset.set_pos_size(pos, size);
+            }
+        }
+        pack.set_data(data);
+        Ok(())
+    }
+}
+
+struct DataBlock {
+    section: usize,
+    asset: usize,
+    original: Option<(usize, usize)>,
+    unchanged: bool,
+    bytes: Vec<u8>,
+}
+
+/// Stored bytes of an asset as `HpkSection::add_asset` would produce them from its files.
+fn repack_asset(
+    section: &dyn HpkSection,
+    asset: &dyn HpkAsset,
+    file_path: &Path,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let data = fs::read(file_path)?;
+    let ext = section.get_data_ext_from_path(file_path);
+    let companion_dir = get_companion_dir(file_path, ext);
+    let data = strip_schema_reference(&data, ext);
+    let data = section.modify_data_on_repack(&data, ext, &companion_dir)?;
+    Ok(match asset.get_asset_compression() {
+        Some(compression) => compression::compress(&data, &compression),
+        None => data,
+    })
+}
+
+/// CRC-32 of an asset file, its properties and its companion files.
+fn get_asset_files_crc(section: &dyn HpkSection, file_path: &Path) -> Result<u32, anyhow::Error> {
+    let mut crc = Crc::new();
+    crc.update(&fs::read(file_path)?);
+
+    if let Some(properties) = property_file::find(file_path) {
+        crc.update(&fs::read(properties)?);
+    }
+
+    let companion_dir = get_companion_dir(file_path, section.get_data_ext_from_path(file_path));
+    if companion_dir.is_dir() {
+        for entry in WalkDir::new(&companion_dir).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                crc.update(
+                    entry
+                        .path()
+                        .strip_prefix(&companion_dir)?
+                        .to_slash_lossy()
+                        .as_bytes(),
+                );
+                crc.update(&fs::read(entry.path())?);
+            }
+        }
+    }
+    Ok(crc.sum())
+}
+
+fn get_gaps(pack: &dyn HalleyPack) -> Vec<DataGap> {
+    let mut ranges: Vec<(usize, usize)> = pack
+        .sections()
+        .iter()
+        .flat_map(|s| s.assets())
+        .map(|a| (a.pos(), a.pos() + a.size()))
+        .collect();
+    ranges.sort();
+
+    let data = pack.data();
+    let mut gaps = vec![];
+    let mut cursor = 0;
+    for (start, end) in ranges.into_iter().chain([(data.len(), data.len())]) {
+        if start > cursor {
+            gaps.push(DataGap {
+                pos: cursor,
+                data: general_purpose::STANDARD.encode(&data[cursor..start]),
+            });
+        }
+        cursor = cursor.max(end);
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        versions::{
+            common::{
+                config::{ConfigFile, ConfigNode, ConfigNodeMap},
+                hpk::{write_to_vec, HalleyPackData},
+            },
+            v2023::hpk::{AssetTypeV2023, HpkAssetV2023, HpkSectionV2023},
+        },
+        write_pack, PackVersion,
+    };
+
+    static SECRET: &str = "K09oemVwNHowNk51S2d1Tg==";
+
+    fn asset(
+        pack: &mut HalleyPackData,
+        name: &str,
+        data: Vec<u8>,
+        config: ConfigNode,
+    ) -> HpkAssetV2023 {
+        let mut asset = HpkAssetV2023 {
+            name: name.to_string(),
+            pos: 0,
+            size: 0,
+            config,
+        };
+        let (pos, size) = pack.add_data(data, asset.get_asset_compression());
+        asset.set_pos_size(pos, size);
+        asset
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let dir = std::env::temp_dir().join(format!("halleypack_lossless_{}", std::process::id()));
+        let mut pack = HalleyPackData::default();
+        pack.set_iv(*b"0123456789abcdef");
+
+        let config = ConfigFile {
+            v: 3,
+            store_file_position: true,
+            root: ConfigNode::Map(ConfigNodeMap::from([(
+                "speed".to_string(),
+                ConfigNode::Float(0.1),
+            )])),
+            positions: None,
+        };
+        let mut configs = HpkSectionV2023::new(AssetTypeV2023::CONFIG as i32).unwrap();
+        configs.set_section_index(7);
+        configs.assets.push(asset(
+            &mut pack,
+            "settings",
+            write_to_vec(&config).unwrap(),
+            ConfigNode::Map(ConfigNodeMap::new()),
+        ));
+        // Padding between assets, which no asset points at.
+        pack.add_data(vec![0xcd; 5], None);
+        let mut texts = HpkSectionV2023::new(AssetTypeV2023::TEXT as i32).unwrap();
+        texts.assets.push(asset(
+            &mut pack,
+            "readme",
+            b"hello hello hello hello".to_vec(),
+            ConfigNode::Map(ConfigNodeMap::from([(
+                "asset_compression".to_string(),
+                ConfigNode::String("deflate".to_string()),
+            )])),
+        ));
+
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x02\0".to_vec();
+        wav.extend(44100_u32.to_le_bytes());
+        wav.extend([0; 8]);
+        let mut clips = HpkSectionV2023::new(AssetTypeV2023::AUDIOCLIP as i32).unwrap();
+        clips.assets.push(asset(
+            &mut pack,
+            "music",
+            wav,
+            ConfigNode::Map(ConfigNodeMap::from([(
+                "loop".to_string(),
+                ConfigNode::Bool(true),
+            )])),
+        ));
+        pack.add_section(Box::new(configs));
+        pack.add_section(Box::new(texts));
+        pack.add_section(Box::new(clips));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        let original = dir.join("original.dat");
+        write_pack(Box::new(pack), &original, Some(SECRET));
+
+        let format = PackVersion::V2023.format();
+        let unpacked = dir.join("unpacked");
+        let pack = format.load(&original, Some(SECRET)).unwrap();
+        unpack_halley_pk_lossless(&*pack, &unpacked, &UnpackOptions::default()).unwrap();
+        let repacked = dir.join("repacked.dat");
+        write_pack(format.pack(&unpacked).unwrap(), &repacked, Some(SECRET));
+
+        let manifest = RoundTripManifest::load(&unpacked).unwrap().unwrap();
+        assert!(!manifest.gaps.is_empty());
+        // Every asset, derived properties included, repacks to its stored bytes.
+        assert!(manifest
+            .sections
+            .iter()
+            .flat_map(|s| &s.assets)
+            .all(|a| a.raw.is_none()));
+        let properties = fs::read_to_string(unpacked.join("audioclip/music.wav.pro.toml")).unwrap();
+        assert!(properties.contains(property_file::DERIVED_KEY));
+        // The section index is kept in the layout only.
+        let section_properties = fs::read_to_string(unpacked.join("config.pro.toml")).unwrap();
+        assert!(!section_properties.contains("section_index"));
+
+        assert_eq!(
+            std::fs::read(&original).unwrap(),
+            std::fs::read(&repacked).unwrap()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
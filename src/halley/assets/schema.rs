@@ -0,0 +1,124 @@
+use crate::halley::versions::common::hpk::HpkSection;
+use std::{borrow::Cow, fs, path::Path};
+
+/// Folder of an unpacked pack holding the schemas its documents refer to.
+pub static SCHEMA_DIR: &str = "pack.schemas";
+static SCHEMA_KEY: &str = "\"$schema\"";
+
+/// Name of the schema file for the documents of `section`'s asset type, after its file ending.
+pub fn get_schema_filename(section: &dyn HpkSection) -> Option<String> {
+    section.get_schema()?;
+    let ending = section
+        .get_unknown_file_type_ending()
+        .trim_start_matches('.');
+    Some(format!("{}.schema.json", ending))
+}
+
+/// Name of the schema file for the asset property files.
+pub fn get_properties_schema_filename() -> String {
+    "properties.schema.json".to_string()
+}
+
+/// Writes the schema of each section's asset type to `dir`. With `properties`, also writes the
+/// schema of the property files.
+pub fn write_schemas(
+    sections: &[&dyn HpkSection],
+    dir: &Path,
+    properties: bool,
+) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(dir)?;
+    for section in sections {
+        if let (Some(filename), Some(schema)) =
+            (get_schema_filename(*section), section.get_schema())
+        {
+            fs::write(dir.join(filename), serde_json::to_string_pretty(&schema)?)?;
+        }
+    }
+    if let (true, Some(section)) = (properties, sections.first()) {
+        fs::write(
+            dir.join(get_properties_schema_filename()),
+            serde_json::to_string_pretty(&section.get_properties_schema())?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Adds a reference to `schema` at the top of a document, the way editors pick it up: a `$schema`
+/// key in JSON, a `yaml-language-server` comment in YAML and a `#:schema` directive in TOML.
+pub fn add_schema_reference(data: &[u8], serialization_ext: &str, schema: &str) -> Vec<u8> {
+    let reference = match serialization_ext {
+        ".json" => match data.strip_prefix(b"{\n") {
+            Some(rest) => {
+                let separator = if rest.starts_with(b"}") { "" } else { "," };
+                let mut out = format!("{{\n  {}: \"{}\"{}\n", SCHEMA_KEY, schema, separator);
+                out.push_str(&String::from_utf8_lossy(rest));
+                return out.into_bytes();
+            }
+            // Only objects can hold the key.
+            None => return data.to_vec(),
+        },
+        ".yaml" => format!("# yaml-language-server: $schema={}\n", schema),
+        ".toml" => format!("#:schema {}\n", schema),
+        _ => return data.to_vec(),
+    };
+    [reference.as_bytes(), data].concat()
+}
+
+/// Removes the `$schema` key `add_schema_reference` puts in an unpacked JSON document, so that
+/// it isn't read as data. Only a reference into `SCHEMA_DIR` is removed, and a `$schema` key of
+/// the document itself is kept. YAML and TOML references are comments and need no stripping.
+pub fn strip_schema_reference<'a>(data: &'a [u8], serialization_ext: &str) -> Cow<'a, [u8]> {
+    let stripped = match serialization_ext {
+        ".json" => std::str::from_utf8(data)
+            .ok()
+            .and_then(strip_json_schema_reference),
+        _ => None,
+    };
+    match stripped {
+        Some(stripped) => Cow::Owned(stripped.into_bytes()),
+        None => Cow::Borrowed(data),
+    }
+}
+
+fn strip_json_schema_reference(i: &str) -> Option<String> {
+    let rest = i.trim_start().strip_prefix('{')?;
+    let rest = rest.trim_start().strip_prefix(SCHEMA_KEY)?;
+    let rest = rest.trim_start().strip_prefix(':')?;
+    let rest = rest.trim_start().strip_prefix('"')?;
+    let (schema, rest) = rest.split_once('"')?;
+    let mut parts = schema.rsplit('/');
+    let is_reference = parts.next()?.ends_with(".schema.json") && parts.next()? == SCHEMA_DIR;
+    if !is_reference {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix(',').unwrap_or(rest);
+    Some(format!("{{{}", rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_schema_reference_round_trip() {
+        let data = "{\n  \"a\": 1\n}";
+        let reference = "../pack.schemas/x.schema.json";
+        let with_ref = add_schema_reference(data.as_bytes(), ".json", reference);
+        assert!(with_ref.starts_with(b"{\n  \"$schema\": \"../pack.schemas/x.schema.json\",\n"));
+        let value: serde_json::Value =
+            serde_json::from_slice(&strip_schema_reference(&with_ref, ".json")).unwrap();
+        assert_eq!(value, serde_json::json!({"a": 1}));
+
+        let empty = add_schema_reference(b"{\n}", ".json", "pack.schemas/x.schema.json");
+        let value: serde_json::Value =
+            serde_json::from_slice(&strip_schema_reference(&empty, ".json")).unwrap();
+        assert_eq!(value, serde_json::json!({}));
+
+        // A `$schema` key of the document itself is data.
+        let own = b"{\n  \"$schema\": \"https://example.com/x.json\",\n  \"a\": 1\n}";
+        assert_eq!(strip_schema_reference(own, ".json"), &own[..]);
+        let with_ref = add_schema_reference(own, ".json", reference);
+        assert_eq!(strip_schema_reference(&with_ref, ".json"), &own[..]);
+    }
+}
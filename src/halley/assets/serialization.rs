@@ -1,10 +1,17 @@
-use anyhow::anyhow;
+use super::json_writer;
+use clap::ValueEnum;
 use serde::{de::DeserializeOwned, Serialize};
 use std::path::Path;
+use toml_edit::{
+    visit_mut::{visit_array_mut, visit_item_mut, visit_table_mut, visit_value_mut, VisitMut},
+    Array, Item, Table, Value,
+};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, ValueEnum)]
 pub enum Format {
+    #[value(name = "json")]
     Json5,
+    #[value(name = "toml")]
     Toml5,
     Yaml,
 }
@@ -13,12 +20,129 @@ static DEFAULT_FORMAT: Format = Format::Toml5;
 
 pub fn serialize<T: Serialize>(t: &T, f: Option<Format>) -> Result<String, anyhow::Error> {
     match f.unwrap_or(DEFAULT_FORMAT) {
-        Format::Json5 => {
-            let json = json5::to_string(t)?;
-            jsonxf::pretty_print(&json).map_err(|e| anyhow!(e))
+        Format::Json5 => json_writer::to_string_pretty(t),
+        Format::Toml5 => {
+            let mut document = toml_edit::ser::to_document(t)?;
+            ShortFloats.visit_document_mut(&mut document);
+            TableLayout { is_value: false }.visit_document_mut(&mut document);
+            Ok(document.to_string())
         }
-        Format::Toml5 => toml::to_string(t).map_err(|e| e.into()),
-        Format::Yaml => serde_yaml::to_string(t).map_err(|e| e.into()),
+        Format::Yaml => {
+            let mut value = serde_yaml::to_value(t)?;
+            shorten_yaml_floats(&mut value);
+            serde_yaml::to_string(&value).map_err(|e| e.into())
+        }
+    }
+}
+
+/// Shortest text for `v` that reads back as the same `f32`. The readers of every format parse
+/// floats as `f64` and round that to `f32`, which for a few values lands next to the one the
+/// shortest `f32` text stands for; those get more digits.
+pub fn f32_text(v: f32) -> String {
+    let reads_back = |text: &str| text.parse::<f64>().is_ok_and(|r| r as f32 == v);
+    let short = format!("{:?}", v);
+    if !v.is_finite() || reads_back(&short) {
+        return short;
+    }
+    (8..17)
+        .map(|precision| format!("{:.*e}", precision, v))
+        .find(|text| reads_back(text))
+        .unwrap_or_else(|| format!("{:?}", v as f64))
+}
+
+/// `v` as an `f32`, when it is exactly one. The asset models have no `f64` fields, so these are
+/// the floats that were written from an `f32`.
+pub fn as_f32(v: f64) -> Option<f32> {
+    let short = v as f32;
+    (v.is_finite() && short as f64 == v).then_some(short)
+}
+
+/// toml writes `f32` values widened to `f64`, with every digit that takes. Rewrites them with
+/// `f32_text`.
+struct ShortFloats;
+
+impl VisitMut for ShortFloats {
+    fn visit_value_mut(&mut self, node: &mut Value) {
+        if let Value::Float(float) = node {
+            // The text of a finite float is always a valid TOML float.
+            if let Some(Ok(mut value)) =
+                as_f32(*float.value()).map(|v| f32_text(v).parse::<Value>())
+            {
+                *value.decor_mut() = float.decor().clone();
+                *node = value;
+            }
+        }
+        visit_value_mut(self, node);
+    }
+}
+
+/// Lays out a document serialized with every map inline the way `toml::to_string` does, with maps
+/// as tables and arrays of maps as arrays of tables, except for typed config nodes. Those stay
+/// inline, as TOML writes tables after the plain values of their parent, which would move them
+/// after the keys that follow them.
+struct TableLayout {
+    is_value: bool,
+}
+
+impl VisitMut for TableLayout {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        let is_parent_value = self.is_value;
+        if !is_parent_value && !is_typed_node(node) {
+            let item = match std::mem::take(node).into_table() {
+                Ok(table) => Item::Table(table),
+                Err(item) => item
+                    .into_array_of_tables()
+                    .map_or_else(|item| item, Item::ArrayOfTables),
+            };
+            *node = item;
+        }
+        self.is_value = node.is_value();
+        visit_item_mut(self, node);
+        self.is_value = is_parent_value;
+    }
+
+    fn visit_table_mut(&mut self, node: &mut Table) {
+        // Empty tables could be semantically meaningful, so make sure they are not implicit
+        if !node.is_empty() {
+            node.set_implicit(true);
+        }
+        visit_table_mut(self, node);
+    }
+
+    fn visit_array_mut(&mut self, node: &mut Array) {
+        visit_array_mut(self, node);
+        node.set_trailing("");
+        node.set_trailing_comma(false);
+    }
+}
+
+/// A map whose only key starts with `!`, which is how config nodes write their typed syntax.
+fn is_typed_node(item: &Item) -> bool {
+    let typed = |value: &Value| {
+        value.as_inline_table().is_some_and(|table| {
+            table.len() == 1 && table.iter().all(|(key, _)| key.starts_with('!'))
+        })
+    };
+    match item.as_value() {
+        Some(Value::Array(array)) => array.iter().any(typed),
+        Some(value) => typed(value),
+        None => false,
+    }
+}
+
+/// Replaces `f32` values with the `f64` that `f32_text` stands for, which serde_yaml writes with
+/// that same text.
+fn shorten_yaml_floats(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Number(number) => {
+            if let Some(v) = number.as_f64().filter(|_| number.is_f64()).and_then(as_f32) {
+                *number = f32_text(v).parse::<f64>().unwrap_or(v.into()).into();
+            }
+        }
+        serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(shorten_yaml_floats),
+        serde_yaml::Value::Mapping(map) => map.values_mut().for_each(shorten_yaml_floats),
+        serde_yaml::Value::Tagged(tagged) => shorten_yaml_floats(&mut tagged.value),
+        _ => {}
     }
 }
 
@@ -64,3 +188,133 @@ pub fn get_serialization_ext_from_path(path: &Path) -> &'static str {
         _ => "",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::common::config::{ConfigNode, ConfigNodeMap};
+    use serde::Deserialize;
+
+    static FORMATS: [Format; 3] = [Format::Toml5, Format::Json5, Format::Yaml];
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Floats {
+        values: Vec<f32>,
+        pair: (f32, f32),
+        node: ConfigNode,
+    }
+
+    fn special_floats() -> Vec<f32> {
+        vec![
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            0.1,
+            1.0 / 3.0,
+            16777216.0,
+            1e10,
+            f32::EPSILON,
+            f32::MIN_POSITIVE,
+            7.038531e-26, // rounds to a neighbour through f64 with its shortest text
+            f32::from_bits(1), // smallest subnormal
+            f32::from_bits(0x007f_ffff), // largest subnormal
+            -f32::from_bits(0x0000_0400),
+            f32::MAX,
+            f32::MIN,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ]
+    }
+
+    fn assert_same_floats(a: &[f32], b: &[f32], format: Format) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            match a.is_nan() {
+                true => assert!(b.is_nan(), "{:?}: {} read back as {}", format, a, b),
+                false => assert_eq!(a.to_bits(), b.to_bits(), "{:?}: {:?}", format, a),
+            }
+        }
+    }
+
+    #[test]
+    fn test_special_floats_round_trip() {
+        let values = special_floats();
+        let floats = Floats {
+            pair: (-0.0, f32::from_bits(1)),
+            node: ConfigNode::Sequence(values.iter().map(|v| ConfigNode::Float(*v)).collect()),
+            values: values.clone(),
+        };
+        for format in FORMATS {
+            let str = serialize(&floats, Some(format)).unwrap();
+            let back: Floats = deserialize(&str, Some(format)).unwrap();
+            assert_same_floats(&values, &back.values, format);
+            assert_same_floats(
+                &[-0.0, f32::from_bits(1)],
+                &[back.pair.0, back.pair.1],
+                format,
+            );
+            let ConfigNode::Sequence(nodes) = back.node else {
+                panic!("{:?}: {:?}", format, back.node);
+            };
+            let nodes: Vec<f32> = nodes
+                .into_iter()
+                .map(|n| match n {
+                    ConfigNode::Float(v) => v,
+                    n => panic!("{:?}: {:?} is not a float", format, n),
+                })
+                .collect();
+            assert_same_floats(&values, &nodes, format);
+        }
+    }
+
+    #[test]
+    fn test_floats_are_written_shortest() {
+        let floats = vec![0.1_f32, 2.13134, 1e-40, -0.0, 1.0];
+        for format in FORMATS {
+            let str = serialize(&indexmap::indexmap! { "v" => &floats }, Some(format)).unwrap();
+            for text in ["0.1", "2.13134", "1e-40", "-0.0", "1.0"] {
+                assert!(str.contains(text), "{:?}: no {} in\n{}", format, text, str);
+            }
+            assert!(!str.contains("0.10000000"), "{:?}:\n{}", format, str);
+        }
+    }
+
+    #[test]
+    fn test_float_sweep_round_trip() {
+        // Every 2^20th bit pattern, which covers each exponent with a spread of mantissas.
+        let values: Vec<f32> = (0..1_u32 << 12)
+            .map(|n| f32::from_bits((n << 20) | ((n * 0x9e37) & 0xf_ffff)))
+            .filter(|v| v.is_finite())
+            .collect();
+        for format in FORMATS {
+            let str = serialize(&indexmap::indexmap! { "v" => &values }, Some(format)).unwrap();
+            let back: indexmap::IndexMap<String, Vec<f32>> =
+                deserialize(&str, Some(format)).unwrap();
+            assert_same_floats(&values, &back["v"], format);
+        }
+    }
+
+    #[test]
+    fn test_toml_keeps_typed_nodes_in_place() {
+        let map = ConfigNode::Map(ConfigNodeMap::from([
+            ("size".to_string(), ConfigNode::Int2((4, 8))),
+            ("name".to_string(), ConfigNode::String("a".to_string())),
+            (
+                "child".to_string(),
+                ConfigNode::Map(ConfigNodeMap::from([(
+                    "id".to_string(),
+                    ConfigNode::Int64(1),
+                )])),
+            ),
+        ]));
+        let str = serialize(&map, Some(Format::Toml5)).unwrap();
+        assert_eq!(
+            str,
+            "size = { \"!int2\" = [4, 8] }\nname = \"a\"\n\n[child]\nid = { \"!int64\" = 1 }\n"
+        );
+        let back: ConfigNode = deserialize(&str, Some(Format::Toml5)).unwrap();
+        assert_eq!(back, map);
+    }
+}
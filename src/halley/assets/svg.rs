@@ -0,0 +1,134 @@
+use crate::halley::versions::v2023::navmesh::{NavmeshPortal, NavmeshSet};
+use std::fmt::Write;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn midpoint(portal: &NavmeshPortal) -> (f32, f32) {
+    (
+        (portal.from.0 + portal.to.0) / 2.0,
+        (portal.from.1 + portal.to.1) / 2.0,
+    )
+}
+
+/// Draws a navmesh set from the top. Polygons are coloured by sub world, portals between
+/// regions are drawn in red with dashed lines to the portals they link to, and sub world
+/// portals are drawn as dashed blue lines between polygon centres.
+pub fn navmesh_set_to_svg(set: &NavmeshSet) -> String {
+    let ((min_x, min_y), (max_x, max_y)) = set.bounds();
+    let (min_x, min_y, max_x, max_y) = if min_x > max_x {
+        (0.0, 0.0, 1.0, 1.0)
+    } else {
+        (min_x, min_y, max_x, max_y)
+    };
+    let size = (max_x - min_x).max(max_y - min_y).max(1.0);
+    let margin = size * 0.02;
+    let stroke = size / 500.0;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        min_x - margin,
+        min_y - margin,
+        max_x - min_x + margin * 2.0,
+        max_y - min_y + margin * 2.0
+    )
+    .unwrap();
+
+    for navmesh in &set.navmeshes {
+        let hue = (navmesh.sub_world * 67).rem_euclid(360);
+        writeln!(out, "  <g id=\"region_{}\">", navmesh.region).unwrap();
+        writeln!(
+            out,
+            "    <title>{} (region {}, sub world {})</title>",
+            escape(&navmesh.region_name),
+            navmesh.region,
+            navmesh.sub_world
+        )
+        .unwrap();
+
+        for (n, polygon) in navmesh.polygons.iter().enumerate() {
+            let points = polygon
+                .vertices
+                .iter()
+                .map(|v| format!("{},{}", v.0, v.1))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                out,
+                "    <polygon points=\"{}\" fill=\"hsl({}, 60%, 70%)\" fill-opacity=\"0.6\" stroke=\"black\" stroke-width=\"{}\"><title>polygon {}, weight {}</title></polygon>",
+                points, hue, stroke, n, polygon.weight
+            )
+            .unwrap();
+        }
+
+        for (n, portal) in navmesh.portals.iter().enumerate() {
+            writeln!(
+                out,
+                "    <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"red\" stroke-width=\"{}\"><title>portal {}</title></line>",
+                portal.from.0,
+                portal.from.1,
+                portal.to.0,
+                portal.to.1,
+                stroke * 3.0,
+                n
+            )
+            .unwrap();
+
+            for link in &portal.links {
+                let Some(other) = set
+                    .find_navmesh(link.region)
+                    .and_then(|n| n.portals.get(link.portal as usize))
+                else {
+                    continue;
+                };
+                let (from, to) = (midpoint(portal), midpoint(other));
+                writeln!(
+                    out,
+                    "    <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"red\" stroke-width=\"{}\" stroke-dasharray=\"{}\"/>",
+                    from.0,
+                    from.1,
+                    to.0,
+                    to.1,
+                    stroke,
+                    stroke * 4.0
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "  </g>").unwrap();
+    }
+
+    for portal in &set.sub_world_portals {
+        let centre = |region: i32, polygon: u16| {
+            set.find_navmesh(region)
+                .and_then(|n| n.polygons.get(polygon as usize))
+                .map(|p| p.centre())
+        };
+        let (Some(from), Some(to)) = (
+            centre(portal.from_region, portal.from_polygon),
+            centre(portal.to_region, portal.to_polygon),
+        ) else {
+            continue;
+        };
+        writeln!(
+            out,
+            "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"blue\" stroke-width=\"{}\" stroke-dasharray=\"{}\"/>",
+            from.0,
+            from.1,
+            to.0,
+            to.1,
+            stroke * 2.0,
+            stroke * 4.0
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "</svg>").unwrap();
+    out
+}
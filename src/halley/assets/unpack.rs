@@ -1,11 +1,21 @@
-use super::property_file;
-use crate::halley::versions::common::hpk::{HalleyPack, HalleyPackData, HpkSection};
+use super::{
+    layout::{LayoutSection, PackLayout, LAYOUT_FILE},
+    property_file, round_trip, schema,
+    serialization::Format,
+    utils::{get_companion_dir, is_in_companion_dir},
+};
+use crate::halley::versions::{
+    common::hpk::{HalleyPack, HalleyPackData, HpkAsset, HpkSection},
+    format::PackFormat,
+};
 use anyhow::anyhow;
 use indexmap::IndexMap;
+use path_slash::PathExt as _;
 use std::{
+    collections::HashMap,
     fs::{create_dir_all, File},
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
 };
 use thiserror::Error;
 use walkdir::WalkDir;
@@ -14,31 +24,108 @@ static SECTION_PREFIX: &str = "section_";
 
 type SectionProps = IndexMap<String, i32>;
 
-pub fn unpack_halley_pk(pack: &dyn HalleyPack, path: &Path) -> Result<(), anyhow::Error> {
+/// Serialization formats used on unpack, for the asset files and their property files.
+#[derive(Debug, Default, Clone)]
+pub struct UnpackOptions {
+    /// Format for every asset type, instead of each type's default.
+    pub format: Option<Format>,
+    /// Formats for specific asset types, taking precedence over `format`.
+    pub type_formats: HashMap<i32, Format>,
+    /// Write the JSON Schemas of the asset types to `pack.schemas` and refer to them from each
+    /// document.
+    pub schemas: bool,
+    /// Write the source positions of config nodes to a companion file, so that repacking keeps
+    /// them. Without it, repacked configs get synthesized positions.
+    pub config_positions: bool,
+}
+
+impl UnpackOptions {
+    pub fn format_for(&self, asset_type: i32) -> Option<Format> {
+        self.type_formats.get(&asset_type).copied().or(self.format)
+    }
+}
+
+/// Asset written by `unpack_halley_pk_with`.
+pub struct UnpackedAsset<'a> {
+    pub section_index: usize,
+    pub section: &'a dyn HpkSection,
+    pub asset: &'a dyn HpkAsset,
+    pub file_path: PathBuf,
+}
+
+pub fn unpack_halley_pk(
+    pack: &dyn HalleyPack,
+    path: &Path,
+    options: &UnpackOptions,
+) -> Result<(), anyhow::Error> {
+    unpack_halley_pk_with(pack, path, options, &mut |_| Ok(()))
+}
+
+/// Unpacks `pack`, calling `on_asset` after each asset is written.
+pub fn unpack_halley_pk_with(
+    pack: &dyn HalleyPack,
+    path: &Path,
+    options: &UnpackOptions,
+    on_asset: &mut dyn FnMut(UnpackedAsset) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
     create_dir_all(path)?;
 
     if !path.is_dir() {
         return Err(anyhow!("Path is not a directory",));
     }
 
-    for (i, section) in pack.sections().iter().enumerate() {
-        let section_name = format!("{}{}", SECTION_PREFIX, i);
+    if options.schemas {
+        let sections: Vec<&dyn HpkSection> = pack.sections().iter().map(|s| &**s).collect();
+        schema::write_schemas(&sections, &path.join(schema::SCHEMA_DIR), true)?;
+    }
+
+    let mut layout = PackLayout::default();
+    let section_names = get_section_folder_names(pack.sections());
+
+    for (i, (section, section_name)) in pack.sections().iter().zip(section_names).enumerate() {
         let mut map = SectionProps::new();
         map.insert("asset_type".to_string(), section.asset_type());
 
-        let section_path = &path.join(section_name);
+        let section_path = &path.join(&section_name);
+        let mut layout_section = LayoutSection {
+            asset_type: section.asset_type(),
+            section_index: section.section_index(),
+            folder: section_name,
+            assets: vec![],
+        };
 
-        property_file::write(section_path, &map)?;
+        property_file::write(section_path, &map, options.format)?;
+        let format = options.format_for(section.asset_type());
         create_dir_all(section_path)?;
+        let schema_filename = match options.schemas {
+            true => schema::get_schema_filename(&**section),
+            false => None,
+        };
 
         for asset in section.assets().into_iter() {
             let data = pack.get_asset_data(*asset);
-            let (data, serialization_ext) = section.modify_data_on_unpack(&data)?;
+            let derived = section.get_derived_properties(&data);
+            let companions = section.get_companion_files(&data, options)?;
+            let (data, serialization_ext) = section.modify_data_on_unpack(&data, format)?;
 
             let filename = section.get_asset_filename(*asset, serialization_ext);
-            let file_path = section_path.join(filename);
+            let file_path = section_path.join(&filename);
             //let file_path = section_path.join(&filename);
-            asset.serialize_properties(&file_path)?;
+            let data = match &schema_filename {
+                Some(schema_filename) => {
+                    // Relative to the document, which sits in its section folder.
+                    let depth = Path::new(&filename).components().count();
+                    let reference = format!(
+                        "{}{}/{}",
+                        "../".repeat(depth),
+                        schema::SCHEMA_DIR,
+                        schema_filename
+                    );
+                    schema::add_schema_reference(&data, serialization_ext, &reference)
+                }
+                None => data,
+            };
+            asset.serialize_properties(&file_path, &derived, format)?;
 
             let parent = file_path.parent().unwrap();
 
@@ -48,89 +135,415 @@ pub fn unpack_halley_pk(pack: &dyn HalleyPack, path: &Path) -> Result<(), anyhow
 
             let mut file = File::create(&file_path)?;
             file.write_all(&data)?;
+
+            let companion_dir = get_companion_dir(&file_path, serialization_ext);
+            for (name, data) in companions {
+                let companion_path = companion_dir.join(name);
+                create_dir_all(companion_path.parent().unwrap())?;
+                std::fs::write(companion_path, data)?;
+            }
+
+            layout_section
+                .assets
+                .push(Path::new(&filename).to_slash_lossy().into_owned());
+
+            on_asset(UnpackedAsset {
+                section_index: i,
+                section: &**section,
+                asset: *asset,
+                file_path,
+            })?;
         }
+        layout.sections.push(layout_section);
     }
 
-    Ok(())
+    layout.save(path)
+}
+
+/// Names of the section folders: the lowercase asset type name, followed by `_2`, `_3`, … for
+/// further sections of the same type.
+pub fn get_section_folder_names(sections: &[Box<dyn HpkSection>]) -> Vec<String> {
+    let mut counts = HashMap::new();
+    sections
+        .iter()
+        .map(|section| {
+            let name = section.asset_type_name().to_lowercase();
+            let count = counts.entry(name.clone()).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => name,
+                n => format!("{}_{}", name, n),
+            }
+        })
+        .collect()
+}
+
+/// Where a section folder not listed in the layout goes, and the asset type its name gives.
+/// `section_N` folders come first, by index, then the folders named after an asset type, with
+/// the `_2`, `_3`, … suffixes of `get_section_folder_names` after the plain name.
+fn get_unlisted_section_key(
+    filename: &str,
+    type_names: &[(i32, String)],
+) -> Option<((bool, i32, usize), Option<i32>)> {
+    if let Some(index) = filename.strip_prefix(SECTION_PREFIX) {
+        return Some(((false, index.parse().ok()?, 0), None));
+    }
+    let find_type = |name: &str| type_names.iter().find(|(_, n)| n == name).map(|(t, _)| *t);
+    // Whole type names first, in case one ends in what looks like a suffix.
+    if let Some(asset_type) = find_type(filename) {
+        return Some(((true, asset_type, 1), Some(asset_type)));
+    }
+    let (name, suffix) = filename.rsplit_once('_')?;
+    let n: usize = suffix.parse().ok()?;
+    if n < 2 || n.to_string() != suffix {
+        return None;
+    }
+    let asset_type = find_type(name)?;
+    Some(((true, asset_type, n), Some(asset_type)))
 }
 
 #[derive(Error, Debug)]
 pub enum PackError {
-    #[error("Invalid file {0} in sections folder")]
-    InvalidFileInSections(String),
-
-    #[error("Missing asset type for section_{0}")]
-    MissingAssetType(i32),
+    #[error("Missing asset type for {0}")]
+    MissingAssetType(String),
 }
 
-pub fn pack_halley_pk<Section: HpkSection + 'static>(
+/// Builds a pack from a folder written by `unpack_halley_pk`. Sections and assets listed in the
+/// folder's `PackLayout` keep their order, and files it doesn't list are added after them. Listed
+/// assets whose file is gone are left out with a warning. Other section folders, named
+/// `section_N` or after their asset type, are added after the listed ones. Other entries in the
+/// folder are ignored. Sections the layout gives no index are numbered by `format`.
+pub fn pack_halley_pk(
     path: &Path,
+    format: &dyn PackFormat,
 ) -> Result<Box<dyn HalleyPack>, anyhow::Error> {
-    let mut dir = path.read_dir()?;
+    let layout = PackLayout::load(path)?;
+    let manifest = round_trip::RoundTripManifest::load(path)?;
+    let listed = layout
+        .as_ref()
+        .map_or(vec![], |l| l.sections.iter().collect());
+
+    let type_names: Vec<(i32, String)> = format
+        .asset_types()
+        .iter()
+        .map(|(t, name)| (*t, name.to_lowercase()))
+        .collect();
+    let mut unlisted: Vec<_> = path
+        .read_dir()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let filename = entry.file_name().to_str()?.to_owned();
+            let (key, folder_type) = get_unlisted_section_key(&filename, &type_names)?;
+            let is_listed = listed.iter().any(|s| s.folder == filename);
+            (entry.file_type().ok()?.is_dir() && !is_listed).then_some((key, filename, folder_type))
+        })
+        .collect();
+    unlisted.sort();
 
-    let non_section_entry = dir.find_map(|entry| {
-        let entry = entry.unwrap();
-        let is_dir = entry.file_type().unwrap().is_dir();
-
-        let filename = entry.file_name().to_str().unwrap().to_owned();
+    let mut pack = HalleyPackData::default();
+    let mut section_files = vec![];
+    let mut missing = vec![];
+
+    let sections = listed
+        .iter()
+        .map(|s| (s.folder.clone(), Some(*s), None))
+        .chain(
+            unlisted
+                .into_iter()
+                .map(|(_, folder, folder_type)| (folder, None, folder_type)),
+        );
+    for (folder, layout_section, folder_type) in sections {
+        let section_filename = path.join(&folder);
+
+        let section_type = match layout_section {
+            Some(s) => s.asset_type,
+            None => {
+                let section_props: SectionProps = property_file::read(&section_filename)?;
+                section_props
+                    .get("asset_type")
+                    .copied()
+                    .or(folder_type)
+                    .ok_or_else(|| PackError::MissingAssetType(folder.clone()))?
+            }
+        };
 
-        let is_section = filename.starts_with(SECTION_PREFIX)
-            && (is_dir || filename.ends_with(property_file::EXT));
-        if is_section {
-            return None;
+        let mut section = format.new_section(section_type)?;
+        if let Some(section_index) = layout_section.and_then(|s| s.section_index) {
+            section.set_section_index(section_index);
         }
 
-        Some(filename)
-    });
+        let paths: Vec<PathBuf> = match section_filename.is_dir() {
+            true => WalkDir::new(&section_filename)
+                .follow_links(false)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| {
+                    let e = e.ok()?;
+                    let path = e.path();
+
+                    if e.file_type().is_file()
+                        && !property_file::is_property_file(path.to_str().unwrap())
+                    {
+                        return Some(path.to_path_buf());
+                    }
+                    None
+                })
+                .filter(|path| !is_in_companion_dir(path.strip_prefix(&section_filename).unwrap()))
+                .collect(),
+            false => vec![],
+        };
+        let paths = match layout_section {
+            Some(layout_section) => {
+                let (ordered, section_missing) =
+                    layout_section.order_files(&*section, &section_filename, paths);
+                missing.extend(
+                    section_missing
+                        .into_iter()
+                        .map(|f| format!("{}/{}", folder, f)),
+                );
+                ordered
+            }
+            None => paths.into_iter().map(|path| (path, None)).collect(),
+        };
 
-    if let Some(non_section_entry) = non_section_entry {
-        return Err(PackError::InvalidFileInSections(non_section_entry).into());
+        for (file_path, _) in &paths {
+            let relative_path = file_path.strip_prefix(&section_filename)?;
+            section.add_asset(&mut pack, file_path.as_path(), relative_path)?;
+        }
+        pack.add_section(section);
+        section_files.push(paths);
     }
+    for file in missing {
+        eprintln!(
+            "Warning: {} is listed in {} but was not found, and was left out",
+            file, LAYOUT_FILE
+        );
+    }
+    if let Some(manifest) = &manifest {
+        manifest.restore(&mut pack, path, &section_files)?;
+    }
+    Ok(Box::new(pack))
+}
 
-    let mut pack = HalleyPackData::default();
-
-    let mut index = 0;
-
-    loop {
-        let section_name = format!("{}{}", SECTION_PREFIX, index);
-        let section_filename = path.join(section_name);
-
-        if !section_filename.exists() {
-            break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        versions::{
+            common::{
+                config::{ConfigFile, ConfigNode, ConfigNodeMap},
+                hpk::write_to_vec,
+            },
+            v2023::hpk::{AssetTypeV2023, HpkAssetV2023, HpkSectionV2023},
+        },
+        PackVersion,
+    };
+
+    fn config_pack(names: &[&str]) -> HalleyPackData {
+        let mut pack = HalleyPackData::default();
+        let mut section = HpkSectionV2023::new(AssetTypeV2023::CONFIG as i32).unwrap();
+        for name in names {
+            let file = ConfigFile {
+                v: 3,
+                store_file_position: false,
+                root: ConfigNode::Map(ConfigNodeMap::from([(
+                    "name".to_string(),
+                    ConfigNode::String(name.to_string()),
+                )])),
+                positions: None,
+            };
+            let (pos, size) = pack.add_data(write_to_vec(&file).unwrap(), None);
+            section.assets.push(HpkAssetV2023 {
+                name: name.to_string(),
+                pos,
+                size,
+                config: ConfigNode::Map(ConfigNodeMap::new()),
+            });
         }
+        pack.add_section(Box::new(section));
+        pack
+    }
 
-        let section_props: SectionProps = property_file::read(section_filename.as_path())?;
-        let section_type = *section_props
-            .get("asset_type")
-            .ok_or(PackError::MissingAssetType(index))?;
-
-        let mut section = Section::new(section_type)?;
+    fn asset_names(pack: &dyn HalleyPack) -> Vec<String> {
+        pack.sections()[0]
+            .assets()
+            .iter()
+            .map(|a| a.name().to_owned())
+            .collect()
+    }
 
-        let paths = WalkDir::new(&section_filename)
-            .follow_links(false)
-            .into_iter()
-            .filter_map(|e| {
-                let e = e.ok()?;
-                let path = e.path();
+    #[test]
+    fn test_repack_with_changed_endings() {
+        let dir = std::env::temp_dir().join(format!("halleypack_layout_{}", std::process::id()));
+        let (toml_dir, yaml_dir) = (dir.join("toml"), dir.join("yaml"));
+        let pack = config_pack(&["b", "c", "a"]);
+        let options = |format| UnpackOptions {
+            format: Some(format),
+            ..Default::default()
+        };
+        unpack_halley_pk(&pack, &toml_dir, &options(Format::Toml5)).unwrap();
+        unpack_halley_pk(&pack, &yaml_dir, &options(Format::Yaml)).unwrap();
+
+        // The layout lists the TOML files, but the folder holds YAML ones.
+        std::fs::copy(toml_dir.join(LAYOUT_FILE), yaml_dir.join(LAYOUT_FILE)).unwrap();
+        let repacked = pack_halley_pk(&yaml_dir, PackVersion::V2023.format()).unwrap();
+        assert_eq!(asset_names(&*repacked), vec!["b", "c", "a"]);
+
+        // A deleted asset is left out.
+        std::fs::remove_file(yaml_dir.join("config/c.config.yaml")).unwrap();
+        let repacked = pack_halley_pk(&yaml_dir, PackVersion::V2023.format()).unwrap();
+        assert_eq!(asset_names(&*repacked), vec!["b", "a"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-                if e.file_type().is_file() && !path.to_str().unwrap().ends_with(property_file::EXT)
-                {
-                    return Some(path.to_path_buf());
-                }
-                None
+    #[test]
+    fn test_repack_leaves_audio_clip_properties_alone() {
+        let dir = std::env::temp_dir().join(format!("halleypack_audio_{}", std::process::id()));
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x02\0".to_vec();
+        wav.extend(44100_u32.to_le_bytes());
+        wav.extend([0; 8]);
+
+        let mut pack = HalleyPackData::default();
+        let mut section = HpkSectionV2023::new(AssetTypeV2023::AUDIOCLIP as i32).unwrap();
+        let configs = [
+            ConfigNode::Map(ConfigNodeMap::from([(
+                "sample_rate".to_string(),
+                ConfigNode::Int(22050),
+            )])),
+            ConfigNode::Int(5),
+        ];
+        for (name, config) in ["music", "other"].iter().zip(configs.clone()) {
+            let (pos, size) = pack.add_data(wav.clone(), None);
+            section.assets.push(HpkAssetV2023 {
+                name: name.to_string(),
+                pos,
+                size,
+                config,
             });
+        }
+        pack.add_section(Box::new(section));
 
-        for file_path in paths {
-            if file_path.is_dir() {
-                continue;
-            }
+        let options = UnpackOptions {
+            format: Some(Format::Yaml),
+            ..Default::default()
+        };
+        unpack_halley_pk(&pack, &dir, &options).unwrap();
+        let properties = std::fs::read_to_string(dir.join("audioclip/music.wav.pro.yaml")).unwrap();
+        assert!(properties.contains("$derived"));
+        assert!(properties.contains("sample_rate: 44100"));
+        let properties = std::fs::read_to_string(dir.join("audioclip/other.wav.pro.yaml")).unwrap();
+        assert_eq!(properties, "5\n");
+
+        let repacked = pack_halley_pk(&dir, PackVersion::V2023.format()).unwrap();
+        let repacked: Vec<_> = repacked.sections()[0]
+            .assets()
+            .iter()
+            .map(|a| a.get_properties())
+            .collect();
+        assert_eq!(repacked, configs);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-            let relative_path = file_path.strip_prefix(&section_filename)?;
-            section.add_asset(&mut pack, file_path.as_path(), relative_path)?;
+    #[test]
+    fn test_section_folder_names() {
+        let sections: Vec<Box<dyn HpkSection>> = [
+            AssetTypeV2023::CONFIG,
+            AssetTypeV2023::SPRITESHEET,
+            AssetTypeV2023::CONFIG,
+            AssetTypeV2023::CONFIG,
+        ]
+        .into_iter()
+        .map(|t| Box::new(HpkSectionV2023::new(t as i32).unwrap()) as Box<dyn HpkSection>)
+        .collect();
+        let names = get_section_folder_names(&sections);
+        assert_eq!(names, vec!["config", "spritesheet", "config_2", "config_3"]);
+    }
+
+    #[test]
+    fn test_unlisted_section_keys() {
+        let type_names = vec![
+            (2, "config".to_string()),
+            (5, "spritesheet".to_string()),
+            (9, "odd_2".to_string()),
+        ];
+        let key = |f| get_unlisted_section_key(f, &type_names);
+        let mut folders = vec![
+            "config_3",
+            "section_10",
+            "odd_2_2",
+            "spritesheet",
+            "config",
+            "section_2",
+            "odd_2",
+            "config_2",
+        ];
+        folders.sort_by_key(|f| key(f).unwrap().0);
+        assert_eq!(
+            folders,
+            vec![
+                "section_2",
+                "section_10",
+                "config",
+                "config_2",
+                "config_3",
+                "spritesheet",
+                "odd_2",
+                "odd_2_2"
+            ]
+        );
+        assert_eq!(key("config_3").unwrap().1, Some(2));
+        assert_eq!(key("odd_2").unwrap().1, Some(9));
+        assert_eq!(key("section_0").unwrap().1, None);
+
+        // `config_1` and `config_02` would sort next to `config` and `config_2`.
+        for folder in [
+            "config_1",
+            "config_0",
+            "config_02",
+            "config_x",
+            "pack.schemas",
+            "other",
+        ] {
+            assert!(key(folder).is_none(), "{}", folder);
         }
-        pack.add_section(Box::new(section));
+    }
 
-        index += 1;
+    #[test]
+    fn test_repack_section_folders_without_layout() {
+        let dir = std::env::temp_dir().join(format!("halleypack_sections_{}", std::process::id()));
+        let mut pack = config_pack(&["a"]);
+        let second = config_pack(&["b"]);
+        let (pos, size) = pack.add_data(second.data().to_vec(), None);
+        let mut section = HpkSectionV2023::new(AssetTypeV2023::CONFIG as i32).unwrap();
+        section.assets.push(HpkAssetV2023 {
+            name: "b".to_string(),
+            pos,
+            size,
+            config: ConfigNode::Map(ConfigNodeMap::new()),
+        });
+        pack.add_section(Box::new(section));
+        unpack_halley_pk(&pack, &dir, &UnpackOptions::default()).unwrap();
+        std::fs::remove_file(dir.join(LAYOUT_FILE)).unwrap();
+
+        // The old layout for the second section, without a property file for the first.
+        std::fs::rename(dir.join("config_2"), dir.join("section_0")).unwrap();
+        std::fs::rename(
+            dir.join("config_2.pro.toml"),
+            dir.join("section_0.pro.toml"),
+        )
+        .unwrap();
+        std::fs::remove_file(dir.join("config.pro.toml")).unwrap();
+
+        let repacked = pack_halley_pk(&dir, PackVersion::V2023.format()).unwrap();
+        let names: Vec<_> = repacked
+            .sections()
+            .iter()
+            .map(|s| (s.asset_type(), s.assets()[0].name().to_owned()))
+            .collect();
+        assert_eq!(names, vec![(2, "b".to_string()), (2, "a".to_string())]);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
-    Ok(Box::new(pack))
 }
@@ -1,14 +1,32 @@
 use std::{
+    ffi::OsString,
     fs,
     path::{Path, PathBuf},
 };
 
+/// Suffix of the folder holding the extra files an asset unpacks to, next to its main file.
+pub static COMPANION_DIR_EXT: &str = ".files";
+
 pub fn pathify(name: &str, ext: &str) -> String {
     let mut filename = format!("{}{}", name, ext).to_string();
     if !filename.contains('.') {
         filename = format!("{}.unk", filename).to_string();
     }
-    filename.replace(':', "___..___")
+    escape_colons(&filename)
+}
+
+fn escape_colons(name: &str) -> String {
+    name.replace(':', "___..___")
+}
+
+/// Makes `name` safe to use as a single component of a path: separators are replaced, and names
+/// made only of dots get a leading underscore so they can't point at a parent folder.
+pub fn pathify_component(name: &str) -> String {
+    let name = escape_colons(name).replace(['/', '\\'], "_");
+    match name.chars().all(|c| c == '.') {
+        true => format!("_{}", name),
+        false => name.to_string(),
+    }
 }
 
 pub fn unpathify(name: &str, ext: &str) -> String {
@@ -21,6 +39,24 @@ pub fn unpathify(name: &str, ext: &str) -> String {
     filename
 }
 
+pub fn get_companion_dir(file_path: &Path, serialization_ext: &str) -> PathBuf {
+    let mut dir: OsString = file_path
+        .to_str()
+        .and_then(|p| p.strip_suffix(serialization_ext))
+        .map(OsString::from)
+        .unwrap_or_else(|| file_path.as_os_str().to_owned());
+    dir.push(COMPANION_DIR_EXT);
+    dir.into()
+}
+
+pub fn is_in_companion_dir(relative_path: &Path) -> bool {
+    relative_path
+        .parent()
+        .into_iter()
+        .flat_map(|p| p.components())
+        .any(|c| c.as_os_str().to_string_lossy().ends_with(COMPANION_DIR_EXT))
+}
+
 pub fn get_dat_files(dir: &Path) -> Vec<PathBuf> {
     let mut files = Vec::new();
     for entry in fs::read_dir(dir).unwrap() {
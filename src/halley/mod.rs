@@ -1,12 +1,22 @@
 use self::{
     assets::{
-        unpack::{pack_halley_pk, unpack_halley_pk},
+        gltf::{mesh_animation_to_gltf, mesh_to_gltf},
+        graph::{to_dot, to_mermaid, GraphConvertible},
+        obj::{mesh_to_obj, navmesh_set_to_obj},
+        serialization::{serialize, Format},
+        svg::navmesh_set_to_svg,
+        unpack::{unpack_halley_pk, UnpackOptions},
         utils::{get_dat_files, get_dat_folders},
     },
     versions::{
-        common::hpk::HalleyPack,
-        v2020::hpk::{HalleyPackV2020, HpkSectionV2020},
-        v2023::hpk::{HalleyPackV2023, HpkSectionV2023},
+        common::hpk::{find_asset_data, HalleyPack, Parsable},
+        common::hpk_parse::encrypt_pack_data,
+        convert::ConversionIssue,
+        format::{PackFormat, PackFormatRegistry},
+        v2020::{mesh::Mesh, mesh_animation::MeshAnimation},
+        v2023::{
+            navmesh::NavmeshSet, render_graph::RenderGraphDefinition, script_graph::ScriptGraph,
+        },
     },
 };
 use clap::ValueEnum;
@@ -28,7 +38,32 @@ pub enum PackVersion {
     V2023,
 }
 
-pub fn unpack_assets(src: &Path, dst: &Path, pack_version: PackVersion, secret: Option<&str>) {
+impl PackVersion {
+    /// The built-in `PackFormat` for this version.
+    pub fn format(&self) -> &'static dyn PackFormat {
+        PackFormatRegistry::builtin()
+            .get(&format!("{:?}", self))
+            .unwrap()
+    }
+}
+
+pub fn unpack_assets(
+    src: &Path,
+    dst: &Path,
+    pack_version: PackVersion,
+    secret: Option<&str>,
+    options: &UnpackOptions,
+) {
+    unpack_assets_with_format(src, dst, pack_version.format(), secret, options)
+}
+
+pub fn unpack_assets_with_format(
+    src: &Path,
+    dst: &Path,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+    options: &UnpackOptions,
+) {
     let dat_files = get_dat_files(src);
     if !dst.exists() && !dat_files.is_empty() {
         fs::create_dir_all(dst).unwrap();
@@ -41,12 +76,21 @@ pub fn unpack_assets(src: &Path, dst: &Path, pack_version: PackVersion, secret:
             fs::remove_dir_all(&dst_file).unwrap();
         }
         fs::create_dir_all(&dst_file).unwrap();
-        let pack = read_pack(dat_file, pack_version, secret);
-        unpack_halley_pk(&*pack, &dst_file).unwrap();
+        let pack = format.load(dat_file, secret).unwrap();
+        unpack_halley_pk(&*pack, &dst_file, options).unwrap();
     });
 }
 
 pub fn pack_assets(src: &Path, dst: &Path, pack_version: PackVersion, secret: Option<&str>) {
+    pack_assets_with_format(src, dst, pack_version.format(), secret)
+}
+
+pub fn pack_assets_with_format(
+    src: &Path,
+    dst: &Path,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) {
     let dat_folders = get_dat_folders(src);
     if !dst.exists() {
         panic!("Destination folder does not exist");
@@ -57,30 +101,27 @@ pub fn pack_assets(src: &Path, dst: &Path, pack_version: PackVersion, secret: Op
         // if dst_file.exists() {
         //     fs::remove_file(&dst_file).unwrap();
         // }
-        let pack = pack_asset(dat_folder, pack_version);
+        let pack = format.pack(dat_folder).unwrap();
         write_pack(pack, &dst_file, secret);
     });
 }
 
 pub fn read_pack(
     path: &Path,
-    pack_version: PackVersion,
+    format: &dyn PackFormat,
     secret: Option<&str>,
 ) -> Box<dyn HalleyPack> {
-    match pack_version {
-        PackVersion::V2023 => HalleyPackV2023::load(path, secret).unwrap(),
-        PackVersion::V2020 => HalleyPackV2020::load(path, secret).unwrap(),
-    }
+    format.load(path, secret).unwrap()
 }
 
-pub fn pack_asset(path: &Path, pack_version: PackVersion) -> Box<dyn HalleyPack> {
-    match pack_version {
-        PackVersion::V2023 => pack_halley_pk::<HpkSectionV2023>(path).unwrap(),
-        PackVersion::V2020 => pack_halley_pk::<HpkSectionV2020>(path).unwrap(),
-    }
+pub fn pack_asset(path: &Path, format: &dyn PackFormat) -> Box<dyn HalleyPack> {
+    format.pack(path).unwrap()
 }
 
-pub fn write_pack(pack: Box<dyn HalleyPack>, path: &Path, secret: Option<&str>) {
+/// Writes `pack` to `path`. Packs read from an encrypted file keep their IV, and are encrypted
+/// again with it when a secret is given.
+pub fn write_pack(mut pack: Box<dyn HalleyPack>, path: &Path, secret: Option<&str>) {
+    encrypt_pack_data(&mut *pack, secret);
     let mut writer = BufWriter::new(fs::File::create(path).unwrap());
     let buf = vec![];
     let res = pack.write()(WriteContext {
@@ -90,3 +131,166 @@ pub fn write_pack(pack: Box<dyn HalleyPack>, path: &Path, secret: Option<&str>)
     .unwrap();
     writer.write_all(&res.write).unwrap();
 }
+
+/// Converts a pack from one version to another with the conversion `registry` holds for them,
+/// returning what could not be converted.
+pub fn convert_pack(
+    src: &Path,
+    dst: &Path,
+    from: &dyn PackFormat,
+    to: &dyn PackFormat,
+    registry: &PackFormatRegistry,
+    secret: Option<&str>,
+) -> Result<Vec<ConversionIssue>, anyhow::Error> {
+    let converter = registry.converter(from.name(), to.name())?;
+    let pack = from.load(src, secret)?;
+    let (pack, issues) = converter(&*pack, to)?;
+    write_pack(pack, dst, secret);
+    Ok(issues)
+}
+
+/// Writes the JSON Schemas of the documents unpacked from `format` packs to `dst`.
+pub fn export_schemas(dst: &Path, format: &dyn PackFormat) -> Result<(), anyhow::Error> {
+    format.write_schemas(dst)
+}
+
+pub fn export_font(
+    src: &Path,
+    dst: &Path,
+    font_name: &str,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let pack = format.load(src, secret)?;
+    format.export_font(&*pack, font_name, dst)
+}
+
+pub fn import_font(
+    font_file: &Path,
+    bmfont_file: &Path,
+    image_file: Option<&Path>,
+    format: &dyn PackFormat,
+) -> Result<(), anyhow::Error> {
+    format.import_font(font_file, bmfont_file, image_file)
+}
+
+/// Exports a mesh to glTF, or to OBJ if `dst` has an `.obj` extension.
+pub fn export_mesh(
+    src: &Path,
+    dst: &Path,
+    mesh_name: &str,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let pack = format.load(src, secret)?;
+    let asset_type = format.asset_type("MESH").unwrap();
+    let data = find_asset_data(&*pack, asset_type, mesh_name)
+        .ok_or_else(|| anyhow::anyhow!("Mesh {} not found", mesh_name))?;
+    let (_, mesh) = Mesh::parse(&data).map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let data = match dst.extension().and_then(|e| e.to_str()) {
+        Some("obj") => mesh_to_obj(&mesh)?.into_bytes(),
+        _ => mesh_to_gltf(&mesh)?,
+    };
+    fs::write(dst, data)?;
+    Ok(())
+}
+
+/// Exports a mesh animation to glTF together with `mesh_name`, or to a YAML timeline when no
+/// mesh is given.
+pub fn export_mesh_animation(
+    src: &Path,
+    dst: &Path,
+    animation_name: &str,
+    mesh_name: Option<&str>,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let pack = format.load(src, secret)?;
+    let animation_type = format.asset_type("MESHANIMATION").unwrap();
+    let mesh_type = format.asset_type("MESH").unwrap();
+    let data = find_asset_data(&*pack, animation_type, animation_name)
+        .ok_or_else(|| anyhow::anyhow!("Mesh animation {} not found", animation_name))?;
+    let (_, animation) =
+        MeshAnimation::parse(&data).map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let data = match mesh_name {
+        Some(mesh_name) => {
+            let data = find_asset_data(&*pack, mesh_type, mesh_name)
+                .ok_or_else(|| anyhow::anyhow!("Mesh {} not found", mesh_name))?;
+            let (_, mesh) = Mesh::parse(&data).map_err(|err| anyhow::anyhow!(err.to_string()))?;
+            mesh_animation_to_gltf(&animation, &mesh)?
+        }
+        None => serialize(&animation.to_timeline(), Some(Format::Yaml))?.into_bytes(),
+    };
+    fs::write(dst, data)?;
+    Ok(())
+}
+
+fn parse_graph<T: Parsable + GraphConvertible + 'static>(
+    data: &[u8],
+) -> Result<Box<dyn GraphConvertible>, anyhow::Error> {
+    let (_, graph) = T::parse(data).map_err(|err| anyhow::anyhow!(err.to_string()))?;
+    Ok(Box::new(graph))
+}
+
+/// Exports a render graph or script graph asset to Graphviz DOT, or to a Mermaid flowchart when
+/// the output file has the `.mmd` extension.
+pub fn export_graph(
+    src: &Path,
+    dst: &Path,
+    graph_name: &str,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let render_graph_type = format.asset_type("RENDERGHRAPHDEFINITION");
+    let script_graph_type = format.asset_type("SCRIPTGHRAPH");
+    if render_graph_type.is_none() && script_graph_type.is_none() {
+        return Err(anyhow::anyhow!(
+            "{} packs have no graph assets",
+            format.name()
+        ));
+    }
+    let pack = format.load(src, secret)?;
+    let find = |asset_type: Option<i32>| find_asset_data(&*pack, asset_type?, graph_name);
+    let graph = if let Some(data) = find(render_graph_type) {
+        parse_graph::<RenderGraphDefinition>(&data)?
+    } else if let Some(data) = find(script_graph_type) {
+        parse_graph::<ScriptGraph>(&data)?
+    } else {
+        return Err(anyhow::anyhow!("Graph {} not found", graph_name));
+    };
+
+    let (nodes, edges) = graph.to_graph();
+    let out = match dst.extension().and_then(|e| e.to_str()) {
+        Some("mmd") => to_mermaid(&nodes, &edges),
+        _ => to_dot(graph_name, &nodes, &edges),
+    };
+    fs::write(dst, out)?;
+    Ok(())
+}
+
+/// Exports a navmesh set to a top-down SVG, or to OBJ when the output file has the `.obj`
+/// extension.
+pub fn export_navmesh(
+    src: &Path,
+    dst: &Path,
+    navmesh_name: &str,
+    format: &dyn PackFormat,
+    secret: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let navmesh_type = format
+        .asset_type("NAVMESHSET")
+        .ok_or_else(|| anyhow::anyhow!("{} packs have no navmesh assets", format.name()))?;
+    let pack = format.load(src, secret)?;
+    let data = find_asset_data(&*pack, navmesh_type, navmesh_name)
+        .ok_or_else(|| anyhow::anyhow!("Navmesh {} not found", navmesh_name))?;
+    let (_, set) = NavmeshSet::parse(&data).map_err(|err| anyhow::anyhow!(err.to_string()))?;
+
+    let out = match dst.extension().and_then(|e| e.to_str()) {
+        Some("obj") => navmesh_set_to_obj(&set)?,
+        _ => navmesh_set_to_svg(&set),
+    };
+    fs::write(dst, out)?;
+    Ok(())
+}
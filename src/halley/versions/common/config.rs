@@ -2,6 +2,7 @@ use super::{
     hpk::{Parsable, Writable},
     primitives::{h_bool, h_f32, h_i32, h_i64, h_string, h_u32, wh_bool, wh_string},
 };
+use crate::halley::assets::serialization::{deserialize, serialize, Format};
 use cookie_factory::{
     bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_i64 as w_le_i64, le_u32 as w_le_u32},
     combinator::{cond as wh_cond, slice as wh_slice},
@@ -18,10 +19,24 @@ use nom::{
     IResult,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
-use serde::{Deserialize, Serialize};
-use std::hash::Hash;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, ObjectValidation, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{
+    de::{
+        self, value::UnitDeserializer, DeserializeSeed, EnumAccess, MapAccess, SeqAccess,
+        VariantAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, hash::Hash};
+
+/// Companion file of unpacked configs holding their node positions.
+pub static CONFIG_POSITIONS_FILE: &str = "positions.json";
 
-#[derive(FromPrimitive, Serialize, Deserialize, ToPrimitive)]
+#[derive(FromPrimitive, Serialize, Deserialize, ToPrimitive, JsonSchema)]
 pub enum ConfigNodeType {
     Undefined = 0,
     String = 1,
@@ -42,8 +57,11 @@ pub enum ConfigNodeType {
     Bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-#[serde(tag = "__node_type", content = "__node_value")]
+/// Maps, sequences and plain values are written as themselves. The other node types, which plain
+/// values can't tell apart, use a typed syntax: a YAML tag such as `!int2 [1, 2]`, or in TOML and
+/// JSON a map whose only key is the tag, such as `{ "!int2" = [1, 2] }`. Maps whose first key
+/// starts with `!` are written as `!map`, so that they aren't read back as a typed node.
+#[derive(Debug, PartialEq, Clone)]
 pub enum ConfigNode {
     Undefined,
 
@@ -58,28 +76,401 @@ pub enum ConfigNode {
     Int64(i64),
     EntityId(i64),
 
-    //TODO: set untagged when https://github.com/dtolnay/serde-yaml/issues/361 is fixed
-    #[serde(untagged)]
     Sequence(Vec<ConfigNode>),
-    #[serde(untagged)]
     Map(ConfigNodeMap),
-    #[serde(untagged)]
     Int(i32),
-    #[serde(untagged)]
     Float(f32),
-    #[serde(untagged)]
     String(String),
-    #[serde(untagged)]
     Bool(bool),
 }
 
 pub type ConfigNodeMap = IndexMap<String, ConfigNode>;
 
-#[derive(Debug, Serialize, Deserialize)]
+static TAG_PREFIX: &str = "!";
+/// Keys of the typed syntax written by earlier versions, `{__node_type, __node_value}`.
+static LEGACY_TYPE_KEY: &str = "__node_type";
+static LEGACY_VALUE_KEY: &str = "__node_value";
+/// Value of the typed nodes that carry none.
+static NO_VALUE: [(); 0] = [];
+
+impl Serialize for ConfigNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ConfigNode::Int(v) => serializer.serialize_i32(*v),
+            ConfigNode::Float(v) => serializer.serialize_f32(*v),
+            ConfigNode::String(v) => serializer.serialize_str(v),
+            ConfigNode::Bool(v) => serializer.serialize_bool(*v),
+            ConfigNode::Sequence(v) => v.serialize(serializer),
+            ConfigNode::Map(v) if !starts_with_tag(v) => v.serialize(serializer),
+            ConfigNode::Map(v) => serialize_typed(serializer, "!map", v),
+            ConfigNode::Int2(v) => serialize_typed(serializer, "!int2", v),
+            ConfigNode::Float2(v) => serialize_typed(serializer, "!float2", v),
+            ConfigNode::Bytes(v) => serialize_typed(serializer, "!bytes", v),
+            ConfigNode::DeltaSequence(v) => serialize_typed(serializer, "!deltasequence", v),
+            ConfigNode::DeltaMap(v) => serialize_typed(serializer, "!deltamap", v),
+            ConfigNode::Idx(v) => serialize_typed(serializer, "!idx", v),
+            ConfigNode::Int64(v) => serialize_typed(serializer, "!int64", v),
+            ConfigNode::EntityId(v) => serialize_typed(serializer, "!entityid", v),
+            ConfigNode::Undefined => serialize_typed(serializer, "!undefined", &NO_VALUE),
+            ConfigNode::Noop => serialize_typed(serializer, "!noop", &NO_VALUE),
+            ConfigNode::Del => serialize_typed(serializer, "!del", &NO_VALUE),
+        }
+    }
+}
+
+fn starts_with_tag(map: &ConfigNodeMap) -> bool {
+    map.keys().next().is_some_and(|k| k.starts_with(TAG_PREFIX))
+}
+
+/// Writes a newtype variant named after the tag, which serde_yaml turns into a YAML tag and the
+/// other formats into a single-key map.
+fn serialize_typed<S: Serializer, T: Serialize + ?Sized>(
+    serializer: S,
+    tag: &'static str,
+    value: &T,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_variant("ConfigNode", 0, tag, value)
+}
+
+impl<'de> Deserialize<'de> for ConfigNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ConfigNodeVisitor)
+    }
+}
+
+struct ConfigNodeVisitor;
+
+impl<'de> Visitor<'de> for ConfigNodeVisitor {
+    type Value = ConfigNode;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a config node")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<ConfigNode, E> {
+        Ok(ConfigNode::Bool(v))
+    }
+
+    /// Integers out of the `Int` range are read as `Int64`.
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<ConfigNode, E> {
+        Ok(i32::try_from(v).map_or(ConfigNode::Int64(v), ConfigNode::Int))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<ConfigNode, E> {
+        let v = i64::try_from(v)
+            .map_err(|_| E::custom(format!("{} is out of range for a config node", v)))?;
+        self.visit_i64(v)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<ConfigNode, E> {
+        Ok(ConfigNode::Float(v as f32))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ConfigNode, E> {
+        Ok(ConfigNode::String(v.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<ConfigNode, E> {
+        Ok(ConfigNode::String(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<ConfigNode, E> {
+        Ok(ConfigNode::Undefined)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ConfigNode, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(ConfigNode::Sequence(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ConfigNode, A::Error> {
+        let Some(first) = map.next_key::<String>()? else {
+            return Ok(ConfigNode::Map(ConfigNodeMap::new()));
+        };
+
+        if first.starts_with(TAG_PREFIX) {
+            let node = map.next_value_seed(TypedNode(&first))?;
+            if map.next_key::<de::IgnoredAny>()?.is_some() {
+                return Err(de::Error::custom(format!(
+                    "{} must be the only key of its map",
+                    first
+                )));
+            }
+            return Ok(node);
+        }
+
+        if first == LEGACY_TYPE_KEY {
+            let node_type: String = map.next_value()?;
+            return match map.next_key::<String>()? {
+                Some(key) if key == LEGACY_VALUE_KEY => map.next_value_seed(TypedNode(&node_type)),
+                Some(key) => Err(de::Error::custom(format!("unexpected key {}", key))),
+                None => TypedNode(&node_type).deserialize(UnitDeserializer::new()),
+            };
+        }
+
+        let mut nodes = ConfigNodeMap::new();
+        nodes.insert(first, map.next_value()?);
+        while let Some((key, value)) = map.next_entry()? {
+            nodes.insert(key, value);
+        }
+        Ok(ConfigNode::Map(nodes))
+    }
+
+    /// YAML tags.
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<ConfigNode, A::Error> {
+        let (tag, value) = data.variant::<String>()?;
+        value.newtype_variant_seed(TypedNode(&tag))
+    }
+}
+
+/// Value of a typed node, given its tag. Tags are case insensitive and the `!` is optional, and
+/// the plain types can be tagged too, as in `!float 1`.
+struct TypedNode<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for TypedNode<'_> {
+    type Value = ConfigNode;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<ConfigNode, D::Error> {
+        let d = deserializer;
+        let node = match self
+            .0
+            .trim_start_matches(TAG_PREFIX)
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "undefined" => de::IgnoredAny::deserialize(d).map(|_| ConfigNode::Undefined)?,
+            "noop" => de::IgnoredAny::deserialize(d).map(|_| ConfigNode::Noop)?,
+            "del" => de::IgnoredAny::deserialize(d).map(|_| ConfigNode::Del)?,
+            "int2" => ConfigNode::Int2(Deserialize::deserialize(d)?),
+            "float2" => ConfigNode::Float2(Deserialize::deserialize(d)?),
+            "bytes" => ConfigNode::Bytes(Deserialize::deserialize(d)?),
+            "deltasequence" => ConfigNode::DeltaSequence(Deserialize::deserialize(d)?),
+            "deltamap" => ConfigNode::DeltaMap(Deserialize::deserialize(d)?),
+            "idx" => ConfigNode::Idx(Deserialize::deserialize(d)?),
+            "int64" => ConfigNode::Int64(Deserialize::deserialize(d)?),
+            "entityid" => ConfigNode::EntityId(Deserialize::deserialize(d)?),
+            "sequence" => ConfigNode::Sequence(Deserialize::deserialize(d)?),
+            "map" => ConfigNode::Map(Deserialize::deserialize(d)?),
+            "int" => ConfigNode::Int(Deserialize::deserialize(d)?),
+            "float" => ConfigNode::Float(Deserialize::deserialize(d)?),
+            "string" => ConfigNode::String(Deserialize::deserialize(d)?),
+            "bool" => ConfigNode::Bool(Deserialize::deserialize(d)?),
+            _ => {
+                return Err(de::Error::custom(format!(
+                    "unknown config node type {}",
+                    self.0
+                )))
+            }
+        };
+        Ok(node)
+    }
+}
+
+/// Written by hand to match the typed syntax: plain values, sequences and maps, or a map holding
+/// a single tag.
+impl JsonSchema for ConfigNode {
+    fn schema_name() -> String {
+        "ConfigNode".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let typed = [
+            ("!undefined", gen.subschema_for::<[(); 0]>()),
+            ("!int2", gen.subschema_for::<(i32, i32)>()),
+            ("!float2", gen.subschema_for::<(f32, f32)>()),
+            ("!bytes", gen.subschema_for::<Vec<u8>>()),
+            (
+                "!deltasequence",
+                gen.subschema_for::<(Vec<ConfigNode>, i32)>(),
+            ),
+            ("!deltamap", gen.subschema_for::<(ConfigNodeMap, i32)>()),
+            ("!noop", gen.subschema_for::<[(); 0]>()),
+            ("!idx", gen.subschema_for::<(i32, i32)>()),
+            ("!del", gen.subschema_for::<[(); 0]>()),
+            ("!int64", gen.subschema_for::<i64>()),
+            ("!entityid", gen.subschema_for::<i64>()),
+            ("!map", gen.subschema_for::<ConfigNodeMap>()),
+        ];
+        let mut any_of: Vec<Schema> = typed
+            .into_iter()
+            .map(|(tag, value)| typed_node_schema(tag, value))
+            .collect();
+        any_of.extend([
+            gen.subschema_for::<Vec<ConfigNode>>(),
+            gen.subschema_for::<ConfigNodeMap>(),
+            gen.subschema_for::<i32>(),
+            gen.subschema_for::<f32>(),
+            gen.subschema_for::<String>(),
+            gen.subschema_for::<bool>(),
+        ]);
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(any_of),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+fn typed_node_schema(tag: &str, value: Schema) -> Schema {
+    let mut object = ObjectValidation::default();
+    object.required.insert(tag.to_string());
+    object.properties.insert(tag.to_string(), value);
+    object.additional_properties = Some(Box::new(Schema::Bool(false)));
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(object)),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ConfigFile {
     pub v: i32,
     pub store_file_position: bool,
     pub root: ConfigNode,
+    /// Source positions of `root` and its descendants, read when `store_file_position` is set.
+    /// Positions missing here are synthesized on write.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positions: Option<ConfigNodePosition>,
+}
+
+/// Line and column of a config node in the source file it was imported from, which the engine
+/// uses in its error messages. The positions of map values are kept by key, so that editing one
+/// entry doesn't move the others, and those of sequence items by index.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+pub struct ConfigNodePosition {
+    pub line: u32,
+    pub column: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ConfigNodePosition>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub entries: IndexMap<String, ConfigNodePosition>,
+}
+
+impl ConfigNodePosition {
+    /// Position of `node` with the positions of its children, given in the order of
+    /// `ConfigNode::children`.
+    pub fn new(node: &ConfigNode, line: u32, column: u32, children: Vec<Self>) -> Self {
+        match node {
+            ConfigNode::Map(map) | ConfigNode::DeltaMap((map, _)) => ConfigNodePosition {
+                line,
+                column,
+                children: vec![],
+                entries: map.keys().cloned().zip(children).collect(),
+            },
+            _ => ConfigNodePosition {
+                line,
+                column,
+                children,
+                entries: IndexMap::new(),
+            },
+        }
+    }
+
+    /// Position of the `n`th child of `node`, which this is the position of.
+    pub fn child(&self, node: &ConfigNode, n: usize) -> Option<&Self> {
+        match node {
+            ConfigNode::Map(map) | ConfigNode::DeltaMap((map, _)) => {
+                self.entries.get(map.get_index(n)?.0)
+            }
+            _ => self.children.get(n),
+        }
+    }
+
+    /// Positions for `node` and all its descendants, taken from `existing` where it has them.
+    /// Missing positions are synthesized as if the node was written one entry per line, indented
+    /// by two columns per level, after the last line of `existing`.
+    pub fn complete(node: &ConfigNode, existing: Option<&ConfigNodePosition>) -> Self {
+        let mut next_line = existing.map_or(0, |p| p.last_line() + 1);
+        complete_position(node, existing, 0, &mut next_line)
+    }
+
+    /// Last line of this node and its descendants.
+    fn last_line(&self) -> u32 {
+        self.children
+            .iter()
+            .chain(self.entries.values())
+            .map(|p| p.last_line())
+            .fold(self.line, u32::max)
+    }
+}
+
+fn complete_position(
+    node: &ConfigNode,
+    existing: Option<&ConfigNodePosition>,
+    depth: u32,
+    next_line: &mut u32,
+) -> ConfigNodePosition {
+    let (line, column) = match existing {
+        Some(p) => (p.line, p.column),
+        None => {
+            *next_line += 1;
+            (*next_line - 1, depth * 2)
+        }
+    };
+    let children = node
+        .children()
+        .into_iter()
+        .enumerate()
+        .map(|(n, child)| {
+            let existing = existing.and_then(|p| p.child(node, n));
+            complete_position(child, existing, depth + 1, next_line)
+        })
+        .collect();
+    ConfigNodePosition::new(node, line, column, children)
+}
+
+impl ConfigNode {
+    /// Map values or sequence items of this node, in order.
+    pub fn children(&self) -> Vec<&ConfigNode> {
+        match self {
+            ConfigNode::Map(map) | ConfigNode::DeltaMap((map, _)) => map.values().collect(),
+            ConfigNode::Sequence(seq) | ConfigNode::DeltaSequence((seq, _)) => seq.iter().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Companion files written next to an unpacked config, holding its node positions.
+    pub fn get_position_files(&self) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        match &self.positions {
+            Some(positions) => Ok(vec![(
+                CONFIG_POSITIONS_FILE.to_string(),
+                serialize(positions, Some(Format::Json5))?.into_bytes(),
+            )]),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Rebuilds a config from its unpacked root, restoring the node positions from the
+    /// companion file read by `read` when it exists.
+    pub fn from_unpacked(
+        v: i32,
+        root: ConfigNode,
+        read: impl Fn(&str) -> Result<Vec<u8>, anyhow::Error>,
+    ) -> Result<Self, anyhow::Error> {
+        let positions = match read(CONFIG_POSITIONS_FILE) {
+            Ok(data) => Some(deserialize(
+                std::str::from_utf8(&data)?,
+                Some(Format::Json5),
+            )?),
+            Err(_) => None,
+        };
+        Ok(ConfigFile {
+            v,
+            store_file_position: true,
+            root,
+            positions,
+        })
+    }
 }
 
 impl Parsable for ConfigFile {
@@ -100,10 +491,12 @@ pub fn h_config_file(i: &[u8]) -> IResult<&[u8], ConfigFile> {
 
         let store_file_position = store_file_position.unwrap_or(v == 2);
 
-        let (i, root) = (if store_file_position {
-            h_confignode_with_position(i)
+        let (i, (root, positions)) = (if store_file_position {
+            map(h_confignode_with_position, |(root, positions)| {
+                (root, Some(positions))
+            })(i)
         } else {
-            h_confignode(i)
+            map(h_confignode, |root| (root, None))(i)
         })
         .unwrap();
 
@@ -113,101 +506,151 @@ pub fn h_config_file(i: &[u8]) -> IResult<&[u8], ConfigFile> {
                 v,
                 store_file_position,
                 root,
+                positions,
             },
         )
     })
 }
 
 pub fn h_confignode(i: &[u8]) -> IResult<&[u8], ConfigNode> {
-    h_confignode_layer(h_confignode, i)
+    fn h_confignode_deep(i: &[u8]) -> IResult<&[u8], (ConfigNode, ())> {
+        map(h_confignode, |node| (node, ()))(i)
+    }
+    map(h_confignode_layer(h_confignode_deep), |(node, _)| node)(i)
 }
 
-pub fn h_confignode_with_position(i: &[u8]) -> IResult<&[u8], ConfigNode> {
-    let (i, confignode) = h_confignode_layer(h_confignode_with_position, i)?;
-    tuple((le_u32, le_u32))(i).map(|(i, (_line, _column))| (i, confignode))
+pub fn h_confignode_with_position(i: &[u8]) -> IResult<&[u8], (ConfigNode, ConfigNodePosition)> {
+    let (i, (confignode, children)) = h_confignode_layer(h_confignode_with_position)(i)?;
+    tuple((le_u32, le_u32))(i).map(|(i, (line, column))| {
+        let position = ConfigNodePosition::new(&confignode, line, column, children);
+        (i, (confignode, position))
+    })
 }
 
-pub type ConfigNodeParser = fn(&[u8]) -> IResult<&[u8], ConfigNode>;
-pub type ConfigNodeWriter<'a> = dyn Fn(&'a ConfigNode) -> Box<dyn SerializeFn<Vec<u8>> + 'a>;
-
-fn h_confignode_layer(h_confignode_deep: ConfigNodeParser, i: &[u8]) -> IResult<&[u8], ConfigNode> {
-    let h_cn_map_deep = map(
-        length_count(le_u32, tuple((h_string, h_confignode_deep))),
-        vec_to_map,
-    );
-    le_u32(i).map(|(i, confignode_type)| {
-        match num::FromPrimitive::from_u32(confignode_type) {
-            Some(ConfigNodeType::Noop) => (i, ConfigNode::Noop),
-            Some(ConfigNodeType::Undefined) => (i, ConfigNode::Undefined),
-            Some(ConfigNodeType::Del) => (i, ConfigNode::Del),
-            Some(ConfigNodeType::Bool) => map(h_bool, ConfigNode::Bool)(i).unwrap(),
-            Some(ConfigNodeType::String) => map(h_string, ConfigNode::String)(i).unwrap(),
-            Some(ConfigNodeType::Map) => map(h_cn_map_deep, ConfigNode::Map)(i).unwrap(),
-            Some(ConfigNodeType::DeltaMap) => {
-                map(tuple((h_cn_map_deep, le_i32)), ConfigNode::DeltaMap)(i).unwrap()
-            }
-            Some(ConfigNodeType::Int64) => map(h_i64, ConfigNode::Int64)(i).unwrap(),
-            Some(ConfigNodeType::EntityId) => map(h_i64, ConfigNode::EntityId)(i).unwrap(),
-            Some(ConfigNodeType::Int) => map(h_i32, ConfigNode::Int)(i).unwrap(),
-            Some(ConfigNodeType::Float) => map(h_f32, ConfigNode::Float)(i).unwrap(),
-            Some(ConfigNodeType::Sequence) => map(
-                length_count(le_u32, h_confignode_deep),
-                ConfigNode::Sequence,
-            )(i)
-            .unwrap(),
-            Some(ConfigNodeType::DeltaSequence) => map(
-                tuple((length_count(le_u32, h_confignode_deep), le_i32)),
-                ConfigNode::DeltaSequence,
-            )(i)
-            .unwrap(),
-            Some(ConfigNodeType::Int2) => map(tuple((h_i32, h_i32)), ConfigNode::Int2)(i).unwrap(),
-            Some(ConfigNodeType::Idx) => map(tuple((h_i32, h_i32)), ConfigNode::Idx)(i).unwrap(),
-            Some(ConfigNodeType::Float2) => {
-                map(tuple((h_f32, h_f32)), ConfigNode::Float2)(i).unwrap()
-            }
-            Some(ConfigNodeType::Bytes) => {
-                map(length_data(h_u32), |b: &[u8]| ConfigNode::Bytes(b.to_vec()))(i).unwrap()
+/// Parses a child node together with whatever is stored alongside it, such as its position.
+pub type ConfigNodeParser<P> = fn(&[u8]) -> IResult<&[u8], (ConfigNode, P)>;
+
+fn h_confignode_layer<P>(
+    h_confignode_deep: ConfigNodeParser<P>,
+) -> impl Fn(&[u8]) -> IResult<&[u8], (ConfigNode, Vec<P>)> {
+    move |i| {
+        let h_cn_map_deep = map(
+            length_count(le_u32, tuple((h_string, h_confignode_deep))),
+            vec_to_map,
+        );
+        let h_cn_seq_deep = map(length_count(le_u32, h_confignode_deep), |v| {
+            v.into_iter().unzip::<_, _, Vec<_>, Vec<_>>()
+        });
+        let leaf = |(i, node)| (i, (node, vec![]));
+        le_u32(i).map(|(i, confignode_type)| {
+            match num::FromPrimitive::from_u32(confignode_type) {
+                Some(ConfigNodeType::Noop) => (i, (ConfigNode::Noop, vec![])),
+                Some(ConfigNodeType::Undefined) => (i, (ConfigNode::Undefined, vec![])),
+                Some(ConfigNodeType::Del) => (i, (ConfigNode::Del, vec![])),
+                Some(ConfigNodeType::Bool) => leaf(map(h_bool, ConfigNode::Bool)(i).unwrap()),
+                Some(ConfigNodeType::String) => leaf(map(h_string, ConfigNode::String)(i).unwrap()),
+                Some(ConfigNodeType::Map) => {
+                    map(h_cn_map_deep, |(m, p)| (ConfigNode::Map(m), p))(i).unwrap()
+                }
+                Some(ConfigNodeType::DeltaMap) => {
+                    map(tuple((h_cn_map_deep, le_i32)), |((m, p), d)| {
+                        (ConfigNode::DeltaMap((m, d)), p)
+                    })(i)
+                    .unwrap()
+                }
+                Some(ConfigNodeType::Int64) => leaf(map(h_i64, ConfigNode::Int64)(i).unwrap()),
+                Some(ConfigNodeType::EntityId) => {
+                    leaf(map(h_i64, ConfigNode::EntityId)(i).unwrap())
+                }
+                Some(ConfigNodeType::Int) => leaf(map(h_i32, ConfigNode::Int)(i).unwrap()),
+                Some(ConfigNodeType::Float) => leaf(map(h_f32, ConfigNode::Float)(i).unwrap()),
+                Some(ConfigNodeType::Sequence) => {
+                    map(h_cn_seq_deep, |(s, p)| (ConfigNode::Sequence(s), p))(i).unwrap()
+                }
+                Some(ConfigNodeType::DeltaSequence) => {
+                    map(tuple((h_cn_seq_deep, le_i32)), |((s, p), d)| {
+                        (ConfigNode::DeltaSequence((s, d)), p)
+                    })(i)
+                    .unwrap()
+                }
+                Some(ConfigNodeType::Int2) => {
+                    leaf(map(tuple((h_i32, h_i32)), ConfigNode::Int2)(i).unwrap())
+                }
+                Some(ConfigNodeType::Idx) => {
+                    leaf(map(tuple((h_i32, h_i32)), ConfigNode::Idx)(i).unwrap())
+                }
+                Some(ConfigNodeType::Float2) => {
+                    leaf(map(tuple((h_f32, h_f32)), ConfigNode::Float2)(i).unwrap())
+                }
+                Some(ConfigNodeType::Bytes) => leaf(
+                    map(length_data(h_u32), |b: &[u8]| ConfigNode::Bytes(b.to_vec()))(i).unwrap(),
+                ),
+                //Some(_) => (i, ConfigNode::Undefined), // throw err
+                None => (i, (ConfigNode::Undefined, vec![])), // throw err
             }
-            //Some(_) => (i, ConfigNode::Undefined), // throw err
-            None => (i, ConfigNode::Undefined), // throw err
-        }
-    })
+        })
+    }
 }
-fn vec_to_map<K: Eq + Hash, V>(v: Vec<(K, V)>) -> IndexMap<K, V> {
-    v.into_iter()
-        .map(|(k, v)| (k, v))
-        .collect::<IndexMap<K, V>>()
+
+/// Collects map entries with what is stored alongside each value, in map order. A repeated key
+/// keeps the place of its first entry and the value of its last, as `IndexMap::insert` does, and
+/// so does what is stored alongside it.
+fn vec_to_map<K: Eq + Hash, V, P>(v: Vec<(K, (V, P))>) -> (IndexMap<K, V>, Vec<P>) {
+    let mut map = IndexMap::with_capacity(v.len());
+    let mut extra = Vec::with_capacity(v.len());
+    for (k, (v, p)) in v {
+        match map.insert_full(k, v) {
+            (n, Some(_)) => extra[n] = p,
+            (_, None) => extra.push(p),
+        }
+    }
+    (map, extra)
 }
 
 pub fn wh_config_file<'a>(file: &'a ConfigFile) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
-    let writer = wh_tuple((
+    let positions = file
+        .store_file_position
+        .then(|| ConfigNodePosition::complete(&file.root, file.positions.as_ref()));
+    let header = wh_tuple((
         w_le_i32(file.v),
         wh_cond(file.v > 2, wh_bool(file.store_file_position)),
-        wh_cond(
-            file.store_file_position,
-            wh_confignode_with_position(&file.root),
-        ),
-        wh_cond(!file.store_file_position, wh_confignode(&file.root)),
     ));
-    Box::new(writer)
+    Box::new(move |w| {
+        let w = header(w)?;
+        match &positions {
+            Some(positions) => wh_confignode_with_position(&file.root, positions)(w),
+            None => wh_confignode(&file.root)(w),
+        }
+    })
 }
 
 pub fn wh_confignode<'a>(node: &'a ConfigNode) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
-    wh_confignode_layer(&wh_confignode, node)
+    wh_confignode_layer(|_, child| wh_confignode(child), node)
 }
 
-pub fn wh_confignode_with_position<'a>(node: &'a ConfigNode) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+/// Writes `node` with the positions of `position`, which must have an entry for every child.
+/// Use `ConfigNodePosition::complete` to fill in missing ones.
+pub fn wh_confignode_with_position<'a>(
+    node: &'a ConfigNode,
+    position: &'a ConfigNodePosition,
+) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
     Box::new(wh_tuple((
-        wh_confignode_layer(&wh_confignode_with_position, node),
-        w_le_u32(0),
-        w_le_u32(0),
+        wh_confignode_layer(
+            |n, child| wh_confignode_with_position(child, position.child(node, n).unwrap()),
+            node,
+        ),
+        w_le_u32(position.line),
+        w_le_u32(position.column),
     )))
 }
 
-fn wh_confignode_layer<'a>(
-    wh_confignode_deep: &'a ConfigNodeWriter<'a>,
+fn wh_confignode_layer<'a, W>(
+    wh_confignode_deep: W,
     node: &'a ConfigNode,
-) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+) -> Box<dyn SerializeFn<Vec<u8>> + 'a>
+where
+    W: Fn(usize, &'a ConfigNode) -> Box<dyn SerializeFn<Vec<u8>> + 'a> + Copy + 'a,
+{
     let slice0 = Box::new(wh_slice::<_, Vec<u8>>(vec![]));
 
     let wh_cn_map_deep = |map: &'a ConfigNodeMap| -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
@@ -215,7 +658,8 @@ fn wh_confignode_layer<'a>(
             w_le_u32(map.len() as u32),
             wh_all(
                 map.iter()
-                    .map(|(k, v)| wh_tuple((wh_string(k), wh_confignode_deep(v)))),
+                    .enumerate()
+                    .map(move |(n, (k, v))| wh_tuple((wh_string(k), wh_confignode_deep(n, v)))),
             ),
         ));
         Box::new(writer)
@@ -233,7 +677,11 @@ fn wh_confignode_layer<'a>(
             ConfigNodeType::Sequence,
             Box::new(wh_tuple((
                 w_le_u32(seq.len() as u32),
-                wh_all(seq.iter().map(wh_confignode_deep)),
+                wh_all(
+                    seq.iter()
+                        .enumerate()
+                        .map(move |(n, v)| wh_confignode_deep(n, v)),
+                ),
             ))),
         ),
         ConfigNode::Float(f) => (ConfigNodeType::Float, Box::new(w_le_f32(*f))),
@@ -253,7 +701,11 @@ fn wh_confignode_layer<'a>(
             ConfigNodeType::DeltaSequence,
             Box::new(wh_tuple((
                 w_le_u32(seq.len() as u32),
-                wh_all(seq.iter().map(wh_confignode_deep)),
+                wh_all(
+                    seq.iter()
+                        .enumerate()
+                        .map(move |(n, v)| wh_confignode_deep(n, v)),
+                ),
                 w_le_i32(*i),
             ))),
         ),
@@ -274,11 +726,12 @@ fn wh_confignode_layer<'a>(
 
 #[cfg(test)]
 mod tests {
-    use crate::halley::assets::serialization::{deserialize, serialize};
+    use crate::halley::assets::serialization::{deserialize, serialize, Format};
     use indexmap::indexmap;
     use serde::de::DeserializeOwned;
 
     use super::*;
+    use crate::halley::versions::common::hpk::write_to_vec;
 
     fn serialize_and_deserialize<T: Serialize + DeserializeOwned + std::fmt::Debug>(t: &T) -> T {
         let str = serialize(t, None).unwrap();
@@ -373,6 +826,168 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_typed_nodes_round_trip_in_all_formats() {
+        let root = ConfigNode::Map(indexmap! {
+            format!("undefined") => ConfigNode::Undefined,
+            format!("string") => ConfigNode::String("1".to_string()),
+            format!("tag_like") => ConfigNode::String("!int2".to_string()),
+            format!("sequence") => ConfigNode::Sequence(vec![ConfigNode::Int(1)]),
+            format!("map") => ConfigNode::Map(indexmap! {
+                format!("!int2") => ConfigNode::Int(1),
+                format!("b") => ConfigNode::Int(2),
+            }),
+            format!("empty_map") => ConfigNode::Map(ConfigNodeMap::new()),
+            format!("int") => ConfigNode::Int(-3),
+            format!("float") => ConfigNode::Float(1.0),
+            format!("float_fraction") => ConfigNode::Float(-2.5),
+            format!("int2") => ConfigNode::Int2((1, -2)),
+            format!("float2") => ConfigNode::Float2((3.0, 0.5)),
+            format!("bytes") => ConfigNode::Bytes(vec![0, 127, 255]),
+            format!("delta_sequence") => ConfigNode::DeltaSequence((vec![ConfigNode::Noop], 2)),
+            format!("delta_map") => ConfigNode::DeltaMap((
+                indexmap! { format!("a") => ConfigNode::Del },
+                1,
+            )),
+            format!("noop") => ConfigNode::Noop,
+            format!("idx") => ConfigNode::Idx((4, 5)),
+            format!("del") => ConfigNode::Del,
+            format!("int64") => ConfigNode::Int64(7),
+            format!("entity_id") => ConfigNode::EntityId(i64::MAX),
+            format!("bool") => ConfigNode::Bool(false),
+        });
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&root, Some(format)).unwrap();
+            let back: ConfigNode = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(back, root, "{:?}:\n{}", format, str);
+        }
+
+        let yaml = serialize(&root, Some(Format::Yaml)).unwrap();
+        assert!(yaml.contains("int2: !int2\n"));
+        assert!(yaml.contains("float: 1.0\n"));
+    }
+
+    #[test]
+    fn test_typed_node_syntax() {
+        let yaml = "a: !int2 [1, 2]\nb: !float 1\nc: !noop\n";
+        let toml = "a = { \"!int2\" = [1, 2] }\nb = { \"!float\" = 1 }\nc = { \"!noop\" = [] }";
+        let legacy = r#"{"a": {"__node_type": "Int2", "__node_value": [1, 2]},
+            "b": {"__node_type": "Float", "__node_value": 1}, "c": {"__node_type": "Noop"}}"#;
+        let expected = ConfigNode::Map(indexmap! {
+            format!("a") => ConfigNode::Int2((1, 2)),
+            format!("b") => ConfigNode::Float(1.0),
+            format!("c") => ConfigNode::Noop,
+        });
+        for (str, format) in [
+            (yaml, Format::Yaml),
+            (toml, Format::Toml5),
+            (legacy, Format::Json5),
+        ] {
+            let node: ConfigNode = deserialize(str, Some(format)).unwrap();
+            assert_eq!(node, expected, "{:?}", format);
+        }
+    }
+
+    fn position(line: u32, column: u32) -> ConfigNodePosition {
+        ConfigNodePosition {
+            line,
+            column,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_positions_round_trip() {
+        let root = ConfigNode::Map(indexmap! {
+            format!("a") => ConfigNode::Int(1),
+            format!("b") => ConfigNode::Sequence(vec![ConfigNode::Int(2), ConfigNode::Int(3)]),
+        });
+        let positions = ConfigNodePosition {
+            entries: indexmap! {
+                format!("a") => position(1, 3),
+                format!("b") => position(4, 2),
+            },
+            ..position(0, 0)
+        };
+        let file = ConfigFile {
+            v: 3,
+            store_file_position: true,
+            root,
+            positions: Some(positions.clone()),
+        };
+        let data = write_to_vec(&file).unwrap();
+        let (_, parsed) = ConfigFile::parse(&data).unwrap();
+        assert_eq!(parsed.root, file.root);
+
+        let complete = parsed.positions.unwrap();
+        assert_eq!(complete.entries["a"], positions.entries["a"]);
+        // Sequence items had no position and are placed after the last known line.
+        assert_eq!(
+            complete.entries["b"].children,
+            vec![position(5, 4), position(6, 4)]
+        );
+
+        let reparsed = ConfigFile {
+            positions: Some(complete.clone()),
+            ..parsed
+        };
+        assert_eq!(write_to_vec(&reparsed).unwrap(), data);
+
+        // Map entries keep their positions when a sibling is added, removed or moved.
+        let edited = ConfigNode::Map(indexmap! {
+            format!("new") => ConfigNode::Int(0),
+            format!("b") => ConfigNode::Sequence(vec![ConfigNode::Int(3)]),
+        });
+        let edited = ConfigNodePosition::complete(&edited, Some(&complete));
+        assert_eq!(edited.entries["new"], position(7, 2));
+        assert_eq!(
+            edited.entries["b"],
+            ConfigNodePosition {
+                children: vec![position(5, 4)],
+                ..position(4, 2)
+            }
+        );
+    }
+
+    #[test]
+    fn test_complete_positions_after_last_line() {
+        // The existing positions of "b" come after the missing one of "a".
+        let root = ConfigNode::Map(indexmap! {
+            format!("a") => ConfigNode::Sequence(vec![ConfigNode::Int(1)]),
+            format!("b") => ConfigNode::Sequence(vec![ConfigNode::Int(2)]),
+        });
+        let existing = ConfigNodePosition {
+            entries: indexmap! {
+                format!("b") => ConfigNodePosition {
+                    children: vec![position(9, 4)],
+                    ..position(8, 2)
+                },
+            },
+            ..position(0, 0)
+        };
+        let complete = ConfigNodePosition::complete(&root, Some(&existing));
+        assert_eq!(
+            complete.entries["a"],
+            ConfigNodePosition {
+                children: vec![position(11, 4)],
+                ..position(10, 2)
+            }
+        );
+        assert_eq!(complete.entries["b"], existing.entries["b"]);
+    }
+
+    #[test]
+    fn test_vec_to_map_keeps_last_duplicate() {
+        let (map, positions) = vec_to_map(vec![
+            ("a", (1, position(1, 0))),
+            ("b", (2, position(2, 0))),
+            ("a", (3, position(3, 0))),
+        ]);
+        assert_eq!(map, indexmap! { "a" => 3, "b" => 2 });
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![&"a", &"b"]);
+        assert_eq!(positions, vec![position(3, 0), position(2, 0)]);
+    }
+
     fn wrap_node(node: ConfigNode) -> ConfigNode {
         ConfigNode::Map(indexmap! {
             format!("root") => node,
@@ -1,7 +1,13 @@
-use super::hpk_parse::parse_hpk;
+use super::{
+    config::{ConfigNode, ConfigNodeMap},
+    hpk_parse::parse_hpk,
+};
 use crate::halley::assets::{
     compression,
-    serialization::{deserialize, get_serialization_ext, serialize, Format},
+    serialization::{
+        deserialize, get_serialization_ext, get_serialization_ext_from_path, serialize, Format,
+    },
+    unpack::UnpackOptions,
     utils::pathify,
     utils::unpathify,
 };
@@ -12,6 +18,7 @@ use derive_new::new;
 use nom::IResult;
 use num_traits::FromPrimitive;
 use path_slash::PathExt as _;
+use schemars::schema::RootSchema;
 use serde::{de::DeserializeOwned, Serialize};
 use std::{fmt::Debug, path::Path};
 use thiserror::Error;
@@ -30,17 +37,24 @@ pub trait HalleyPack: Writable + Debug {
         Ok(Box::new(pack))
     }
     fn sections(&self) -> &Vec<Box<dyn HpkSection>>;
+    fn sections_mut(&mut self) -> &mut Vec<Box<dyn HpkSection>>;
     fn add_section(&mut self, section: Box<dyn HpkSection>);
     fn get_asset_data(&self, asset: &dyn HpkAsset) -> Vec<u8>;
     fn data(&self) -> &[u8];
+    fn set_data(&mut self, data: Vec<u8>);
     fn add_data(&mut self, data: Vec<u8>, compression: Option<String>) -> (usize, usize);
+    fn iv(&self) -> [u8; 16];
+    fn set_iv(&mut self, iv: [u8; 16]);
+    /// Asset table as compressed in the file the pack was read from. It is written again as long
+    /// as the table is unchanged, since recompressing it may not give the same bytes.
+    fn compressed_asset_db(&self) -> Option<&[u8]>;
+    fn set_compressed_asset_db(&mut self, data: Option<Vec<u8>>);
     // fn get_boxed(&self) -> Box<Self>;
 }
 
 #[derive(Derivative, new)]
 #[derivative(Debug, Default)]
 pub struct HalleyPackData {
-    //iv: [u8; 16],
     //asset_db_start_pos: u64,
     #[derivative(Default(value = "vec![]"))]
     asset_db: Vec<Box<dyn HpkSection>>,
@@ -48,6 +62,13 @@ pub struct HalleyPackData {
     #[derivative(Debug = "ignore")]
     #[derivative(Default(value = "vec![]"))]
     data: Vec<u8>,
+
+    #[new(default)]
+    iv: [u8; 16],
+
+    #[derivative(Debug = "ignore")]
+    #[new(default)]
+    asset_db_compressed: Option<Vec<u8>>,
 }
 
 impl HalleyPack for HalleyPackData {
@@ -55,6 +76,10 @@ impl HalleyPack for HalleyPackData {
         &self.asset_db
     }
 
+    fn sections_mut(&mut self) -> &mut Vec<Box<dyn HpkSection>> {
+        &mut self.asset_db
+    }
+
     fn add_section(&mut self, section: Box<dyn HpkSection>) {
         self.asset_db.push(section);
     }
@@ -72,6 +97,10 @@ impl HalleyPack for HalleyPackData {
         &self.data
     }
 
+    fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
     fn add_data(&mut self, data: Vec<u8>, compression: Option<String>) -> (usize, usize) {
         let data = match compression {
             Some(compression) => compression::compress(&data, &compression),
@@ -81,17 +110,43 @@ impl HalleyPack for HalleyPackData {
         self.data.extend_from_slice(&data);
         (pos, data.len())
     }
+
+    fn iv(&self) -> [u8; 16] {
+        self.iv
+    }
+
+    fn set_iv(&mut self, iv: [u8; 16]) {
+        self.iv = iv;
+    }
+
+    fn compressed_asset_db(&self) -> Option<&[u8]> {
+        self.asset_db_compressed.as_deref()
+    }
+
+    fn set_compressed_asset_db(&mut self, data: Option<Vec<u8>>) {
+        self.asset_db_compressed = data;
+    }
 }
 
 pub trait HpkSection
 where
     Self: HpkSectionUnpackable + Writable + Debug,
 {
+    /// Empty section of `asset_type`. Use `PackFormat::new_section` for one numbered like the
+    /// version numbers its sections.
     fn new(asset_type: i32) -> Result<Self, anyhow::Error>
     where
         Self: Sized;
     fn asset_type(&self) -> i32;
+    /// Name of the asset type, such as `SPRITESHEET`.
+    fn asset_type_name(&self) -> String;
+    /// Index stored with the section, for versions that store one.
+    fn section_index(&self) -> Option<i32> {
+        None
+    }
+    fn set_section_index(&mut self, _section_index: i32) {}
     fn assets(&self) -> Vec<Box<&dyn HpkAsset>>;
+    fn assets_mut(&mut self) -> Vec<&mut dyn HpkAsset>;
     fn add_asset(
         &mut self,
         pack: &mut dyn HalleyPack,
@@ -105,9 +160,15 @@ pub trait HpkAsset: Writable + Debug {
     fn pos(&self) -> usize;
     fn size(&self) -> usize;
     fn set_pos_size(&mut self, pos: usize, size: usize);
-    fn serialize_properties(&self, filaname: &Path) -> Result<(), anyhow::Error>;
+    fn serialize_properties(
+        &self,
+        filename: &Path,
+        derived: &ConfigNodeMap,
+        format: Option<Format>,
+    ) -> Result<(), anyhow::Error>;
     fn get_asset_compression(&self) -> Option<String>;
     fn get_compression(&self) -> Option<String>;
+    fn get_properties(&self) -> ConfigNode;
 }
 
 pub trait HpkSectionUnpackable {
@@ -115,14 +176,53 @@ pub trait HpkSectionUnpackable {
         ""
     }
 
-    fn modify_data_on_unpack(&self, i: &[u8]) -> Result<(Vec<u8>, &str), anyhow::Error> {
+    /// Converts the asset data to the file written on unpack, serialized in `format` when the
+    /// asset type has a text form and `format` is given.
+    fn modify_data_on_unpack(
+        &self,
+        i: &[u8],
+        _format: Option<Format>,
+    ) -> Result<(Vec<u8>, &str), anyhow::Error> {
         Ok((i.into(), ""))
     }
 
-    fn modify_data_on_repack(&self, i: &[u8], _ext: &str) -> Result<Vec<u8>, anyhow::Error> {
+    /// JSON Schema of the document `modify_data_on_unpack` writes, for asset types with a text
+    /// form.
+    fn get_schema(&self) -> Option<RootSchema> {
+        None
+    }
+
+    /// JSON Schema of the asset property files.
+    fn get_properties_schema(&self) -> RootSchema;
+
+    /// Extra files written to the asset's companion folder on unpack, as `(relative path, data)`.
+    fn get_companion_files(
+        &self,
+        _i: &[u8],
+        _options: &UnpackOptions,
+    ) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        Ok(vec![])
+    }
+
+    fn modify_data_on_repack(
+        &self,
+        i: &[u8],
+        _ext: &str,
+        _companion_dir: &Path,
+    ) -> Result<Vec<u8>, anyhow::Error> {
         Ok(i.into())
     }
 
+    /// Properties computed from the asset data, shown in the property file on unpack. They are
+    /// never packed.
+    fn get_derived_properties(&self, _i: &[u8]) -> ConfigNodeMap {
+        ConfigNodeMap::new()
+    }
+
+    fn get_data_ext_from_path(&self, path: &Path) -> &'static str {
+        get_serialization_ext_from_path(path)
+    }
+
     fn get_asset_filename(&self, asset: &dyn HpkAsset, serialization_ext: &str) -> String {
         let name = asset.name();
         let u_ext = self.get_unknown_file_type_ending();
@@ -151,8 +251,8 @@ pub trait Writable {
 pub fn unpack_transform<T: Parsable + Serialize, TT: Serialize>(
     i: &[u8],
     transform: Option<fn(T) -> TT>,
+    format: Option<Format>,
 ) -> Result<(Vec<u8>, &'static str), anyhow::Error> {
-    let format = None;
     let (_, t) = T::parse(i).map_err(|err| anyhow!(err.to_string()))?;
     let data = match transform {
         Some(transform) => serialize(&transform(t), format),
@@ -182,11 +282,24 @@ pub fn pack_transform<T: Writable + DeserializeOwned, TT: DeserializeOwned + Deb
         }
     };
 
-    let writer = object.write();
+    write_to_vec(&object)
+}
+
+pub fn write_to_vec<T: Writable>(t: &T) -> Result<Vec<u8>, anyhow::Error> {
+    let writer = t.write();
     let w = WriteContext::from(Vec::new());
     Ok(writer(w)?.write)
 }
 
+pub fn find_asset_data(pack: &dyn HalleyPack, asset_type: i32, name: &str) -> Option<Vec<u8>> {
+    pack.sections()
+        .iter()
+        .filter(|s| s.asset_type() == asset_type)
+        .flat_map(|s| s.assets())
+        .find(|a| a.name() == name)
+        .map(|a| pack.get_asset_data(*a))
+}
+
 #[derive(Error, Debug)]
 pub enum HalleyPackParseError {
     #[error("Invalid asset type {0}")]
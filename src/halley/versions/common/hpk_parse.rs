@@ -21,6 +21,8 @@ use std::{io::Read, mem::size_of};
 use super::hpk::{HalleyPack, HalleyPackData, HpkSection, Parsable, Writable};
 
 static IDENTIFIER: &str = "HALLEYPK";
+/// Start of the compressed asset table, after the header and the table's uncompressed size.
+const ASSET_DB_DATA_POS: usize = 48;
 
 pub fn parse_hpk<'a, Section>(
     i_full: &'a [u8],
@@ -31,6 +33,7 @@ where
 {
     parse_hpk_header(i_full).map(move |(i, header)| {
         let (_, iv, _asset_db_start_pos, data_start_pos, asset_db_size) = header;
+        let asset_db_compressed = i_full[ASSET_DB_DATA_POS..data_start_pos as usize].to_vec();
 
         let mut asset_db_bytes = vec![0; asset_db_size as usize];
 
@@ -52,7 +55,10 @@ where
             .into_iter()
             .map(move |s| Box::new(s) as Box<dyn HpkSection>)
             .collect();
-        (i, HalleyPackData::new(asset_db, data))
+        let mut pack = HalleyPackData::new(asset_db, data);
+        pack.set_iv(iv);
+        pack.set_compressed_asset_db(Some(asset_db_compressed));
+        (i, pack)
     })
 }
 
@@ -66,6 +72,25 @@ fn parse_hpk_header(i: &[u8]) -> IResult<&[u8], (&[u8], [u8; 16], u64, u64, u64)
     ))(i)
 }
 
+/// Prepares the data of `pack` for writing. Packs with an IV are encrypted again with it when a
+/// secret is given. Without one the data stays plain, and the IV is cleared so that readers don't
+/// try to decrypt it.
+pub fn encrypt_pack_data(pack: &mut dyn HalleyPack, secret: Option<&str>) {
+    let iv = pack.iv();
+    if iv == [0_u8; 16] {
+        return;
+    }
+    match secret {
+        Some(_) => {
+            let mut data = pack.data().to_vec();
+            data.resize(data.len().next_multiple_of(16), 0);
+            let (data, _) = get_encrypted_data(&data, secret, Some(&iv));
+            pack.set_data(data);
+        }
+        None => pack.set_iv([0_u8; 16]),
+    }
+}
+
 pub fn get_encrypted_data(
     data: &[u8],
     secret: Option<&str>,
@@ -130,22 +155,28 @@ impl Writable for HalleyPackData {
         .unwrap()
         .write;
 
-        let mut encoded = Vec::new();
         let decoded: &[u8] = asset_db.as_ref();
-
-        ZlibEncoder::new(decoded, flate2::Compression::default())
-            .read_to_end(&mut encoded)
-            .unwrap();
+        let encoded = match self.compressed_asset_db() {
+            Some(original) if decompresses_to(original, decoded) => original.to_vec(),
+            _ => {
+                let mut encoded = Vec::new();
+                ZlibEncoder::new(decoded, flate2::Compression::default())
+                    .read_to_end(&mut encoded)
+                    .unwrap();
+                encoded
+            }
+        };
 
         let asset_db_size = asset_db.len();
         let asset_db_start_pos = IDENTIFIER.len() + 16 + (size_of::<u64>() * 2);
         let data_start_pos = asset_db_start_pos + size_of::<u64>() + encoded.len();
 
         assert!(asset_db_start_pos == 40);
+        assert!(asset_db_start_pos + size_of::<u64>() == ASSET_DB_DATA_POS);
 
         let writer = wh_tuple((
             w_slice(IDENTIFIER),
-            w_slice([0_u8; 16]),
+            w_slice(self.iv()),
             w_le_u64(asset_db_start_pos as u64),
             w_le_u64(data_start_pos as u64),
             w_le_u64(asset_db_size as u64),
@@ -156,3 +187,44 @@ impl Writable for HalleyPackData {
         Box::new(writer)
     }
 }
+
+fn decompresses_to(compressed: &[u8], expected: &[u8]) -> bool {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(compressed)
+        .read_to_end(&mut decompressed)
+        .is_ok()
+        && decompressed == expected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::{common::hpk::write_to_vec, v2023::hpk::HpkSectionV2023};
+
+    static SECRET: &str = "K09oemVwNHowNk51S2d1Tg==";
+
+    fn read(bytes: &[u8], secret: Option<&str>) -> impl HalleyPack {
+        parse_hpk::<HpkSectionV2023>(bytes, secret).unwrap().1
+    }
+
+    #[test]
+    fn test_write_decrypted_pack_without_secret() {
+        let plain = b"sixteen byte blk".repeat(2);
+        let mut pack = HalleyPackData::default();
+        pack.set_data(plain.clone());
+        pack.set_iv([7; 16]);
+        encrypt_pack_data(&mut pack, Some(SECRET));
+        let encrypted = write_to_vec(&pack).unwrap();
+
+        let mut pack = read(&encrypted, Some(SECRET));
+        assert_eq!(pack.iv(), [7; 16]);
+        assert_eq!(pack.data(), plain);
+
+        encrypt_pack_data(&mut pack, None);
+        let written = write_to_vec(&pack).unwrap();
+        // Even a reader with the secret sees the plain data.
+        let pack = read(&written, Some(SECRET));
+        assert_eq!(pack.iv(), [0; 16]);
+        assert_eq!(pack.data(), plain);
+    }
+}
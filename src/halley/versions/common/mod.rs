@@ -3,3 +3,4 @@ pub mod hpk;
 pub mod hpk_parse;
 pub mod hsave;
 pub mod primitives;
+pub mod uuid;
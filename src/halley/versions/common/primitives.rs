@@ -1,18 +1,19 @@
 use cookie_factory::{
-    bytes::{le_i8 as w_le_i8, le_u32 as w_le_u32},
-    combinator::slice as w_slice,
+    bytes::{le_i32 as w_le_i32, le_i8 as w_le_i8, le_u32 as w_le_u32},
+    combinator::{cond as w_cond, slice as w_slice},
     multi::all as w_all,
     sequence::tuple as w_tuple,
     SerializeFn,
 };
 use indexmap::IndexMap;
 use nom::{
-    combinator::{map, map_res, peek},
+    combinator::{cond, map, map_opt, map_res, peek},
     multi::{length_count, length_data},
     number::complete::{le_f32, le_i32, le_i64, le_i8, le_u32, le_u64, u8},
     sequence::tuple,
     IResult,
 };
+use num_traits::{FromPrimitive, ToPrimitive};
 use std::{cmp::min, io::Write};
 use thiserror::Error;
 
@@ -115,6 +116,34 @@ pub fn h_u32(i: &[u8]) -> IResult<&[u8], u32> {
     le_u32(i)
 }
 
+pub fn h_option<'a, T, F>(mut f: F) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Option<T>>
+where
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], T>,
+{
+    move |i: &'a [u8]| {
+        let (i, present) = h_bool(i)?;
+        cond(present, &mut f)(i)
+    }
+}
+
+pub fn wh_option<'a, T, F, W: Write + 'a>(v: &'a Option<T>, f: F) -> impl SerializeFn<W> + 'a
+where
+    F: Fn(&'a T) -> Box<dyn SerializeFn<W> + 'a> + 'a,
+{
+    w_tuple((
+        wh_bool(v.is_some()),
+        w_cond(v.is_some(), move |w| f(v.as_ref().unwrap())(w)),
+    ))
+}
+
+pub fn h_enum<T: FromPrimitive>(i: &[u8]) -> IResult<&[u8], T> {
+    map_opt(le_i32, T::from_i32)(i)
+}
+
+pub fn wh_enum<W: Write, T: ToPrimitive>(v: &T) -> impl SerializeFn<W> {
+    w_le_i32(v.to_i32().unwrap())
+}
+
 pub fn h_var_i(i: &[u8]) -> IResult<&[u8], i64> {
     map(var_u64(true), |(v, sign)| {
         let v = i64::from_le_bytes(v.to_le_bytes());
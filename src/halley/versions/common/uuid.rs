@@ -0,0 +1,89 @@
+use cookie_factory::{combinator::slice as w_slice, SerializeFn};
+use nom::{bytes::complete::take, combinator::map, IResult};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io::Write};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Invalid UUID {0}")]
+pub struct UuidError(String);
+
+/// Halley UUID, written as hex in the `8-4-4-4-12` form used by the engine's source files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl JsonSchema for Uuid {
+    fn schema_name() -> String {
+        "Uuid".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(
+                    "^[0-9a-fA-F]{8}-?[0-9a-fA-F]{4}-?[0-9a-fA-F]{4}-?[0-9a-fA-F]{4}-?[0-9a-fA-F]{12}$"
+                        .to_string(),
+                ),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, b) in self.0.iter().enumerate() {
+            if matches!(n, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Uuid {
+    type Error = UuidError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hex: Vec<char> = value.chars().filter(|c| *c != '-').collect();
+        if hex.len() != 32 {
+            return Err(UuidError(value));
+        }
+        let mut bytes = [0; 16];
+        for (b, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+            let pair: String = pair.iter().collect();
+            *b = u8::from_str_radix(&pair, 16).map_err(|_| UuidError(value.clone()))?;
+        }
+        Ok(Uuid(bytes))
+    }
+}
+
+impl From<Uuid> for String {
+    fn from(value: Uuid) -> Self {
+        value.to_string()
+    }
+}
+
+pub fn h_uuid(i: &[u8]) -> IResult<&[u8], Uuid> {
+    map(take(16_usize), |b: &[u8]| Uuid(b.try_into().unwrap()))(i)
+}
+
+pub fn wh_uuid<'a, W: Write + 'a>(uuid: &'a Uuid) -> impl SerializeFn<W> + 'a {
+    w_slice(&uuid.0)
+}
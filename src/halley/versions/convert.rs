@@ -0,0 +1,640 @@
+use super::{
+    common::{
+        config::{ConfigFile, ConfigNode},
+        hpk::{
+            make_asset_type, write_to_vec, HalleyPack, HalleyPackData, HpkAsset, HpkSection,
+            Parsable,
+        },
+    },
+    format::PackFormat,
+    v2020::{
+        self,
+        hpk::{AssetTypeV2020, HpkAssetV2020, HpkPropertiesV2020, HpkSectionV2020},
+    },
+    v2023::{
+        self,
+        hpk::{AssetTypeV2023, HpkAssetV2023, HpkSectionV2023},
+    },
+};
+use anyhow::anyhow;
+use std::fmt;
+
+/// Something that was lost or skipped while converting a pack.
+#[derive(Debug)]
+pub struct ConversionIssue {
+    pub asset: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConversionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.asset, self.message)
+    }
+}
+
+fn asset_type_to_v2023(asset_type: &AssetTypeV2020) -> AssetTypeV2023 {
+    match asset_type {
+        AssetTypeV2020::BINARY => AssetTypeV2023::BINARY,
+        AssetTypeV2020::TEXT => AssetTypeV2023::TEXT,
+        AssetTypeV2020::CONFIG => AssetTypeV2023::CONFIG,
+        AssetTypeV2020::TEXTURE => AssetTypeV2023::TEXTURE,
+        AssetTypeV2020::SHADER => AssetTypeV2023::SHADER,
+        AssetTypeV2020::MATERIAL => AssetTypeV2023::MATERIAL,
+        AssetTypeV2020::IMAGE => AssetTypeV2023::IMAGE,
+        AssetTypeV2020::SPRITE => AssetTypeV2023::SPRITE,
+        AssetTypeV2020::SPRITESHEET => AssetTypeV2023::SPRITESHEET,
+        AssetTypeV2020::ANIMATION => AssetTypeV2023::ANIMATION,
+        AssetTypeV2020::FONT => AssetTypeV2023::FONT,
+        AssetTypeV2020::AUDIOCLIP => AssetTypeV2023::AUDIOCLIP,
+        AssetTypeV2020::AUDIOEVENT => AssetTypeV2023::AUDIOEVENT,
+        AssetTypeV2020::MESH => AssetTypeV2023::MESH,
+        AssetTypeV2020::MESHANIMATION => AssetTypeV2023::MESHANIMATION,
+        AssetTypeV2020::VARIABLETABLE => AssetTypeV2023::VARIABLETABLE,
+    }
+}
+
+fn asset_type_to_v2020(asset_type: &AssetTypeV2023) -> Option<AssetTypeV2020> {
+    match asset_type {
+        AssetTypeV2023::BINARY => Some(AssetTypeV2020::BINARY),
+        AssetTypeV2023::TEXT => Some(AssetTypeV2020::TEXT),
+        AssetTypeV2023::CONFIG => Some(AssetTypeV2020::CONFIG),
+        AssetTypeV2023::TEXTURE => Some(AssetTypeV2020::TEXTURE),
+        AssetTypeV2023::SHADER => Some(AssetTypeV2020::SHADER),
+        AssetTypeV2023::MATERIAL => Some(AssetTypeV2020::MATERIAL),
+        AssetTypeV2023::IMAGE => Some(AssetTypeV2020::IMAGE),
+        AssetTypeV2023::SPRITE => Some(AssetTypeV2020::SPRITE),
+        AssetTypeV2023::SPRITESHEET => Some(AssetTypeV2020::SPRITESHEET),
+        AssetTypeV2023::ANIMATION => Some(AssetTypeV2020::ANIMATION),
+        AssetTypeV2023::FONT => Some(AssetTypeV2020::FONT),
+        AssetTypeV2023::AUDIOCLIP => Some(AssetTypeV2020::AUDIOCLIP),
+        AssetTypeV2023::AUDIOEVENT => Some(AssetTypeV2020::AUDIOEVENT),
+        AssetTypeV2023::MESH => Some(AssetTypeV2020::MESH),
+        AssetTypeV2023::MESHANIMATION => Some(AssetTypeV2020::MESHANIMATION),
+        AssetTypeV2023::VARIABLETABLE => Some(AssetTypeV2020::VARIABLETABLE),
+        _ => None,
+    }
+}
+
+/// Lifts a V2020 string property to the `ConfigNode` it most likely was in the source asset.
+fn lift_property(value: &str) -> ConfigNode {
+    if let Ok(b) = value.parse::<bool>() {
+        ConfigNode::Bool(b)
+    } else if let Ok(i) = value.parse::<i32>() {
+        ConfigNode::Int(i)
+    } else if let Ok(i) = value.parse::<i64>() {
+        ConfigNode::Int64(i)
+    } else if value.contains('.') && value.parse::<f32>().is_ok() {
+        ConfigNode::Float(value.parse().unwrap())
+    } else {
+        ConfigNode::String(value.to_owned())
+    }
+}
+
+fn lift_properties(properties: &ConfigNode) -> ConfigNode {
+    match properties {
+        ConfigNode::Map(map) => ConfigNode::Map(
+            map.iter()
+                .map(|(k, v)| match v {
+                    ConfigNode::String(s) => (k.to_owned(), lift_property(s)),
+                    v => (k.to_owned(), v.clone()),
+                })
+                .collect(),
+        ),
+        properties => properties.clone(),
+    }
+}
+
+fn flatten_properties(config: &ConfigNode, issues: &mut Vec<String>) -> HpkPropertiesV2020 {
+    let ConfigNode::Map(map) = config else {
+        if !matches!(config, ConfigNode::Undefined) {
+            issues.push("properties are not a map and were dropped".to_string());
+        }
+        return HpkPropertiesV2020::new();
+    };
+    map.iter()
+        .filter_map(|(k, v)| {
+            let value = match v {
+                ConfigNode::String(s) => s.to_owned(),
+                ConfigNode::Int(i) => i.to_string(),
+                ConfigNode::Int64(i) => i.to_string(),
+                ConfigNode::Float(f) => f.to_string(),
+                ConfigNode::Bool(b) => b.to_string(),
+                _ => {
+                    issues.push(format!(
+                        "property {} is not a plain value and was dropped",
+                        k
+                    ));
+                    return None;
+                }
+            };
+            Some((k.to_owned(), value))
+        })
+        .collect()
+}
+
+fn parse<T: Parsable>(data: &[u8]) -> Result<T, anyhow::Error> {
+    let (_, t) = T::parse(data).map_err(|err| anyhow!(err.to_string()))?;
+    Ok(t)
+}
+
+fn upgrade_animation(animation: v2020::animation::Animation) -> v2023::animation::Animation {
+    v2023::animation::Animation {
+        name: animation.name,
+        spritesheet: animation.spritesheet,
+        material: animation.material,
+        sequences: animation
+            .sequences
+            .into_iter()
+            .enumerate()
+            .map(|(id, s)| v2023::animation::Sequence {
+                frames: s.frames,
+                name: s.name,
+                id: id as i32,
+                is_loop: s.is_loop,
+                no_flip: s.no_flip,
+                fallback: false,
+            })
+            .collect(),
+        directions: animation.directions,
+        action_points: vec![],
+    }
+}
+
+fn downgrade_animation(
+    animation: v2023::animation::Animation,
+    issues: &mut Vec<String>,
+) -> v2020::animation::Animation {
+    if !animation.action_points.is_empty() {
+        issues.push(format!(
+            "{} action points were dropped",
+            animation.action_points.len()
+        ));
+    }
+    v2020::animation::Animation {
+        name: animation.name,
+        spritesheet: animation.spritesheet,
+        material: animation.material,
+        sequences: animation
+            .sequences
+            .into_iter()
+            .enumerate()
+            .map(|(n, s)| {
+                if s.id != n as i32 {
+                    issues.push(format!("id {} of sequence {} was dropped", s.id, s.name));
+                }
+                if s.fallback {
+                    issues.push(format!("sequence {} is no longer a fallback", s.name));
+                }
+                v2020::animation::Sequence {
+                    frames: s.frames,
+                    name: s.name,
+                    is_loop: s.is_loop,
+                    no_flip: s.no_flip,
+                }
+            })
+            .collect(),
+        directions: animation.directions,
+    }
+}
+
+fn upgrade_spritesheet(
+    sheet: v2020::spritesheet::SpriteSheet,
+    issues: &mut Vec<String>,
+) -> v2023::spritesheet::SpriteSheet {
+    if sheet.sprites.iter().any(|s| s.duration != 0) {
+        issues.push("sprite durations were dropped".to_string());
+    }
+    v2023::spritesheet::SpriteSheet {
+        v: 0,
+        name: sheet.name,
+        sprites: sheet
+            .sprites
+            .into_iter()
+            .map(|s| v2023::spritesheet::Sprite {
+                pivot: s.pivot,
+                orig_pivot: s.orig_pivot,
+                size: s.size,
+                coords: s.coords,
+                rotated: s.rotated,
+                trim_border: s.trim_border,
+                slices: s.slices,
+            })
+            .collect(),
+        sprite_idx: v2023::spritesheet::SpriteIdx(sheet.sprite_idx.0),
+        frame_tags: sheet
+            .frame_tags
+            .into_iter()
+            .map(|t| v2023::spritesheet::FrameTag {
+                name: t.name,
+                to: t.to,
+                from: t.from,
+            })
+            .collect(),
+        def_material_name: None,
+        palette_name: None,
+    }
+    .upgrade()
+}
+
+fn downgrade_spritesheet(
+    sheet: v2023::spritesheet::SpriteSheet,
+    issues: &mut Vec<String>,
+) -> v2020::spritesheet::SpriteSheet {
+    let (sheet, warnings) = sheet.migrate(0).unwrap();
+    issues.extend(warnings);
+    v2020::spritesheet::SpriteSheet {
+        name: sheet.name,
+        sprites: sheet
+            .sprites
+            .into_iter()
+            .map(|s| v2020::spritesheet::Sprite {
+                pivot: s.pivot,
+                orig_pivot: s.orig_pivot,
+                size: s.size,
+                coords: s.coords,
+                duration: 0,
+                rotated: s.rotated,
+                trim_border: s.trim_border,
+                slices: s.slices,
+            })
+            .collect(),
+        sprite_idx: v2020::spritesheet::SpriteIdx(sheet.sprite_idx.0),
+        frame_tags: sheet
+            .frame_tags
+            .into_iter()
+            .map(|t| v2020::spritesheet::FrameTag {
+                name: t.name,
+                to: t.to,
+                from: t.from,
+            })
+            .collect(),
+    }
+}
+
+fn upgrade_font(font: v2020::font::Font) -> v2023::font::Font {
+    v2023::font::Font {
+        name: font.name,
+        image_name: font.image_name,
+        distance_field: font.metrics.smooth_radius > 0.0,
+        metrics: font.metrics,
+        fallback_fonts: Some(font.fallback)
+            .into_iter()
+            .filter(|f| !f.is_empty())
+            .collect(),
+        glyphs: font.glyphs,
+    }
+}
+
+fn downgrade_font(font: v2023::font::Font, issues: &mut Vec<String>) -> v2020::font::Font {
+    let mut fallback_fonts = font.fallback_fonts.into_iter();
+    let fallback = fallback_fonts.next().unwrap_or_default();
+    for dropped in fallback_fonts {
+        issues.push(format!("fallback font {} was dropped", dropped));
+    }
+    v2020::font::Font {
+        name: font.name,
+        image_name: font.image_name,
+        metrics: font.metrics,
+        fallback,
+        glyphs: font.glyphs,
+    }
+}
+
+/// Rewrites a config with the header of another version. Positions missing from the source are
+/// synthesized when the target version stores them.
+fn convert_config(file: ConfigFile, v: i32) -> ConfigFile {
+    ConfigFile {
+        v,
+        store_file_position: match v > 2 {
+            true => file.store_file_position,
+            false => v == 2,
+        },
+        ..file
+    }
+}
+
+fn upgrade_material(
+    material: v2020::material::MaterialDefinition,
+) -> v2023::material::MaterialDefinition {
+    v2023::material::MaterialDefinition {
+        name: material.name,
+        passes: material.passes,
+        uniform_blocks: material
+            .uniform_blocks
+            .into_iter()
+            .map(|block| v2023::material::MaterialUniformBlock {
+                name: block.name,
+                uniforms: block
+                    .uniforms
+                    .into_iter()
+                    .map(|u| v2023::material::MaterialUniform {
+                        name: u.name,
+                        uniform_type: u.uniform_type,
+                        default_value: ConfigNode::Undefined,
+                        range: None,
+                        granularity: None,
+                        editable: true,
+                        auto_variable: String::new(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        attributes: material.attributes,
+        vertex_size: material.vertex_size,
+        vertex_pos_offset: material.vertex_pos_offset,
+        textures: material
+            .textures
+            .into_iter()
+            .map(|name| v2023::material::MaterialTexture {
+                name,
+                default_texture_name: String::new(),
+                sampler_type: v2023::material::TextureSamplerType::Texture2D,
+            })
+            .collect(),
+        tags: vec![],
+    }
+}
+
+fn downgrade_material(
+    material: v2023::material::MaterialDefinition,
+    issues: &mut Vec<String>,
+) -> v2020::material::MaterialDefinition {
+    if !material.tags.is_empty() {
+        issues.push(format!("tags {} were dropped", material.tags.join(", ")));
+    }
+    v2020::material::MaterialDefinition {
+        name: material.name,
+        passes: material.passes,
+        uniform_blocks: material
+            .uniform_blocks
+            .into_iter()
+            .map(|block| v2020::material::MaterialUniformBlock {
+                name: block.name,
+                uniforms: block
+                    .uniforms
+                    .into_iter()
+                    .map(|u| {
+                        let has_settings = u.default_value != ConfigNode::Undefined
+                            || u.range.is_some()
+                            || u.granularity.is_some()
+                            || !u.editable
+                            || !u.auto_variable.is_empty();
+                        if has_settings {
+                            issues.push(format!(
+                                "default value and editor settings of uniform {} were dropped",
+                                u.name
+                            ));
+                        }
+                        v2020::material::MaterialUniform {
+                            name: u.name,
+                            uniform_type: u.uniform_type,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect(),
+        attributes: material.attributes,
+        vertex_size: material.vertex_size,
+        vertex_pos_offset: material.vertex_pos_offset,
+        textures: material
+            .textures
+            .into_iter()
+            .map(|t| {
+                if !t.default_texture_name.is_empty() {
+                    issues.push(format!(
+                        "default texture {} of texture {} was dropped",
+                        t.default_texture_name, t.name
+                    ));
+                }
+                if !matches!(
+                    t.sampler_type,
+                    v2023::material::TextureSamplerType::Texture2D
+                ) {
+                    issues.push(format!(
+                        "sampler type {:?} of texture {} was dropped",
+                        t.sampler_type, t.name
+                    ));
+                }
+                t.name
+            })
+            .collect(),
+    }
+}
+
+/// Converts the data of a V2020 asset, or returns `None` when it has no V2023 equivalent.
+fn upgrade_data(
+    asset_type: &AssetTypeV2020,
+    data: Vec<u8>,
+    issues: &mut Vec<String>,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    match asset_type {
+        AssetTypeV2020::ANIMATION => write_to_vec(&upgrade_animation(parse(&data)?)).map(Some),
+        AssetTypeV2020::SPRITESHEET => {
+            write_to_vec(&upgrade_spritesheet(parse(&data)?, issues)).map(Some)
+        }
+        AssetTypeV2020::FONT => write_to_vec(&upgrade_font(parse(&data)?)).map(Some),
+        AssetTypeV2020::CONFIG => {
+            write_to_vec(&convert_config(parse(&data)?, v2023::hpk::CONFIG_VERSION)).map(Some)
+        }
+        AssetTypeV2020::MATERIAL => write_to_vec(&upgrade_material(parse(&data)?)).map(Some),
+        AssetTypeV2020::SPRITE | AssetTypeV2020::AUDIOEVENT => {
+            issues.push(format!("{:?} assets can't be converted", asset_type));
+            Ok(None)
+        }
+        _ => Ok(Some(data)),
+    }
+}
+
+/// Converts the data of a V2023 asset, or returns `None` when it has no V2020 equivalent.
+fn downgrade_data(
+    asset_type: &AssetTypeV2023,
+    data: Vec<u8>,
+    issues: &mut Vec<String>,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    match asset_type {
+        AssetTypeV2023::ANIMATION => {
+            write_to_vec(&downgrade_animation(parse(&data)?, issues)).map(Some)
+        }
+        AssetTypeV2023::SPRITESHEET => {
+            write_to_vec(&downgrade_spritesheet(parse(&data)?, issues)).map(Some)
+        }
+        AssetTypeV2023::FONT => write_to_vec(&downgrade_font(parse(&data)?, issues)).map(Some),
+        AssetTypeV2023::CONFIG => {
+            write_to_vec(&convert_config(parse(&data)?, v2020::hpk::CONFIG_VERSION)).map(Some)
+        }
+        AssetTypeV2023::MATERIAL => {
+            write_to_vec(&downgrade_material(parse(&data)?, issues)).map(Some)
+        }
+        AssetTypeV2023::SPRITE | AssetTypeV2023::AUDIOEVENT => {
+            issues.push(format!("{:?} assets can't be converted", asset_type));
+            Ok(None)
+        }
+        _ if asset_type_to_v2020(asset_type).is_none() => {
+            issues.push(format!("{:?} assets don't exist in V2020", asset_type));
+            Ok(None)
+        }
+        _ => Ok(Some(data)),
+    }
+}
+
+fn report(issues: &mut Vec<ConversionIssue>, asset: &dyn HpkAsset, messages: Vec<String>) {
+    issues.extend(messages.into_iter().map(|message| ConversionIssue {
+        asset: asset.name().to_owned(),
+        message,
+    }));
+}
+
+/// Rewrites a V2020 pack as a V2023 one. Assets that can't be converted are left out, and
+/// everything that was lost is returned as issues. Sections are numbered by `to`.
+pub fn convert_v2020_to_v2023(
+    pack: &dyn HalleyPack,
+    to: &dyn PackFormat,
+) -> Result<(Box<dyn HalleyPack>, Vec<ConversionIssue>), anyhow::Error> {
+    let mut converted = HalleyPackData::default();
+    let mut issues = vec![];
+
+    for section in pack.sections() {
+        let asset_type: AssetTypeV2020 = make_asset_type(section.asset_type())?;
+        let new_type = asset_type_to_v2023(&asset_type) as i32;
+        let mut new_section = HpkSectionV2023::new(new_type)?;
+        if let Some(section_index) = to.section_index(new_type) {
+            new_section.set_section_index(section_index);
+        }
+
+        for asset in section.assets() {
+            let mut messages = vec![];
+            let data = pack.get_asset_data(*asset);
+            let Some(data) = upgrade_data(&asset_type, data, &mut messages)? else {
+                report(&mut issues, *asset, messages);
+                continue;
+            };
+            let mut new_asset = HpkAssetV2023 {
+                name: asset.name().to_owned(),
+                pos: 0,
+                size: 0,
+                config: lift_properties(&asset.get_properties()),
+            };
+            let (pos, size) = converted.add_data(data, new_asset.get_asset_compression());
+            new_asset.set_pos_size(pos, size);
+            new_section.assets.push(new_asset);
+            report(&mut issues, *asset, messages);
+        }
+        converted.add_section(Box::new(new_section));
+    }
+    Ok((Box::new(converted), issues))
+}
+
+/// Rewrites a V2023 pack as a V2020 one. Assets that can't be converted are left out, and
+/// everything that was lost is returned as issues.
+pub fn convert_v2023_to_v2020(
+    pack: &dyn HalleyPack,
+    _to: &dyn PackFormat,
+) -> Result<(Box<dyn HalleyPack>, Vec<ConversionIssue>), anyhow::Error> {
+    let mut converted = HalleyPackData::default();
+    let mut issues = vec![];
+
+    for section in pack.sections() {
+        let asset_type: AssetTypeV2023 = make_asset_type(section.asset_type())?;
+        let mut new_section = asset_type_to_v2020(&asset_type).map(|t| HpkSectionV2020 {
+            asset_type: t,
+            assets: vec![],
+        });
+
+        for asset in section.assets() {
+            let mut messages = vec![];
+            let data = pack.get_asset_data(*asset);
+            let (Some(new_section), Some(data)) = (
+                new_section.as_mut(),
+                downgrade_data(&asset_type, data, &mut messages)?,
+            ) else {
+                report(&mut issues, *asset, messages);
+                continue;
+            };
+            let mut new_asset = HpkAssetV2020 {
+                name: asset.name().to_owned(),
+                pos: 0,
+                size: 0,
+                properties: flatten_properties(&asset.get_properties(), &mut messages),
+            };
+            let (pos, size) = converted.add_data(data, new_asset.get_asset_compression());
+            new_asset.set_pos_size(pos, size);
+            new_section.assets.push(new_asset);
+            report(&mut issues, *asset, messages);
+        }
+        if let Some(new_section) = new_section {
+            converted.add_section(Box::new(new_section));
+        }
+    }
+    Ok((Box::new(converted), issues))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::{common::config::ConfigNodeMap, format::PackFormatRegistry};
+
+    fn config_pack(file: &ConfigFile) -> HalleyPackData {
+        let mut pack = HalleyPackData::default();
+        let (pos, size) = pack.add_data(write_to_vec(file).unwrap(), None);
+        pack.add_section(Box::new(HpkSectionV2020 {
+            asset_type: AssetTypeV2020::CONFIG,
+            assets: vec![HpkAssetV2020 {
+                name: "settings".to_string(),
+                pos,
+                size,
+                properties: HpkPropertiesV2020::new(),
+            }],
+        }));
+        pack
+    }
+
+    fn config_data(pack: &dyn HalleyPack) -> ConfigFile {
+        let section = &pack.sections()[0];
+        assert_eq!(section.asset_type(), AssetTypeV2020::CONFIG as i32);
+        parse(&pack.get_asset_data(*section.assets()[0])).unwrap()
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let root = ConfigNode::Map(ConfigNodeMap::from([
+            ("speed".to_string(), ConfigNode::Float(1.5)),
+            (
+                "names".to_string(),
+                ConfigNode::Sequence(vec![ConfigNode::String("a".to_string())]),
+            ),
+        ]));
+        let file = ConfigFile {
+            v: v2020::hpk::CONFIG_VERSION,
+            store_file_position: true,
+            root: root.clone(),
+            positions: None,
+        };
+
+        let registry = PackFormatRegistry::builtin();
+        let (upgraded, issues) =
+            convert_v2020_to_v2023(&config_pack(&file), registry.get("V2023").unwrap()).unwrap();
+        assert!(issues.is_empty());
+        let upgraded_file = config_data(&*upgraded);
+        assert_eq!(upgraded_file.v, v2023::hpk::CONFIG_VERSION);
+        assert!(upgraded_file.store_file_position);
+        assert_eq!(upgraded_file.root, root);
+
+        assert_eq!(
+            upgraded.sections()[0].section_index(),
+            Some(AssetTypeV2023::CONFIG as i32)
+        );
+
+        let (downgraded, issues) =
+            convert_v2023_to_v2020(&*upgraded, registry.get("V2020").unwrap()).unwrap();
+        assert!(issues.is_empty());
+        let downgraded_file = config_data(&*downgraded);
+        assert_eq!(downgraded_file.v, v2020::hpk::CONFIG_VERSION);
+        assert_eq!(downgraded_file.root, root);
+        // Back to the original bytes, header and positions included.
+        let section = &downgraded.sections()[0];
+        assert_eq!(
+            downgraded.get_asset_data(*section.assets()[0]),
+            write_to_vec(&file).unwrap()
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use super::{
+    common::hpk::{HalleyPack, HalleyPackData, HpkSection, Parsable},
+    convert::{convert_v2020_to_v2023, convert_v2023_to_v2020, ConversionIssue},
+    v2020::{
+        self,
+        hpk::{AssetTypeV2020, HpkSectionV2020},
+    },
+    v2023::{
+        self,
+        hpk::{AssetTypeV2023, HpkSectionV2023},
+    },
+};
+use crate::halley::assets::{
+    bmfont::{export_bmfont, import_bmfont, BmFontConvertible},
+    schema::write_schemas,
+    unpack::pack_halley_pk,
+};
+use anyhow::anyhow;
+use num_traits::FromPrimitive;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    path::Path,
+    sync::OnceLock,
+};
+
+/// A version of the pack layout. The section type carries everything that differs between
+/// versions: how sections and assets are parsed and written, and, through
+/// `HpkSectionUnpackable`, the per-type transforms and file endings used on unpack and repack.
+pub trait PackFormat: Send + Sync {
+    fn name(&self) -> &str;
+    /// Asset types known to this version, as `(value, name)`.
+    fn asset_types(&self) -> &[(i32, String)];
+    fn load(&self, path: &Path, secret: Option<&str>)
+        -> Result<Box<dyn HalleyPack>, anyhow::Error>;
+    fn pack(&self, path: &Path) -> Result<Box<dyn HalleyPack>, anyhow::Error>;
+    /// Empty section of `asset_type`, numbered with `section_index` when the version stores one.
+    fn new_section(&self, asset_type: i32) -> Result<Box<dyn HpkSection>, anyhow::Error>;
+    /// Index stored with a new section of `asset_type`, for versions that store one: the
+    /// position of the type in `asset_types`. Sections read from a pack or listed in a layout
+    /// keep their own.
+    fn section_index(&self, asset_type: i32) -> Option<i32>;
+    /// File ending added to unpacked assets of `asset_type`, such as `.anim`.
+    fn file_ending(&self, asset_type: i32) -> Result<String, anyhow::Error>;
+    /// Writes the JSON Schema of every asset type with a text form, and of the property files,
+    /// to `dir`.
+    fn write_schemas(&self, dir: &Path) -> Result<(), anyhow::Error>;
+    /// Writes the font asset `font_name` of `pack` as a BMFont file and its atlas to `dir`.
+    fn export_font(
+        &self,
+        pack: &dyn HalleyPack,
+        font_name: &str,
+        dir: &Path,
+    ) -> Result<(), anyhow::Error>;
+    /// Applies a BMFont file to an unpacked font document of this version.
+    fn import_font(
+        &self,
+        font_file: &Path,
+        bmfont_file: &Path,
+        image_file: Option<&Path>,
+    ) -> Result<(), anyhow::Error>;
+
+    fn asset_type(&self, name: &str) -> Option<i32> {
+        self.asset_types()
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(v, _)| *v)
+    }
+
+    fn asset_type_name(&self, asset_type: i32) -> Option<&str> {
+        self.asset_types()
+            .iter()
+            .find(|(v, _)| *v == asset_type)
+            .map(|(_, n)| n.as_str())
+    }
+}
+
+impl Debug for dyn PackFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `PackFormat` for any section and font type. Forks of the engine register their own version
+/// with `HpkFormat::<MySection, MyFont>::new("name", asset_type_table::<MyAssetType>())`.
+pub struct HpkFormat<Section, Font> {
+    name: String,
+    asset_types: Vec<(i32, String)>,
+    section: PhantomData<fn() -> (Section, Font)>,
+}
+
+impl<Section, Font> HpkFormat<Section, Font> {
+    pub fn new(name: &str, asset_types: Vec<(i32, String)>) -> Self {
+        HpkFormat {
+            name: name.to_owned(),
+            asset_types,
+            section: PhantomData,
+        }
+    }
+}
+
+impl<Section, Font> PackFormat for HpkFormat<Section, Font>
+where
+    Section: Parsable + HpkSection + 'static,
+    Font: Parsable + Serialize + DeserializeOwned + BmFontConvertible,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn asset_types(&self) -> &[(i32, String)] {
+        &self.asset_types
+    }
+
+    fn load(
+        &self,
+        path: &Path,
+        secret: Option<&str>,
+    ) -> Result<Box<dyn HalleyPack>, anyhow::Error> {
+        Ok(HalleyPackData::load::<Section>(path, secret)?)
+    }
+
+    fn pack(&self, path: &Path) -> Result<Box<dyn HalleyPack>, anyhow::Error> {
+        pack_halley_pk(path, self)
+    }
+
+    fn new_section(&self, asset_type: i32) -> Result<Box<dyn HpkSection>, anyhow::Error> {
+        let mut section = Section::new(asset_type)?;
+        if let Some(section_index) = self.section_index(asset_type) {
+            section.set_section_index(section_index);
+        }
+        Ok(Box::new(section))
+    }
+
+    fn section_index(&self, asset_type: i32) -> Option<i32> {
+        Section::new(asset_type).ok()?.section_index()?;
+        self.asset_types
+            .iter()
+            .position(|(t, _)| *t == asset_type)
+            .map(|position| position as i32)
+    }
+
+    fn file_ending(&self, asset_type: i32) -> Result<String, anyhow::Error> {
+        Ok(Section::new(asset_type)?
+            .get_unknown_file_type_ending()
+            .to_owned())
+    }
+
+    fn write_schemas(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        let sections = self
+            .asset_types
+            .iter()
+            .map(|(asset_type, _)| Section::new(*asset_type))
+            .collect::<Result<Vec<_>, _>>()?;
+        let sections: Vec<&dyn HpkSection> =
+            sections.iter().map(|s| s as &dyn HpkSection).collect();
+        write_schemas(&sections, dir, true)
+    }
+
+    fn export_font(
+        &self,
+        pack: &dyn HalleyPack,
+        font_name: &str,
+        dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let asset_type = |name| {
+            self.asset_type(name)
+                .ok_or_else(|| anyhow!("{} packs have no {} assets", self.name, name))
+        };
+        export_bmfont::<Font>(
+            pack,
+            asset_type("FONT")?,
+            asset_type("IMAGE")?,
+            font_name,
+            dir,
+        )
+    }
+
+    fn import_font(
+        &self,
+        font_file: &Path,
+        bmfont_file: &Path,
+        image_file: Option<&Path>,
+    ) -> Result<(), anyhow::Error> {
+        import_bmfont::<Font>(font_file, bmfont_file, image_file)
+    }
+}
+
+/// Builds the `(value, name)` table of an asset type enum whose values start at 0 and have no
+/// gaps.
+pub fn asset_type_table<T: FromPrimitive + Debug>() -> Vec<(i32, String)> {
+    (0..)
+        .map_while(|v| T::from_i32(v).map(|t| (v, format!("{:?}", t))))
+        .collect()
+}
+
+/// Rewrites a pack of one version as a pack of `to`, returning what could not be converted.
+pub type PackConverter = fn(
+    pack: &dyn HalleyPack,
+    to: &dyn PackFormat,
+) -> Result<(Box<dyn HalleyPack>, Vec<ConversionIssue>), anyhow::Error>;
+
+/// Pack versions, and the conversions between them, looked up by name. The default registry
+/// holds the versions built into this crate.
+pub struct PackFormatRegistry {
+    formats: Vec<Box<dyn PackFormat>>,
+    converters: Vec<(String, String, PackConverter)>,
+}
+
+static BUILTIN: OnceLock<PackFormatRegistry> = OnceLock::new();
+
+impl PackFormatRegistry {
+    pub fn empty() -> Self {
+        PackFormatRegistry {
+            formats: vec![],
+            converters: vec![],
+        }
+    }
+
+    /// The default registry, shared by everything that looks up a built-in version.
+    pub fn builtin() -> &'static PackFormatRegistry {
+        BUILTIN.get_or_init(PackFormatRegistry::default)
+    }
+
+    /// Adds a version, replacing any registered under the same name.
+    pub fn register(&mut self, format: Box<dyn PackFormat>) {
+        self.formats
+            .retain(|f| !f.name().eq_ignore_ascii_case(format.name()));
+        self.formats.push(format);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&dyn PackFormat, anyhow::Error> {
+        self.formats
+            .iter()
+            .find(|f| f.name().eq_ignore_ascii_case(name))
+            .map(|f| f.as_ref())
+            .ok_or_else(|| anyhow!("Unknown pack version {}", name))
+    }
+
+    /// Removes a version from the registry and returns it.
+    pub fn take(&mut self, name: &str) -> Result<Box<dyn PackFormat>, anyhow::Error> {
+        let index = self
+            .formats
+            .iter()
+            .position(|f| f.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("Unknown pack version {}", name))?;
+        Ok(self.formats.remove(index))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.formats.iter().map(|f| f.name()).collect()
+    }
+
+    /// Adds a conversion between two versions, replacing any registered for the same pair.
+    pub fn register_converter(&mut self, from: &str, to: &str, converter: PackConverter) {
+        self.converters
+            .retain(|(f, t, _)| !(f.eq_ignore_ascii_case(from) && t.eq_ignore_ascii_case(to)));
+        self.converters
+            .push((from.to_owned(), to.to_owned(), converter));
+    }
+
+    pub fn converter(&self, from: &str, to: &str) -> Result<PackConverter, anyhow::Error> {
+        if from.eq_ignore_ascii_case(to) {
+            return Err(anyhow!("The pack is already a {} pack", to));
+        }
+        self.converters
+            .iter()
+            .find(|(f, t, _)| f.eq_ignore_ascii_case(from) && t.eq_ignore_ascii_case(to))
+            .map(|(_, _, converter)| *converter)
+            .ok_or_else(|| anyhow!("No conversion from {} to {}", from, to))
+    }
+}
+
+impl Default for PackFormatRegistry {
+    fn default() -> Self {
+        let mut registry = PackFormatRegistry::empty();
+        registry.register(Box::new(
+            HpkFormat::<HpkSectionV2020, v2020::font::Font>::new(
+                "V2020",
+                asset_type_table::<AssetTypeV2020>(),
+            ),
+        ));
+        registry.register(Box::new(
+            HpkFormat::<HpkSectionV2023, v2023::font::Font>::new(
+                "V2023",
+                asset_type_table::<AssetTypeV2023>(),
+            ),
+        ));
+        registry.register_converter("V2020", "V2023", convert_v2020_to_v2023);
+        registry.register_converter("V2023", "V2020", convert_v2023_to_v2020);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_formats() {
+        let registry = PackFormatRegistry::builtin();
+        assert_eq!(registry.names(), vec!["V2020", "V2023"]);
+
+        let v2023 = registry.get("v2023").unwrap();
+        assert_eq!(v2023.asset_type("mesh"), Some(AssetTypeV2023::MESH as i32));
+        assert_eq!(v2023.asset_type_name(23), Some("UIDDEFINITION"));
+        assert_eq!(
+            v2023.file_ending(AssetTypeV2023::ANIMATION as i32).unwrap(),
+            ".anim"
+        );
+        assert_eq!(v2023.section_index(AssetTypeV2023::PREFAB as i32), Some(21));
+        assert_eq!(registry.get("v2020").unwrap().section_index(0), None);
+        assert!(registry.get("V1999").is_err());
+
+        assert!(registry.converter("v2020", "V2023").is_ok());
+        assert!(registry.converter("V2023", "V2023").is_err());
+    }
+}
@@ -1,3 +1,5 @@
 pub mod common;
+pub mod convert;
+pub mod format;
 pub mod v2020;
 pub mod v2023;
@@ -15,9 +15,10 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Animation {
     pub name: String,
     pub spritesheet: String,
@@ -62,7 +63,7 @@ impl Writable for Animation {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Frame {
     pub image_name: String,
     pub frame_number: i32,
@@ -93,7 +94,7 @@ impl Writable for Frame {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Sequence {
     pub frames: Vec<Frame>,
     pub name: String,
@@ -128,7 +129,7 @@ impl Writable for Sequence {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Direction {
     pub name: String,
     pub filename: String,
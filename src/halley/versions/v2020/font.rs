@@ -0,0 +1,322 @@
+use super::super::common::{
+    hpk::{Parsable, Writable},
+    primitives::{h_string, wh_string},
+};
+use crate::halley::assets::bmfont::{BmChar, BmFont, BmFontConvertible, BmKerning};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u32 as w_le_u32},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Font {
+    pub name: String,
+    pub image_name: String,
+    pub metrics: FontMetrics,
+    pub fallback: String,
+    pub glyphs: Vec<Glyph>,
+}
+
+impl Parsable for Font {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                h_string,
+                FontMetrics::parse,
+                h_string,
+                length_count(le_u32, Glyph::parse),
+            )),
+            |(name, image_name, metrics, fallback, glyphs)| Font {
+                name,
+                image_name,
+                metrics,
+                fallback,
+                glyphs,
+            },
+        )(i)
+    }
+}
+
+impl Writable for Font {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_string(&self.image_name),
+            self.metrics.write(),
+            wh_string(&self.fallback),
+            w_le_u32(self.glyphs.len() as u32),
+            wh_all(self.glyphs.iter().map(|g| g.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl BmFontConvertible for Font {
+    fn image_name(&self) -> &str {
+        &self.image_name
+    }
+
+    fn to_bmfont(&self) -> BmFont {
+        glyphs_to_bmfont(&self.name, &self.image_name, &self.metrics, &self.glyphs)
+    }
+
+    fn apply_bmfont(&mut self, font: &BmFont) {
+        apply_bmfont_to_glyphs(&mut self.metrics, &mut self.glyphs, font)
+    }
+}
+
+/// `smooth_radius` is the distance field spread, in pixels, used when the atlas was generated.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FontMetrics {
+    pub ascender: f32,
+    pub height: f32,
+    pub size_points: f32,
+    pub smooth_radius: f32,
+    pub image_size: (f32, f32),
+}
+
+impl Parsable for FontMetrics {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((le_f32, le_f32, le_f32, le_f32, tuple((le_f32, le_f32)))),
+            |(ascender, height, size_points, smooth_radius, image_size)| FontMetrics {
+                ascender,
+                height,
+                size_points,
+                smooth_radius,
+                image_size,
+            },
+        )(i)
+    }
+}
+
+impl Writable for FontMetrics {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_f32(self.ascender),
+            w_le_f32(self.height),
+            w_le_f32(self.size_points),
+            w_le_f32(self.smooth_radius),
+            w_le_f32(self.image_size.0),
+            w_le_f32(self.image_size.1),
+        ));
+        Box::new(writer)
+    }
+}
+
+/// `area` holds the glyph's texture coordinates, normalised to the atlas size.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Glyph {
+    pub charcode: i32,
+    pub area: (f32, f32, f32, f32),
+    pub size: (f32, f32),
+    pub horizontal_bearing: (f32, f32),
+    pub vertical_bearing: (f32, f32),
+    pub advance: (f32, f32),
+    pub kerning: Vec<KerningPair>,
+}
+
+impl Parsable for Glyph {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                le_i32,
+                tuple((le_f32, le_f32, le_f32, le_f32)),
+                tuple((le_f32, le_f32)),
+                tuple((le_f32, le_f32)),
+                tuple((le_f32, le_f32)),
+                tuple((le_f32, le_f32)),
+                length_count(le_u32, KerningPair::parse),
+            )),
+            |(charcode, area, size, horizontal_bearing, vertical_bearing, advance, kerning)| {
+                Glyph {
+                    charcode,
+                    area,
+                    size,
+                    horizontal_bearing,
+                    vertical_bearing,
+                    advance,
+                    kerning,
+                }
+            },
+        )(i)
+    }
+}
+
+impl Writable for Glyph {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_i32(self.charcode),
+            wh_tuple((
+                w_le_f32(self.area.0),
+                w_le_f32(self.area.1),
+                w_le_f32(self.area.2),
+                w_le_f32(self.area.3),
+            )),
+            wh_tuple((w_le_f32(self.size.0), w_le_f32(self.size.1))),
+            wh_tuple((
+                w_le_f32(self.horizontal_bearing.0),
+                w_le_f32(self.horizontal_bearing.1),
+            )),
+            wh_tuple((
+                w_le_f32(self.vertical_bearing.0),
+                w_le_f32(self.vertical_bearing.1),
+            )),
+            wh_tuple((w_le_f32(self.advance.0), w_le_f32(self.advance.1))),
+            w_le_u32(self.kerning.len() as u32),
+            wh_all(self.kerning.iter().map(|k| k.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct KerningPair {
+    pub second: i32,
+    pub amount: (f32, f32),
+}
+
+impl Parsable for KerningPair {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((le_i32, tuple((le_f32, le_f32)))),
+            |(second, amount)| KerningPair { second, amount },
+        )(i)
+    }
+}
+
+impl Writable for KerningPair {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_i32(self.second),
+            w_le_f32(self.amount.0),
+            w_le_f32(self.amount.1),
+        ));
+        Box::new(writer)
+    }
+}
+
+fn round(v: f32) -> i32 {
+    v.round() as i32
+}
+
+fn glyph_to_bmchar(glyph: &Glyph, metrics: &FontMetrics) -> BmChar {
+    BmChar {
+        id: glyph.charcode,
+        x: round(glyph.area.0 * metrics.image_size.0),
+        y: round(glyph.area.1 * metrics.image_size.1),
+        width: round(glyph.size.0),
+        height: round(glyph.size.1),
+        xoffset: round(glyph.horizontal_bearing.0),
+        yoffset: round(metrics.ascender - glyph.horizontal_bearing.1),
+        xadvance: round(glyph.advance.0),
+    }
+}
+
+pub fn glyphs_to_bmfont(
+    name: &str,
+    image_name: &str,
+    metrics: &FontMetrics,
+    glyphs: &[Glyph],
+) -> BmFont {
+    BmFont {
+        face: name.to_string(),
+        size: round(metrics.size_points),
+        line_height: round(metrics.height),
+        base: round(metrics.ascender),
+        scale_w: round(metrics.image_size.0),
+        scale_h: round(metrics.image_size.1),
+        page_file: format!(
+            "{}.png",
+            image_name.rsplit('/').next().unwrap_or(image_name)
+        ),
+        chars: glyphs.iter().map(|g| glyph_to_bmchar(g, metrics)).collect(),
+        kernings: glyphs
+            .iter()
+            .flat_map(|g| {
+                g.kerning.iter().map(|k| BmKerning {
+                    first: g.charcode,
+                    second: k.second,
+                    amount: round(k.amount.0),
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Replaces the glyphs with the ones in `font`. Glyphs, kerning pairs and metrics that would
+/// export to the same integer values are kept as they were, so an unedited import is lossless.
+pub fn apply_bmfont_to_glyphs(metrics: &mut FontMetrics, glyphs: &mut Vec<Glyph>, font: &BmFont) {
+    if round(metrics.ascender) != font.base {
+        metrics.ascender = font.base as f32;
+    }
+    if round(metrics.height) != font.line_height {
+        metrics.height = font.line_height as f32;
+    }
+    if round(metrics.size_points) != font.size {
+        metrics.size_points = font.size as f32;
+    }
+    if (round(metrics.image_size.0), round(metrics.image_size.1)) != (font.scale_w, font.scale_h) {
+        metrics.image_size = (font.scale_w as f32, font.scale_h as f32);
+    }
+
+    let (w, h) = metrics.image_size;
+    let new_glyphs = font
+        .chars
+        .iter()
+        .map(|c| {
+            let existing = glyphs.iter().find(|g| g.charcode == c.id);
+            let mut glyph = match existing {
+                Some(g) if glyph_to_bmchar(g, metrics) == *c => g.clone(),
+                _ => Glyph {
+                    charcode: c.id,
+                    area: (
+                        c.x as f32 / w,
+                        c.y as f32 / h,
+                        (c.x + c.width) as f32 / w,
+                        (c.y + c.height) as f32 / h,
+                    ),
+                    size: (c.width as f32, c.height as f32),
+                    horizontal_bearing: (c.xoffset as f32, metrics.ascender - c.yoffset as f32),
+                    vertical_bearing: existing
+                        .map(|g| g.vertical_bearing)
+                        .unwrap_or((-(c.width as f32) / 2.0, 0.0)),
+                    advance: (c.xadvance as f32, 0.0),
+                    kerning: vec![],
+                },
+            };
+
+            let old_kerning = std::mem::take(&mut glyph.kerning);
+            glyph.kerning = font
+                .kernings
+                .iter()
+                .filter(|k| k.first == c.id)
+                .map(|k| {
+                    old_kerning
+                        .iter()
+                        .find(|o| o.second == k.second && round(o.amount.0) == k.amount)
+                        .cloned()
+                        .unwrap_or(KerningPair {
+                            second: k.second,
+                            amount: (k.amount as f32, 0.0),
+                        })
+                })
+                .collect();
+            glyph
+        })
+        .collect();
+
+    *glyphs = new_glyphs;
+}
@@ -5,20 +5,35 @@ use super::{
         primitives::{wh_map, wh_pos_size, wh_string},
     },
     animation::Animation,
+    font::Font,
+    material::MaterialDefinition,
+    mesh::{Mesh, MeshManifest, MESH_GLTF_FILE},
+    mesh_animation::MeshAnimation,
+    shader::{ShaderFile, ShaderManifest},
     spritesheet::SpriteSheet,
+    variable_table::VariableTable,
 };
 use crate::halley::{
     assets::{
+        audio::{get_audio_clip_properties, get_audio_ext_from_path, sniff_audio_format},
+        gltf::{mesh_from_gltf, mesh_to_gltf},
         property_file,
-        serialization::{get_format_from_ext, get_serialization_ext_from_path},
+        schema::strip_schema_reference,
+        serialization::{
+            deserialize, get_format_from_ext, get_serialization_ext_from_path, Format,
+        },
+        unpack::UnpackOptions,
+        utils::get_companion_dir,
     },
     versions::common::{
-        config::{ConfigFile, ConfigNode},
+        config::{ConfigFile, ConfigNode, ConfigNodeMap},
         hpk::{
-            make_asset_type, pack_transform, unpack_transform, HalleyPackData, HalleyPackParseError,
+            make_asset_type, pack_transform, unpack_transform, write_to_vec, HalleyPackData,
+            HalleyPackParseError,
         },
     },
 };
+use anyhow::anyhow;
 use cookie_factory::{
     bytes::{le_i32 as w_le_i32, le_u32 as w_le_u32},
     multi::all as wh_all,
@@ -34,8 +49,13 @@ use nom::{
     IResult,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::{schema::RootSchema, schema_for};
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+/// Version of the config files in V2020 packs, which always store node positions.
+pub const CONFIG_VERSION: i32 = 2;
+
 pub struct HalleyPackV2020 {}
 
 impl HalleyPackV2020 {
@@ -65,6 +85,19 @@ pub enum AssetTypeV2020 {
 }
 pub type HpkPropertiesV2020 = IndexMap<String, String>;
 
+/// Contents of a V2020 property file, the stored properties followed by the derived ones.
+#[derive(Serialize, Deserialize, Debug)]
+struct PropertyFileV2020 {
+    #[serde(flatten)]
+    properties: HpkPropertiesV2020,
+    #[serde(
+        rename = "$derived",
+        default,
+        skip_serializing_if = "ConfigNodeMap::is_empty"
+    )]
+    derived: ConfigNodeMap,
+}
+
 #[derive(Debug)]
 pub struct HpkSectionV2020
 where
@@ -87,6 +120,10 @@ impl HpkSection for HpkSectionV2020 {
         num_traits::ToPrimitive::to_i32(&self.asset_type).unwrap()
     }
 
+    fn asset_type_name(&self) -> String {
+        format!("{:?}", self.asset_type)
+    }
+
     fn assets(&self) -> Vec<Box<&dyn HpkAsset>> {
         self.assets
             .iter()
@@ -94,16 +131,26 @@ impl HpkSection for HpkSectionV2020 {
             .collect()
     }
 
+    fn assets_mut(&mut self) -> Vec<&mut dyn HpkAsset> {
+        self.assets
+            .iter_mut()
+            .map(|a| a as &mut dyn HpkAsset)
+            .collect()
+    }
+
     fn add_asset(
         &mut self,
         pack: &mut dyn HalleyPack,
         path: &Path,
         relative_path: &Path,
     ) -> Result<(), anyhow::Error> {
-        let (properties, data) = property_file::read_with_file_data::<HpkPropertiesV2020>(path)?;
+        let (PropertyFileV2020 { properties, .. }, data) =
+            property_file::read_with_file_data(path)?;
 
-        let serialization_ext = get_serialization_ext_from_path(path);
-        let data = self.modify_data_on_repack(&data, serialization_ext)?;
+        let serialization_ext = self.get_data_ext_from_path(path);
+        let companion_dir = get_companion_dir(path, serialization_ext);
+        let data = strip_schema_reference(&data, serialization_ext);
+        let data = self.modify_data_on_repack(&data, serialization_ext, &companion_dir)?;
 
         let name = self.get_asset_name(relative_path, serialization_ext);
 
@@ -131,37 +178,151 @@ impl HpkSectionUnpackable for HpkSectionV2020 {
             AssetTypeV2020::SPRITESHEET => ".sheet",
             AssetTypeV2020::ANIMATION => ".anim",
             AssetTypeV2020::CONFIG => ".config",
+            AssetTypeV2020::MATERIAL => ".material",
+            AssetTypeV2020::FONT => ".font",
+            AssetTypeV2020::MESH => ".mesh",
+            AssetTypeV2020::MESHANIMATION => ".meshanim",
+            AssetTypeV2020::VARIABLETABLE => ".variables",
+            AssetTypeV2020::SHADER => ".shader",
             _ => "",
         }
     }
 
-    fn modify_data_on_unpack(&self, i: &[u8]) -> Result<(Vec<u8>, &str), anyhow::Error> {
+    fn modify_data_on_unpack(
+        &self,
+        i: &[u8],
+        format: Option<Format>,
+    ) -> Result<(Vec<u8>, &'static str), anyhow::Error> {
         match self.asset_type {
-            AssetTypeV2020::SPRITESHEET => unpack_transform::<SpriteSheet, SpriteSheet>(i, None),
-            AssetTypeV2020::ANIMATION => unpack_transform::<Animation, Animation>(i, None),
+            AssetTypeV2020::SPRITESHEET => {
+                unpack_transform::<SpriteSheet, SpriteSheet>(i, None, format)
+            }
+            AssetTypeV2020::ANIMATION => unpack_transform::<Animation, Animation>(i, None, format),
             AssetTypeV2020::CONFIG => {
-                unpack_transform::<ConfigFile, ConfigNode>(i, Some(|c| c.root))
+                unpack_transform::<ConfigFile, ConfigNode>(i, Some(|c| c.root), format)
+            }
+            AssetTypeV2020::MATERIAL => {
+                unpack_transform::<MaterialDefinition, MaterialDefinition>(i, None, format)
+            }
+            AssetTypeV2020::FONT => unpack_transform::<Font, Font>(i, None, format),
+            AssetTypeV2020::MESH => {
+                unpack_transform::<Mesh, MeshManifest>(i, Some(MeshManifest::from), format)
+            }
+            AssetTypeV2020::MESHANIMATION => {
+                unpack_transform::<MeshAnimation, MeshAnimation>(i, None, format)
             }
+            AssetTypeV2020::VARIABLETABLE => {
+                unpack_transform::<VariableTable, VariableTable>(i, None, format)
+            }
+            AssetTypeV2020::SHADER => unpack_transform::<ShaderFile, ShaderManifest>(
+                i,
+                Some(ShaderManifest::from),
+                format,
+            ),
+            AssetTypeV2020::AUDIOCLIP => Ok((i.into(), sniff_audio_format(i).ext())),
             _ => Ok((i.into(), "")),
         }
     }
 
-    fn modify_data_on_repack(&self, i: &[u8], ext: &str) -> Result<Vec<u8>, anyhow::Error> {
+    fn get_derived_properties(&self, i: &[u8]) -> ConfigNodeMap {
+        match self.asset_type {
+            AssetTypeV2020::AUDIOCLIP => get_audio_clip_properties(i),
+            _ => ConfigNodeMap::new(),
+        }
+    }
+
+    fn get_data_ext_from_path(&self, path: &Path) -> &'static str {
+        match self.asset_type {
+            AssetTypeV2020::AUDIOCLIP => get_audio_ext_from_path(path),
+            _ => get_serialization_ext_from_path(path),
+        }
+    }
+
+    fn get_schema(&self) -> Option<RootSchema> {
+        let schema = match self.asset_type {
+            AssetTypeV2020::SPRITESHEET => schema_for!(SpriteSheet),
+            AssetTypeV2020::ANIMATION => schema_for!(Animation),
+            AssetTypeV2020::CONFIG => schema_for!(ConfigNode),
+            AssetTypeV2020::MATERIAL => schema_for!(MaterialDefinition),
+            AssetTypeV2020::FONT => schema_for!(Font),
+            AssetTypeV2020::MESH => schema_for!(MeshManifest),
+            AssetTypeV2020::MESHANIMATION => schema_for!(MeshAnimation),
+            AssetTypeV2020::VARIABLETABLE => schema_for!(VariableTable),
+            AssetTypeV2020::SHADER => schema_for!(ShaderManifest),
+            _ => return None,
+        };
+        Some(schema)
+    }
+
+    fn get_properties_schema(&self) -> RootSchema {
+        schema_for!(HpkPropertiesV2020)
+    }
+
+    fn get_companion_files(
+        &self,
+        i: &[u8],
+        options: &UnpackOptions,
+    ) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        match self.asset_type {
+            AssetTypeV2020::CONFIG if options.config_positions => {
+                let (_, file) = ConfigFile::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                file.get_position_files()
+            }
+            AssetTypeV2020::SHADER => {
+                let (_, shader) = ShaderFile::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                shader.get_stage_files()
+            }
+            AssetTypeV2020::MESH => {
+                let (_, mesh) = Mesh::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                Ok(vec![(MESH_GLTF_FILE.to_string(), mesh_to_gltf(&mesh)?)])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn modify_data_on_repack(
+        &self,
+        i: &[u8],
+        ext: &str,
+        companion_dir: &Path,
+    ) -> Result<Vec<u8>, anyhow::Error> {
         let format = get_format_from_ext(ext);
         match self.asset_type {
+            AssetTypeV2020::SHADER => {
+                let manifest: ShaderManifest = deserialize(std::str::from_utf8(i)?, format)?;
+                let shader = ShaderFile::from_manifest(manifest, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&shader)
+            }
+            AssetTypeV2020::MESH => {
+                let manifest: MeshManifest = deserialize(std::str::from_utf8(i)?, format)?;
+                let mesh = mesh_from_gltf(manifest, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&mesh)
+            }
             AssetTypeV2020::SPRITESHEET => {
                 pack_transform::<SpriteSheet, SpriteSheet>(i, format, None)
             }
             AssetTypeV2020::ANIMATION => pack_transform::<Animation, Animation>(i, format, None),
-            AssetTypeV2020::CONFIG => pack_transform::<ConfigFile, ConfigNode>(
-                i,
-                format,
-                Some(|t| ConfigFile {
-                    v: 2,
-                    store_file_position: true,
-                    root: t,
-                }),
-            ),
+            AssetTypeV2020::CONFIG => {
+                let root: ConfigNode = deserialize(std::str::from_utf8(i)?, format)?;
+                let file = ConfigFile::from_unpacked(CONFIG_VERSION, root, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&file)
+            }
+            AssetTypeV2020::MATERIAL => {
+                pack_transform::<MaterialDefinition, MaterialDefinition>(i, format, None)
+            }
+            AssetTypeV2020::FONT => pack_transform::<Font, Font>(i, format, None),
+            AssetTypeV2020::MESHANIMATION => {
+                pack_transform::<MeshAnimation, MeshAnimation>(i, format, None)
+            }
+            AssetTypeV2020::VARIABLETABLE => {
+                pack_transform::<VariableTable, VariableTable>(i, format, None)
+            }
             _ => Ok(i.into()),
         }
     }
@@ -221,8 +382,17 @@ impl HpkAsset for HpkAssetV2020 {
         self.size = size;
     }
 
-    fn serialize_properties(&self, filename: &std::path::Path) -> Result<(), anyhow::Error> {
-        super::super::super::assets::property_file::write(filename, &self.properties)
+    fn serialize_properties(
+        &self,
+        filename: &std::path::Path,
+        derived: &ConfigNodeMap,
+        format: Option<Format>,
+    ) -> Result<(), anyhow::Error> {
+        let file = PropertyFileV2020 {
+            properties: self.properties.clone(),
+            derived: derived.clone(),
+        };
+        super::super::super::assets::property_file::write(filename, &file, format)
     }
 
     fn get_asset_compression(&self) -> Option<String> {
@@ -234,6 +404,15 @@ impl HpkAsset for HpkAssetV2020 {
     fn get_compression(&self) -> Option<String> {
         self.properties.get("compression").map(|s| s.to_owned())
     }
+
+    fn get_properties(&self) -> ConfigNode {
+        ConfigNode::Map(
+            self.properties
+                .iter()
+                .map(|(k, v)| (k.to_owned(), ConfigNode::String(v.to_owned())))
+                .collect(),
+        )
+    }
 }
 
 impl Parsable for HpkAssetV2020 {
@@ -260,3 +439,22 @@ impl Writable for HpkAssetV2020 {
         Box::new(writer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::assets::serialization::serialize;
+
+    #[test]
+    fn test_property_file_drops_derived_properties() {
+        let file = PropertyFileV2020 {
+            properties: HpkPropertiesV2020::from([("channels".to_string(), "1".to_string())]),
+            derived: ConfigNodeMap::from([("channels".to_string(), ConfigNode::Int(2))]),
+        };
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let text = serialize(&file, Some(format)).unwrap();
+            let read: PropertyFileV2020 = deserialize(&text, Some(format)).unwrap();
+            assert_eq!(read.properties, file.properties);
+        }
+    }
+}
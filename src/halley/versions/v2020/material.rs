@@ -0,0 +1,412 @@
+use super::super::common::{
+    hpk::{Parsable, Writable},
+    primitives::{h_bool, h_enum, h_string, wh_bool, wh_enum, wh_string},
+};
+use cookie_factory::{
+    bytes::{le_i32 as w_le_i32, le_u32 as w_le_u32, le_u8 as w_le_u8},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_i32, le_u32, u8},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialDefinition {
+    pub name: String,
+    pub passes: Vec<MaterialPass>,
+    pub uniform_blocks: Vec<MaterialUniformBlock>,
+    pub attributes: Vec<MaterialAttribute>,
+    pub vertex_size: i32,
+    pub vertex_pos_offset: i32,
+    pub textures: Vec<String>,
+}
+
+impl MaterialDefinition {
+    pub fn shader_references(&self) -> Vec<&String> {
+        self.passes.iter().map(|p| &p.shader_asset_id).collect()
+    }
+}
+
+impl Parsable for MaterialDefinition {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                length_count(le_u32, MaterialPass::parse),
+                length_count(le_u32, MaterialUniformBlock::parse),
+                length_count(le_u32, MaterialAttribute::parse),
+                le_i32,
+                le_i32,
+                length_count(le_u32, h_string),
+            )),
+            |(
+                name,
+                passes,
+                uniform_blocks,
+                attributes,
+                vertex_size,
+                vertex_pos_offset,
+                textures,
+            )| {
+                MaterialDefinition {
+                    name,
+                    passes,
+                    uniform_blocks,
+                    attributes,
+                    vertex_size,
+                    vertex_pos_offset,
+                    textures,
+                }
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialDefinition {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_u32(self.passes.len() as u32),
+            wh_all(self.passes.iter().map(|p| p.write())),
+            w_le_u32(self.uniform_blocks.len() as u32),
+            wh_all(self.uniform_blocks.iter().map(|b| b.write())),
+            w_le_u32(self.attributes.len() as u32),
+            wh_all(self.attributes.iter().map(|a| a.write())),
+            w_le_i32(self.vertex_size),
+            w_le_i32(self.vertex_pos_offset),
+            w_le_u32(self.textures.len() as u32),
+            wh_all(self.textures.iter().map(wh_string)),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum BlendType {
+    Undefined = 0,
+    Opaque = 1,
+    Alpha,
+    AlphaPremultiplied,
+    Add,
+    AddPremultiplied,
+    Multiply,
+    Darken,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum CullingMode {
+    None = 0,
+    Front = 1,
+    Back,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum DepthStencilComparisonFunction {
+    Never = 0,
+    Less = 1,
+    Greater,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Always,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum StencilWriteOperation {
+    Keep = 0,
+    Zero = 1,
+    Replace,
+    IncrementClamp,
+    IncrementWrap,
+    DecrementClamp,
+    DecrementWrap,
+    Invert,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum ShaderParameterType {
+    Float = 0,
+    Float2 = 1,
+    Float3,
+    Float4,
+    Int,
+    Int2,
+    Int3,
+    Int4,
+    Matrix2,
+    Matrix3,
+    Matrix4,
+    Texture2D,
+    Invalid,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialPass {
+    pub blend: BlendType,
+    pub shader_asset_id: String,
+    pub cull: CullingMode,
+    pub depth_stencil: MaterialDepthStencil,
+}
+
+impl Parsable for MaterialPass {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_enum, h_string, h_enum, MaterialDepthStencil::parse)),
+            |(blend, shader_asset_id, cull, depth_stencil)| MaterialPass {
+                blend,
+                shader_asset_id,
+                cull,
+                depth_stencil,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialPass {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_enum(&self.blend),
+            wh_string(&self.shader_asset_id),
+            wh_enum(&self.cull),
+            self.depth_stencil.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialDepthStencil {
+    pub enable_depth_test: bool,
+    pub enable_depth_write: bool,
+    pub enable_stencil_test: bool,
+    pub stencil_reference: i32,
+    pub stencil_write_mask: u8,
+    pub stencil_read_mask: u8,
+    pub depth_comparison: DepthStencilComparisonFunction,
+    pub stencil_comparison: DepthStencilComparisonFunction,
+    pub stencil_op_pass: StencilWriteOperation,
+    pub stencil_op_depth_fail: StencilWriteOperation,
+    pub stencil_op_stencil_fail: StencilWriteOperation,
+}
+
+impl Parsable for MaterialDepthStencil {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                tuple((h_bool, h_bool, h_bool)),
+                tuple((le_i32, u8, u8)),
+                tuple((h_enum, h_enum)),
+                tuple((h_enum, h_enum, h_enum)),
+            )),
+            |(
+                (enable_depth_test, enable_depth_write, enable_stencil_test),
+                (stencil_reference, stencil_write_mask, stencil_read_mask),
+                (depth_comparison, stencil_comparison),
+                (stencil_op_pass, stencil_op_depth_fail, stencil_op_stencil_fail),
+            )| MaterialDepthStencil {
+                enable_depth_test,
+                enable_depth_write,
+                enable_stencil_test,
+                stencil_reference,
+                stencil_write_mask,
+                stencil_read_mask,
+                depth_comparison,
+                stencil_comparison,
+                stencil_op_pass,
+                stencil_op_depth_fail,
+                stencil_op_stencil_fail,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialDepthStencil {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_tuple((
+                wh_bool(self.enable_depth_test),
+                wh_bool(self.enable_depth_write),
+                wh_bool(self.enable_stencil_test),
+            )),
+            wh_tuple((
+                w_le_i32(self.stencil_reference),
+                w_le_u8(self.stencil_write_mask),
+                w_le_u8(self.stencil_read_mask),
+            )),
+            wh_tuple((
+                wh_enum(&self.depth_comparison),
+                wh_enum(&self.stencil_comparison),
+            )),
+            wh_tuple((
+                wh_enum(&self.stencil_op_pass),
+                wh_enum(&self.stencil_op_depth_fail),
+                wh_enum(&self.stencil_op_stencil_fail),
+            )),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialUniformBlock {
+    pub name: String,
+    pub uniforms: Vec<MaterialUniform>,
+}
+
+impl Parsable for MaterialUniformBlock {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, length_count(le_u32, MaterialUniform::parse))),
+            |(name, uniforms)| MaterialUniformBlock { name, uniforms },
+        )(i)
+    }
+}
+
+impl Writable for MaterialUniformBlock {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_u32(self.uniforms.len() as u32),
+            wh_all(self.uniforms.iter().map(|u| u.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialUniform {
+    pub name: String,
+    pub uniform_type: ShaderParameterType,
+}
+
+impl Parsable for MaterialUniform {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((h_string, h_enum)), |(name, uniform_type)| {
+            MaterialUniform { name, uniform_type }
+        })(i)
+    }
+}
+
+impl Writable for MaterialUniform {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        Box::new(wh_tuple((
+            wh_string(&self.name),
+            wh_enum(&self.uniform_type),
+        )))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialAttribute {
+    pub name: String,
+    pub attribute_type: ShaderParameterType,
+    pub location: i32,
+    pub offset: i32,
+    pub semantic: String,
+    pub semantic_index: i32,
+}
+
+impl Parsable for MaterialAttribute {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, h_enum, le_i32, le_i32, h_string, le_i32)),
+            |(name, attribute_type, location, offset, semantic, semantic_index)| {
+                MaterialAttribute {
+                    name,
+                    attribute_type,
+                    location,
+                    offset,
+                    semantic,
+                    semantic_index,
+                }
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialAttribute {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_enum(&self.attribute_type),
+            w_le_i32(self.location),
+            w_le_i32(self.offset),
+            wh_string(&self.semantic),
+            w_le_i32(self.semantic_index),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::hpk::write_to_vec,
+    };
+
+    #[test]
+    fn test_material_round_trip() {
+        let material = MaterialDefinition {
+            name: "sprite".to_string(),
+            passes: vec![MaterialPass {
+                blend: BlendType::AlphaPremultiplied,
+                shader_asset_id: "shaders/sprite".to_string(),
+                cull: CullingMode::Back,
+                depth_stencil: MaterialDepthStencil {
+                    enable_depth_test: true,
+                    enable_depth_write: false,
+                    enable_stencil_test: true,
+                    stencil_reference: -3,
+                    stencil_write_mask: 0xf0,
+                    stencil_read_mask: 0x0f,
+                    depth_comparison: DepthStencilComparisonFunction::LessEqual,
+                    stencil_comparison: DepthStencilComparisonFunction::Always,
+                    stencil_op_pass: StencilWriteOperation::Replace,
+                    stencil_op_depth_fail: StencilWriteOperation::Keep,
+                    stencil_op_stencil_fail: StencilWriteOperation::Invert,
+                },
+            }],
+            uniform_blocks: vec![MaterialUniformBlock {
+                name: "MaterialBlock".to_string(),
+                uniforms: vec![MaterialUniform {
+                    name: "u_col".to_string(),
+                    uniform_type: ShaderParameterType::Float4,
+                }],
+            }],
+            attributes: vec![MaterialAttribute {
+                name: "a_position".to_string(),
+                attribute_type: ShaderParameterType::Float2,
+                location: 0,
+                offset: 8,
+                semantic: "POSITION".to_string(),
+                semantic_index: 1,
+            }],
+            vertex_size: 16,
+            vertex_pos_offset: 8,
+            textures: vec!["tex0".to_string()],
+        };
+
+        let bytes = write_to_vec(&material).unwrap();
+        let (rest, parsed) = MaterialDefinition::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: MaterialDefinition = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
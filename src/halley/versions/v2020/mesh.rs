@@ -0,0 +1,259 @@
+use super::{
+    super::common::{
+        hpk::{Parsable, Writable},
+        primitives::{h_enum, h_string, wh_enum, wh_string},
+    },
+    material::{MaterialAttribute, ShaderParameterType},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u16 as w_le_u16, le_u32 as w_le_u32},
+    combinator::slice as wh_slice,
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::{length_count, length_data},
+    number::complete::{le_f32, le_i32, le_u16, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Name of the glTF companion file holding the geometry of an unpacked mesh.
+pub static MESH_GLTF_FILE: &str = "mesh.gltf";
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Mesh {
+    pub parts: Vec<MeshPart>,
+    pub bounds: MeshBounds,
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, JsonSchema,
+)]
+pub enum PrimitiveType {
+    Undefined = 0,
+    Point = 1,
+    Line,
+    Triangle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, JsonSchema)]
+pub struct MeshBounds {
+    pub min: (f32, f32, f32),
+    pub max: (f32, f32, f32),
+}
+
+/// `vertex_data` holds `num_vertices` interleaved vertices of `vertex_size` bytes, laid out
+/// as described by `vertex_attributes`.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MeshPart {
+    pub material_name: String,
+    pub textures: Vec<String>,
+    pub primitive_type: PrimitiveType,
+    pub vertex_size: i32,
+    pub vertex_attributes: Vec<MaterialAttribute>,
+    pub num_vertices: u32,
+    pub vertex_data: Vec<u8>,
+    pub indices: Vec<u16>,
+}
+
+impl Parsable for Mesh {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((length_count(le_u32, MeshPart::parse), MeshBounds::parse)),
+            |(parts, bounds)| Mesh { parts, bounds },
+        )(i)
+    }
+}
+
+impl Writable for Mesh {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.parts.len() as u32),
+            wh_all(self.parts.iter().map(|p| p.write())),
+            self.bounds.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for MeshBounds {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                tuple((le_f32, le_f32, le_f32)),
+                tuple((le_f32, le_f32, le_f32)),
+            )),
+            |(min, max)| MeshBounds { min, max },
+        )(i)
+    }
+}
+
+impl Writable for MeshBounds {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_f32(self.min.0),
+            w_le_f32(self.min.1),
+            w_le_f32(self.min.2),
+            w_le_f32(self.max.0),
+            w_le_f32(self.max.1),
+            w_le_f32(self.max.2),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for MeshPart {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                length_count(le_u32, h_string),
+                h_enum,
+                le_i32,
+                length_count(le_u32, MaterialAttribute::parse),
+                le_u32,
+                length_data(le_u32),
+                length_count(le_u32, le_u16),
+            )),
+            |(
+                material_name,
+                textures,
+                primitive_type,
+                vertex_size,
+                vertex_attributes,
+                num_vertices,
+                vertex_data,
+                indices,
+            )| MeshPart {
+                material_name,
+                textures,
+                primitive_type,
+                vertex_size,
+                vertex_attributes,
+                num_vertices,
+                vertex_data: vertex_data.to_vec(),
+                indices,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MeshPart {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.material_name),
+            w_le_u32(self.textures.len() as u32),
+            wh_all(self.textures.iter().map(wh_string)),
+            wh_enum(&self.primitive_type),
+            w_le_i32(self.vertex_size),
+            w_le_u32(self.vertex_attributes.len() as u32),
+            wh_all(self.vertex_attributes.iter().map(|a| a.write())),
+            w_le_u32(self.num_vertices),
+            w_le_u32(self.vertex_data.len() as u32),
+            wh_slice(&self.vertex_data),
+            w_le_u32(self.indices.len() as u32),
+            wh_all(self.indices.iter().map(|i| w_le_u16(*i))),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl MeshPart {
+    /// Reads the components of `attribute` for every vertex, one vertex after the other.
+    pub fn read_attribute(&self, attribute: &MaterialAttribute) -> Result<Vec<f64>, anyhow::Error> {
+        let (components, is_int) = get_attribute_components(&attribute.attribute_type);
+        let mut values = Vec::with_capacity(self.num_vertices as usize * components);
+        for v in 0..self.num_vertices as usize {
+            for c in 0..components {
+                let pos = v * self.vertex_size as usize + attribute.offset as usize + c * 4;
+                let bytes: [u8; 4] = self
+                    .vertex_data
+                    .get(pos..pos + 4)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Attribute {} is outside the vertex data", attribute.name)
+                    })?;
+                values.push(match is_int {
+                    true => i32::from_le_bytes(bytes) as f64,
+                    false => f32::from_le_bytes(bytes) as f64,
+                });
+            }
+        }
+        Ok(values)
+    }
+
+    /// Writes the values of `attribute` for every vertex, the inverse of `read_attribute`.
+    pub fn write_attribute(&mut self, attribute: &MaterialAttribute, values: &[f64]) {
+        let (components, is_int) = get_attribute_components(&attribute.attribute_type);
+        for (n, value) in values.iter().enumerate() {
+            let (v, c) = (n / components, n % components);
+            let pos = v * self.vertex_size as usize + attribute.offset as usize + c * 4;
+            let bytes = match is_int {
+                true => (*value as i64 as i32).to_le_bytes(),
+                false => (*value as f32).to_le_bytes(),
+            };
+            self.vertex_data[pos..pos + 4].copy_from_slice(&bytes);
+        }
+    }
+}
+
+/// Number of 32 bit components of a vertex attribute, and whether they are integers.
+pub fn get_attribute_components(attribute_type: &ShaderParameterType) -> (usize, bool) {
+    match attribute_type {
+        ShaderParameterType::Float => (1, false),
+        ShaderParameterType::Float2 => (2, false),
+        ShaderParameterType::Float3 => (3, false),
+        ShaderParameterType::Float4 => (4, false),
+        ShaderParameterType::Int => (1, true),
+        ShaderParameterType::Int2 => (2, true),
+        ShaderParameterType::Int3 => (3, true),
+        ShaderParameterType::Int4 => (4, true),
+        ShaderParameterType::Matrix2 => (4, false),
+        ShaderParameterType::Matrix3 => (9, false),
+        ShaderParameterType::Matrix4 => (16, false),
+        ShaderParameterType::Texture2D | ShaderParameterType::Invalid => (0, false),
+    }
+}
+
+/// Unpacked form of a `Mesh`, with the geometry of every part stored in a glTF file.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MeshManifest {
+    pub file: String,
+    pub parts: Vec<MeshPartManifest>,
+    pub bounds: MeshBounds,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MeshPartManifest {
+    pub material_name: String,
+    pub textures: Vec<String>,
+    pub primitive_type: PrimitiveType,
+    pub vertex_size: i32,
+    pub vertex_attributes: Vec<MaterialAttribute>,
+}
+
+impl From<Mesh> for MeshManifest {
+    fn from(mesh: Mesh) -> Self {
+        MeshManifest {
+            file: MESH_GLTF_FILE.to_string(),
+            parts: mesh
+                .parts
+                .into_iter()
+                .map(|p| MeshPartManifest {
+                    material_name: p.material_name,
+                    textures: p.textures,
+                    primitive_type: p.primitive_type,
+                    vertex_size: p.vertex_size,
+                    vertex_attributes: p.vertex_attributes,
+                })
+                .collect(),
+            bounds: mesh.bounds,
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use super::super::common::{
+    hpk::{Parsable, Writable},
+    primitives::{h_enum, h_string, wh_enum, wh_string},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u32 as w_le_u32},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MeshAnimation {
+    pub name: String,
+    pub duration: f32,
+    pub bones: Vec<Bone>,
+    pub tracks: Vec<AnimationTrack>,
+}
+
+/// Bind pose of a bone, relative to its parent. Root bones have a `parent` of -1, and
+/// `rotation` is a quaternion stored as x, y, z, w.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Bone {
+    pub name: String,
+    pub parent: i32,
+    pub translation: (f32, f32, f32),
+    pub rotation: (f32, f32, f32, f32),
+    pub scale: (f32, f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, Clone, Copy, JsonSchema)]
+pub enum TrackTarget {
+    Translation = 0,
+    Rotation = 1,
+    Scale,
+    Weights,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, Clone, Copy, JsonSchema)]
+pub enum TrackInterpolation {
+    Step = 0,
+    Linear = 1,
+    CubicSpline,
+}
+
+/// Keyframes of one bone property. `Weights` tracks animate the morph targets of the mesh, and
+/// have one value per target in each keyframe. `CubicSpline` keyframes hold the in-tangent,
+/// value and out-tangent one after the other.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AnimationTrack {
+    pub bone: i32,
+    pub target: TrackTarget,
+    pub interpolation: TrackInterpolation,
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: Vec<f32>,
+}
+
+impl Parsable for MeshAnimation {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                le_f32,
+                length_count(le_u32, Bone::parse),
+                length_count(le_u32, AnimationTrack::parse),
+            )),
+            |(name, duration, bones, tracks)| MeshAnimation {
+                name,
+                duration,
+                bones,
+                tracks,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MeshAnimation {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_f32(self.duration),
+            w_le_u32(self.bones.len() as u32),
+            wh_all(self.bones.iter().map(|b| b.write())),
+            w_le_u32(self.tracks.len() as u32),
+            wh_all(self.tracks.iter().map(|t| t.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for Bone {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                le_i32,
+                tuple((le_f32, le_f32, le_f32)),
+                tuple((le_f32, le_f32, le_f32, le_f32)),
+                tuple((le_f32, le_f32, le_f32)),
+            )),
+            |(name, parent, translation, rotation, scale)| Bone {
+                name,
+                parent,
+                translation,
+                rotation,
+                scale,
+            },
+        )(i)
+    }
+}
+
+impl Writable for Bone {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_i32(self.parent),
+            wh_tuple((
+                w_le_f32(self.translation.0),
+                w_le_f32(self.translation.1),
+                w_le_f32(self.translation.2),
+            )),
+            wh_tuple((
+                w_le_f32(self.rotation.0),
+                w_le_f32(self.rotation.1),
+                w_le_f32(self.rotation.2),
+                w_le_f32(self.rotation.3),
+            )),
+            wh_tuple((
+                w_le_f32(self.scale.0),
+                w_le_f32(self.scale.1),
+                w_le_f32(self.scale.2),
+            )),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for AnimationTrack {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                le_i32,
+                h_enum,
+                h_enum,
+                length_count(le_u32, Keyframe::parse),
+            )),
+            |(bone, target, interpolation, keyframes)| AnimationTrack {
+                bone,
+                target,
+                interpolation,
+                keyframes,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AnimationTrack {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_i32(self.bone),
+            wh_enum(&self.target),
+            wh_enum(&self.interpolation),
+            w_le_u32(self.keyframes.len() as u32),
+            wh_all(self.keyframes.iter().map(|k| k.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for Keyframe {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((le_f32, length_count(le_u32, le_f32))),
+            |(time, value)| Keyframe { time, value },
+        )(i)
+    }
+}
+
+impl Writable for Keyframe {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_f32(self.time),
+            w_le_u32(self.value.len() as u32),
+            wh_all(self.value.iter().map(|v| w_le_f32(*v))),
+        ));
+        Box::new(writer)
+    }
+}
+
+/// Keyframes of every track merged in time order, for reading an animation without a mesh.
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct MeshAnimationTimeline<'a> {
+    pub name: &'a str,
+    pub duration: f32,
+    pub bones: &'a [Bone],
+    pub timeline: Vec<TimelineEntry<'a>>,
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct TimelineEntry<'a> {
+    pub time: f32,
+    pub bone: &'a str,
+    pub target: TrackTarget,
+    pub interpolation: TrackInterpolation,
+    pub value: &'a [f32],
+}
+
+impl MeshAnimation {
+    pub fn bone_name(&self, bone: i32) -> &str {
+        usize::try_from(bone)
+            .ok()
+            .and_then(|b| self.bones.get(b))
+            .map_or("", |b| &b.name)
+    }
+
+    pub fn to_timeline(&self) -> MeshAnimationTimeline<'_> {
+        let mut timeline: Vec<TimelineEntry> = self
+            .tracks
+            .iter()
+            .flat_map(|t| {
+                t.keyframes.iter().map(|k| TimelineEntry {
+                    time: k.time,
+                    bone: self.bone_name(t.bone),
+                    target: t.target,
+                    interpolation: t.interpolation,
+                    value: &k.value,
+                })
+            })
+            .collect();
+        timeline.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        MeshAnimationTimeline {
+            name: &self.name,
+            duration: self.duration,
+            bones: &self.bones,
+            timeline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::hpk::write_to_vec,
+    };
+
+    #[test]
+    fn test_mesh_animation_round_trip() {
+        let bone = |name: &str, parent| Bone {
+            name: name.to_string(),
+            parent,
+            translation: (0.1, -2.5, 1.0 / 3.0),
+            rotation: (0.0, 0.70710677, 0.0, 0.70710677),
+            scale: (1.0, 1.0, 1e-7),
+        };
+        let animation = MeshAnimation {
+            name: "walk".to_string(),
+            duration: 1.25,
+            bones: vec![bone("root", -1), bone("leg", 0)],
+            tracks: vec![
+                AnimationTrack {
+                    bone: 1,
+                    target: TrackTarget::Rotation,
+                    interpolation: TrackInterpolation::Linear,
+                    keyframes: vec![
+                        Keyframe {
+                            time: 0.0,
+                            value: vec![0.0, 0.0, 0.0, 1.0],
+                        },
+                        Keyframe {
+                            time: 0.6,
+                            value: vec![0.1, 0.2, 0.3, 0.9273618],
+                        },
+                    ],
+                },
+                AnimationTrack {
+                    bone: 0,
+                    target: TrackTarget::Weights,
+                    interpolation: TrackInterpolation::Step,
+                    keyframes: vec![Keyframe {
+                        time: 0.3,
+                        value: vec![],
+                    }],
+                },
+            ],
+        };
+        assert_eq!(
+            animation
+                .to_timeline()
+                .timeline
+                .iter()
+                .map(|e| e.bone)
+                .collect::<Vec<_>>(),
+            vec!["leg", "root", "leg"]
+        );
+
+        let bytes = write_to_vec(&animation).unwrap();
+        let (rest, parsed) = MeshAnimation::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: MeshAnimation = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
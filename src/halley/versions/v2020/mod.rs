@@ -1,3 +1,9 @@
 pub mod animation;
+pub mod font;
 pub mod hpk;
+pub mod material;
+pub mod mesh;
+pub mod mesh_animation;
+pub mod shader;
 pub mod spritesheet;
+pub mod variable_table;
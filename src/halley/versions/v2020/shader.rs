@@ -0,0 +1,293 @@
+use super::{
+    super::common::{
+        hpk::{Parsable, Writable},
+        primitives::{h_enum, h_string, wh_enum, wh_string},
+    },
+    material::MaterialAttribute,
+};
+use crate::halley::assets::utils::pathify_component;
+use anyhow::anyhow;
+use cookie_factory::{
+    bytes::le_u32 as w_le_u32, combinator::slice as wh_slice, multi::all as wh_all,
+    sequence::tuple as wh_tuple, SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::{length_count, length_data},
+    number::complete::le_u32,
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, Clone, Copy, JsonSchema)]
+pub enum ShaderStage {
+    Vertex = 0,
+    Pixel = 1,
+    Geometry,
+    Combined,
+    Compute,
+}
+
+impl ShaderStage {
+    fn file_name(&self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vertex",
+            ShaderStage::Pixel => "pixel",
+            ShaderStage::Geometry => "geometry",
+            ShaderStage::Combined => "combined",
+            ShaderStage::Compute => "compute",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderFile {
+    pub variants: Vec<ShaderVariant>,
+    pub vertex_attributes: Vec<MaterialAttribute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderVariant {
+    pub backend: String,
+    pub stages: Vec<ShaderStageCode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderStageCode {
+    pub stage: ShaderStage,
+    pub code: Vec<u8>,
+}
+
+impl Parsable for ShaderFile {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                length_count(le_u32, ShaderVariant::parse),
+                length_count(le_u32, MaterialAttribute::parse),
+            )),
+            |(variants, vertex_attributes)| ShaderFile {
+                variants,
+                vertex_attributes,
+            },
+        )(i)
+    }
+}
+
+impl Writable for ShaderFile {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.variants.len() as u32),
+            wh_all(self.variants.iter().map(|v| v.write())),
+            w_le_u32(self.vertex_attributes.len() as u32),
+            wh_all(self.vertex_attributes.iter().map(|a| a.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for ShaderVariant {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, length_count(le_u32, ShaderStageCode::parse))),
+            |(backend, stages)| ShaderVariant { backend, stages },
+        )(i)
+    }
+}
+
+impl Writable for ShaderVariant {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.backend),
+            w_le_u32(self.stages.len() as u32),
+            wh_all(self.stages.iter().map(|s| s.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for ShaderStageCode {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((h_enum, length_data(le_u32))), |(stage, code)| {
+            ShaderStageCode {
+                stage,
+                code: code.to_vec(),
+            }
+        })(i)
+    }
+}
+
+impl Writable for ShaderStageCode {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_enum(&self.stage),
+            w_le_u32(self.code.len() as u32),
+            wh_slice(&self.code),
+        ));
+        Box::new(writer)
+    }
+}
+
+fn get_backend_ext(backend: &str) -> &'static str {
+    match backend.to_lowercase().as_str() {
+        "hlsl" => ".hlsl",
+        "glsl" => ".glsl",
+        "metal" => ".metal",
+        "spirv" => ".spv",
+        _ => ".bin",
+    }
+}
+
+/// Unpacked form of a `ShaderFile`, with the code of every stage stored in its own file.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderManifest {
+    pub variants: Vec<ShaderVariantManifest>,
+    pub vertex_attributes: Vec<MaterialAttribute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderVariantManifest {
+    pub backend: String,
+    pub stages: Vec<ShaderStageManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ShaderStageManifest {
+    pub stage: ShaderStage,
+    pub file: String,
+}
+
+impl ShaderFile {
+    /// Files holding the code of each stage, named `<backend>/<stage>.<ext>`. Fails when two
+    /// stages would be written to the same file.
+    pub fn get_stage_files(&self) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        let mut names = HashSet::new();
+        let mut files = vec![];
+        for v in &self.variants {
+            for s in &v.stages {
+                let name = get_stage_file_name(&v.backend, s.stage);
+                if !names.insert(name.clone()) {
+                    return Err(anyhow!(
+                        "Duplicate shader stage {:?} for backend {} ({})",
+                        s.stage,
+                        v.backend,
+                        name
+                    ));
+                }
+                files.push((name, s.code.clone()));
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn from_manifest(
+        manifest: ShaderManifest,
+        read_file: impl Fn(&str) -> Result<Vec<u8>, anyhow::Error>,
+    ) -> Result<Self, anyhow::Error> {
+        let variants = manifest
+            .variants
+            .into_iter()
+            .map(|v| {
+                let stages = v
+                    .stages
+                    .into_iter()
+                    .map(|s| {
+                        Ok(ShaderStageCode {
+                            stage: s.stage,
+                            code: read_file(&s.file)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, anyhow::Error>>()?;
+                Ok(ShaderVariant {
+                    backend: v.backend,
+                    stages,
+                })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        Ok(ShaderFile {
+            variants,
+            vertex_attributes: manifest.vertex_attributes,
+        })
+    }
+}
+
+impl From<ShaderFile> for ShaderManifest {
+    fn from(shader: ShaderFile) -> Self {
+        ShaderManifest {
+            variants: shader
+                .variants
+                .into_iter()
+                .map(|v| ShaderVariantManifest {
+                    stages: v
+                        .stages
+                        .iter()
+                        .map(|s| ShaderStageManifest {
+                            stage: s.stage,
+                            file: get_stage_file_name(&v.backend, s.stage),
+                        })
+                        .collect(),
+                    backend: v.backend,
+                })
+                .collect(),
+            vertex_attributes: shader.vertex_attributes,
+        }
+    }
+}
+
+fn get_stage_file_name(backend: &str, stage: ShaderStage) -> String {
+    format!(
+        "{}/{}{}",
+        pathify_component(backend),
+        stage.file_name(),
+        get_backend_ext(backend)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader(backends: &[&str]) -> ShaderFile {
+        ShaderFile {
+            variants: backends
+                .iter()
+                .map(|backend| ShaderVariant {
+                    backend: backend.to_string(),
+                    stages: vec![ShaderStageCode {
+                        stage: ShaderStage::Vertex,
+                        code: vec![1, 2],
+                    }],
+                })
+                .collect(),
+            vertex_attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn test_stage_files_stay_in_companion_dir() {
+        let files = shader(&["hlsl", "../../x", "..", "a\\b"])
+            .get_stage_files()
+            .unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "hlsl/vertex.hlsl",
+                ".._.._x/vertex.bin",
+                "_../vertex.bin",
+                "a_b/vertex.bin"
+            ]
+        );
+
+        let manifest = ShaderManifest::from(shader(&["../../x"]));
+        assert_eq!(manifest.variants[0].backend, "../../x");
+        assert_eq!(manifest.variants[0].stages[0].file, ".._.._x/vertex.bin");
+
+        assert!(shader(&["glsl", "glsl"]).get_stage_files().is_err());
+        assert!(shader(&["a/b", "a_b"]).get_stage_files().is_err());
+    }
+}
@@ -16,9 +16,10 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct SpriteSheet {
     pub name: String,
     pub sprites: Vec<Sprite>,
@@ -59,7 +60,7 @@ impl Writable for SpriteSheet {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Sprite {
     pub pivot: (f32, f32),
     pub orig_pivot: (i32, i32),
@@ -129,8 +130,8 @@ impl Writable for Sprite {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SpriteIdx(IndexMap<String, i32>);
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SpriteIdx(pub IndexMap<String, i32>);
 
 impl Parsable for SpriteIdx {
     fn parse(i: &[u8]) -> IResult<&[u8], Self> {
@@ -158,7 +159,7 @@ impl Writable for SpriteIdx {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct FrameTag {
     pub name: String,
     pub to: i32,
@@ -0,0 +1,85 @@
+use super::super::common::{
+    config::{h_confignode, wh_confignode, ConfigNodeMap},
+    hpk::{Parsable, Writable},
+    primitives::{h_string, wh_string},
+};
+use cookie_factory::{
+    bytes::le_u32 as w_le_u32, multi::all as wh_all, sequence::tuple as wh_tuple, SerializeFn,
+};
+use nom::{
+    combinator::map, multi::length_count, number::complete::le_u32, sequence::tuple, IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Tuning variables by name. Unpacks to a plain map, so each variable is one line of the
+/// document.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(transparent)]
+pub struct VariableTable {
+    pub variables: ConfigNodeMap,
+}
+
+impl Parsable for VariableTable {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            length_count(le_u32, tuple((h_string, h_confignode))),
+            |variables| VariableTable {
+                variables: variables.into_iter().collect(),
+            },
+        )(i)
+    }
+}
+
+impl Writable for VariableTable {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.variables.len() as u32),
+            wh_all(
+                self.variables
+                    .iter()
+                    .map(|(k, v)| wh_tuple((wh_string(k), wh_confignode(v)))),
+            ),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::{config::ConfigNode, hpk::write_to_vec},
+    };
+
+    #[test]
+    fn test_variable_table_round_trip() {
+        let table = VariableTable {
+            variables: ConfigNodeMap::from([
+                ("speed".to_string(), ConfigNode::Float(0.1)),
+                ("lives".to_string(), ConfigNode::Int(3)),
+                ("title".to_string(), ConfigNode::String("Boss".to_string())),
+                ("hard".to_string(), ConfigNode::Bool(false)),
+                ("spawn".to_string(), ConfigNode::Int2((4, -8))),
+                ("offset".to_string(), ConfigNode::Float2((0.5, 1.0 / 3.0))),
+                ("seed".to_string(), ConfigNode::Int64(1 << 40)),
+                (
+                    "waves".to_string(),
+                    ConfigNode::Sequence(vec![ConfigNode::Int(1), ConfigNode::Int(2)]),
+                ),
+            ]),
+        };
+
+        let bytes = write_to_vec(&table).unwrap();
+        let (rest, parsed) = VariableTable::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: VariableTable = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
@@ -19,9 +19,10 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Animation {
     pub name: String,
     pub spritesheet: String,
@@ -71,7 +72,7 @@ impl Writable for Animation {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Sequence {
     pub frames: Vec<Frame>,
     pub name: String,
@@ -119,7 +120,7 @@ impl Writable for Sequence {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct ActionPoint {
     pub name: String,
     pub id: i32,
@@ -6,14 +6,15 @@ use nom::{
     sequence::tuple,
     IResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct AudioEvent {
     pub actions: Vec<AudioEventAction>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub enum AudioEventActionType {
     PlayLegacy = 0,
     Play = 1,
@@ -28,7 +29,7 @@ pub enum AudioEventActionType {
     SetVariable,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub enum AudioEventAction {
     PlayLegacy,
     Play,
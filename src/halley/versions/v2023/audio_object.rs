@@ -0,0 +1,502 @@
+use crate::halley::versions::common::{
+    hpk::{Parsable, Writable},
+    primitives::{h_bool, h_enum, h_string, wh_bool, wh_enum, wh_string},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u32 as w_le_u32},
+    combinator::slice as wh_slice,
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioObject {
+    pub id: String,
+    pub bus: String,
+    pub pitch: Range,
+    pub volume: Range,
+    pub root: AudioSubObject,
+}
+
+impl AudioObject {
+    /// Names of every audio clip referenced anywhere in the sub-object tree.
+    pub fn clip_references(&self) -> Vec<&String> {
+        let mut clips = vec![];
+        self.root.collect_clips(&mut clips);
+        clips
+    }
+}
+
+impl Parsable for AudioObject {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                h_string,
+                Range::parse,
+                Range::parse,
+                AudioSubObject::parse,
+            )),
+            |(id, bus, pitch, volume, root)| AudioObject {
+                id,
+                bus,
+                pitch,
+                volume,
+                root,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AudioObject {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.id),
+            wh_string(&self.bus),
+            self.pitch.write(),
+            self.volume.write(),
+            self.root.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Range {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Parsable for Range {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((le_f32, le_f32)), |(start, end)| Range { start, end })(i)
+    }
+}
+
+impl Writable for Range {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        Box::new(wh_tuple((w_le_f32(self.start), w_le_f32(self.end))))
+    }
+}
+
+#[derive(Debug, FromPrimitive, ToPrimitive)]
+pub enum AudioSubObjectType {
+    None = 0,
+    Clips = 1,
+    Layers = 2,
+    Sequence = 3,
+    Switch = 4,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(tag = "type")]
+pub enum AudioSubObject {
+    None,
+    Clips(AudioClips),
+    Layers(AudioLayers),
+    Sequence(AudioSequence),
+    Switch(AudioSwitch),
+}
+
+impl AudioSubObject {
+    fn sub_object_type(&self) -> AudioSubObjectType {
+        match self {
+            AudioSubObject::None => AudioSubObjectType::None,
+            AudioSubObject::Clips(_) => AudioSubObjectType::Clips,
+            AudioSubObject::Layers(_) => AudioSubObjectType::Layers,
+            AudioSubObject::Sequence(_) => AudioSubObjectType::Sequence,
+            AudioSubObject::Switch(_) => AudioSubObjectType::Switch,
+        }
+    }
+
+    fn collect_clips<'a>(&'a self, clips: &mut Vec<&'a String>) {
+        match self {
+            AudioSubObject::None => {}
+            AudioSubObject::Clips(c) => clips.extend(c.clips.iter()),
+            AudioSubObject::Layers(l) => {
+                l.layers.iter().for_each(|l| l.object.collect_clips(clips))
+            }
+            AudioSubObject::Sequence(s) => s
+                .segments
+                .iter()
+                .for_each(|s| s.object.collect_clips(clips)),
+            AudioSubObject::Switch(s) => s.cases.iter().for_each(|c| c.object.collect_clips(clips)),
+        }
+    }
+}
+
+impl Parsable for AudioSubObject {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        let (i, sub_object_type) = h_enum(i)?;
+        match sub_object_type {
+            AudioSubObjectType::None => Ok((i, AudioSubObject::None)),
+            AudioSubObjectType::Clips => map(AudioClips::parse, AudioSubObject::Clips)(i),
+            AudioSubObjectType::Layers => map(AudioLayers::parse, AudioSubObject::Layers)(i),
+            AudioSubObjectType::Sequence => map(AudioSequence::parse, AudioSubObject::Sequence)(i),
+            AudioSubObjectType::Switch => map(AudioSwitch::parse, AudioSubObject::Switch)(i),
+        }
+    }
+}
+
+impl Writable for AudioSubObject {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let data: Box<dyn SerializeFn<Vec<u8>> + 'a> = match self {
+            AudioSubObject::None => Box::new(wh_slice(vec![])),
+            AudioSubObject::Clips(c) => c.write(),
+            AudioSubObject::Layers(l) => l.write(),
+            AudioSubObject::Sequence(s) => s.write(),
+            AudioSubObject::Switch(s) => s.write(),
+        };
+        Box::new(wh_tuple((wh_enum(&self.sub_object_type()), data)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioClips {
+    pub clips: Vec<String>,
+    pub is_loop: bool,
+    pub gain: Range,
+}
+
+impl Parsable for AudioClips {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((length_count(le_u32, h_string), h_bool, Range::parse)),
+            |(clips, is_loop, gain)| AudioClips {
+                clips,
+                is_loop,
+                gain,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AudioClips {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.clips.len() as u32),
+            wh_all(self.clips.iter().map(wh_string)),
+            wh_bool(self.is_loop),
+            self.gain.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum AudioFadeCurve {
+    None = 0,
+    Linear = 1,
+    Sinusoidal,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioFade {
+    pub length: f32,
+    pub curve: AudioFadeCurve,
+}
+
+impl Parsable for AudioFade {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((le_f32, h_enum)), |(length, curve)| AudioFade {
+            length,
+            curve,
+        })(i)
+    }
+}
+
+impl Writable for AudioFade {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        Box::new(wh_tuple((w_le_f32(self.length), wh_enum(&self.curve))))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioLayers {
+    pub layers: Vec<AudioLayer>,
+    pub fade: AudioFade,
+}
+
+impl Parsable for AudioLayers {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((length_count(le_u32, AudioLayer::parse), AudioFade::parse)),
+            |(layers, fade)| AudioLayers { layers, fade },
+        )(i)
+    }
+}
+
+impl Writable for AudioLayers {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.layers.len() as u32),
+            wh_all(self.layers.iter().map(|l| l.write())),
+            self.fade.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioLayer {
+    pub object: AudioSubObject,
+    pub expression: String,
+    pub synchronised: bool,
+    pub fade_in: AudioFade,
+    pub fade_out: AudioFade,
+}
+
+impl Parsable for AudioLayer {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                AudioSubObject::parse,
+                h_string,
+                h_bool,
+                AudioFade::parse,
+                AudioFade::parse,
+            )),
+            |(object, expression, synchronised, fade_in, fade_out)| AudioLayer {
+                object,
+                expression,
+                synchronised,
+                fade_in,
+                fade_out,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AudioLayer {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            self.object.write(),
+            wh_string(&self.expression),
+            wh_bool(self.synchronised),
+            self.fade_in.write(),
+            self.fade_out.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum AudioSequenceType {
+    Sequential = 0,
+    Shuffle = 1,
+    Random = 2,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioSequence {
+    pub segments: Vec<AudioSegment>,
+    pub sequence_type: AudioSequenceType,
+    pub cross_fade: AudioFade,
+}
+
+impl Parsable for AudioSequence {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                length_count(le_u32, AudioSegment::parse),
+                h_enum,
+                AudioFade::parse,
+            )),
+            |(segments, sequence_type, cross_fade)| AudioSequence {
+                segments,
+                sequence_type,
+                cross_fade,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AudioSequence {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.segments.len() as u32),
+            wh_all(self.segments.iter().map(|s| s.write())),
+            wh_enum(&self.sequence_type),
+            self.cross_fade.write(),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioSegment {
+    pub object: AudioSubObject,
+    pub name: String,
+    pub end_sample: i32,
+}
+
+impl Parsable for AudioSegment {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((AudioSubObject::parse, h_string, le_i32)),
+            |(object, name, end_sample)| AudioSegment {
+                object,
+                name,
+                end_sample,
+            },
+        )(i)
+    }
+}
+
+impl Writable for AudioSegment {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            self.object.write(),
+            wh_string(&self.name),
+            w_le_i32(self.end_sample),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioSwitch {
+    pub variable: String,
+    pub cases: Vec<AudioSwitchCase>,
+}
+
+impl Parsable for AudioSwitch {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, length_count(le_u32, AudioSwitchCase::parse))),
+            |(variable, cases)| AudioSwitch { variable, cases },
+        )(i)
+    }
+}
+
+impl Writable for AudioSwitch {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.variable),
+            w_le_u32(self.cases.len() as u32),
+            wh_all(self.cases.iter().map(|c| c.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AudioSwitchCase {
+    pub value: String,
+    pub object: AudioSubObject,
+}
+
+impl Parsable for AudioSwitchCase {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, AudioSubObject::parse)),
+            |(value, object)| AudioSwitchCase { value, object },
+        )(i)
+    }
+}
+
+impl Writable for AudioSwitchCase {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        Box::new(wh_tuple((wh_string(&self.value), self.object.write())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::hpk::write_to_vec,
+    };
+
+    #[test]
+    fn test_audio_object_round_trip() {
+        let fade = || AudioFade {
+            length: 0.5,
+            curve: AudioFadeCurve::Linear,
+        };
+        let clips = |names: &[&str]| {
+            AudioSubObject::Clips(AudioClips {
+                clips: names.iter().map(|n| n.to_string()).collect(),
+                is_loop: true,
+                gain: Range {
+                    start: 0.8,
+                    end: 1.0,
+                },
+            })
+        };
+        let object = AudioObject {
+            id: "music/boss".to_string(),
+            bus: "music".to_string(),
+            pitch: Range {
+                start: 1.0,
+                end: 1.0,
+            },
+            volume: Range {
+                start: 0.5,
+                end: 0.75,
+            },
+            root: AudioSubObject::Switch(AudioSwitch {
+                variable: "phase".to_string(),
+                cases: vec![
+                    AudioSwitchCase {
+                        value: "intro".to_string(),
+                        object: clips(&["boss_intro"]),
+                    },
+                    AudioSwitchCase {
+                        value: "fight".to_string(),
+                        object: AudioSubObject::Layers(AudioLayers {
+                            layers: vec![AudioLayer {
+                                object: AudioSubObject::Sequence(AudioSequence {
+                                    segments: vec![AudioSegment {
+                                        object: clips(&["boss_a", "boss_b"]),
+                                        name: "loop".to_string(),
+                                        end_sample: 44100,
+                                    }],
+                                    sequence_type: AudioSequenceType::Shuffle,
+                                    cross_fade: fade(),
+                                }),
+                                expression: "intensity > 0.5".to_string(),
+                                synchronised: true,
+                                fade_in: fade(),
+                                fade_out: fade(),
+                            }],
+                            fade: fade(),
+                        }),
+                    },
+                    AudioSwitchCase {
+                        value: "none".to_string(),
+                        object: AudioSubObject::None,
+                    },
+                ],
+            }),
+        };
+
+        assert_eq!(
+            object.clip_references(),
+            vec!["boss_intro", "boss_a", "boss_b"]
+        );
+
+        let bytes = write_to_vec(&object).unwrap();
+        let (rest, parsed) = AudioObject::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: AudioObject = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::halley::{
+    assets::bmfont::{BmFont, BmFontConvertible},
+    versions::{
+        common::{
+            hpk::{Parsable, Writable},
+            primitives::{h_bool, h_string, wh_bool, wh_string},
+        },
+        v2020::font::{apply_bmfont_to_glyphs, glyphs_to_bmfont, FontMetrics, Glyph},
+    },
+};
+use cookie_factory::{
+    bytes::le_u32 as w_le_u32, multi::all as wh_all, sequence::tuple as wh_tuple, SerializeFn,
+};
+use nom::{
+    combinator::map, multi::length_count, number::complete::le_u32, sequence::tuple, IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Font {
+    pub name: String,
+    pub image_name: String,
+    pub metrics: FontMetrics,
+    pub distance_field: bool,
+    pub fallback_fonts: Vec<String>,
+    pub glyphs: Vec<Glyph>,
+}
+
+impl Parsable for Font {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                h_string,
+                FontMetrics::parse,
+                h_bool,
+                length_count(le_u32, h_string),
+                length_count(le_u32, Glyph::parse),
+            )),
+            |(name, image_name, metrics, distance_field, fallback_fonts, glyphs)| Font {
+                name,
+                image_name,
+                metrics,
+                distance_field,
+                fallback_fonts,
+                glyphs,
+            },
+        )(i)
+    }
+}
+
+impl Writable for Font {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_string(&self.image_name),
+            self.metrics.write(),
+            wh_bool(self.distance_field),
+            w_le_u32(self.fallback_fonts.len() as u32),
+            wh_all(self.fallback_fonts.iter().map(wh_string)),
+            w_le_u32(self.glyphs.len() as u32),
+            wh_all(self.glyphs.iter().map(|g| g.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl BmFontConvertible for Font {
+    fn image_name(&self) -> &str {
+        &self.image_name
+    }
+
+    fn to_bmfont(&self) -> BmFont {
+        glyphs_to_bmfont(&self.name, &self.image_name, &self.metrics, &self.glyphs)
+    }
+
+    fn apply_bmfont(&mut self, font: &BmFont) {
+        apply_bmfont_to_glyphs(&mut self.metrics, &mut self.glyphs, font)
+    }
+}
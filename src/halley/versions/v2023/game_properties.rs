@@ -0,0 +1,213 @@
+use crate::halley::versions::common::{
+    config::{
+        h_config_file, wh_config_file, ConfigFile, ConfigNode, ConfigNodeMap, ConfigNodePosition,
+    },
+    hpk::{Parsable, Writable},
+};
+use cookie_factory::SerializeFn;
+use indexmap::IndexMap;
+use nom::{combinator::map_res, IResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+static NAME_KEY: &str = "name";
+static VERSION_KEY: &str = "version";
+static RESOLUTION_KEY: &str = "resolution";
+static FEATURES_KEY: &str = "features";
+
+#[derive(Error, Debug)]
+pub enum GamePropertiesError {
+    #[error("Game properties root is not a map")]
+    RootIsNotAMap,
+}
+
+/// Global game settings. These are stored as a config file with a map root; well-known keys are
+/// lifted into typed fields when they have the expected shape, everything else stays in `settings`.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct GameProperties {
+    pub v: i32,
+    pub store_file_position: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<(i32, i32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<IndexMap<String, bool>>,
+    #[serde(default)]
+    pub settings: ConfigNodeMap,
+    /// Original key order, only kept when it differs from the typed fields followed by `settings`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_order: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positions: Option<ConfigNodePosition>,
+}
+
+impl TryFrom<ConfigFile> for GameProperties {
+    type Error = GamePropertiesError;
+
+    fn try_from(file: ConfigFile) -> Result<Self, Self::Error> {
+        let ConfigNode::Map(mut settings) = file.root else {
+            return Err(GamePropertiesError::RootIsNotAMap);
+        };
+        let original_order: Vec<String> = settings.keys().cloned().collect();
+
+        let name = match settings.get(NAME_KEY) {
+            Some(ConfigNode::String(s)) => Some(s.to_owned()),
+            _ => None,
+        };
+        let version = match settings.get(VERSION_KEY) {
+            Some(ConfigNode::String(s)) => Some(s.to_owned()),
+            _ => None,
+        };
+        let resolution = match settings.get(RESOLUTION_KEY) {
+            Some(ConfigNode::Int2(r)) => Some(*r),
+            _ => None,
+        };
+        let features = match settings.get(FEATURES_KEY) {
+            Some(ConfigNode::Map(map)) => map
+                .iter()
+                .map(|(k, v)| match v {
+                    ConfigNode::Bool(b) => Some((k.to_owned(), *b)),
+                    _ => None,
+                })
+                .collect::<Option<IndexMap<String, bool>>>(),
+            _ => None,
+        };
+
+        let typed_keys = [
+            (NAME_KEY, name.is_some()),
+            (VERSION_KEY, version.is_some()),
+            (RESOLUTION_KEY, resolution.is_some()),
+            (FEATURES_KEY, features.is_some()),
+        ];
+        for (key, _) in typed_keys.iter().filter(|(_, is_typed)| *is_typed) {
+            settings.shift_remove(*key);
+        }
+
+        let mut properties = GameProperties {
+            v: file.v,
+            store_file_position: file.store_file_position,
+            name,
+            version,
+            resolution,
+            features,
+            settings,
+            key_order: vec![],
+            positions: file.positions,
+        };
+        if properties.to_map().keys().ne(original_order.iter()) {
+            properties.key_order = original_order;
+        }
+        Ok(properties)
+    }
+}
+
+impl GameProperties {
+    fn to_map(&self) -> ConfigNodeMap {
+        let mut map = ConfigNodeMap::new();
+        if let Some(name) = &self.name {
+            map.insert(NAME_KEY.to_string(), ConfigNode::String(name.to_owned()));
+        }
+        if let Some(version) = &self.version {
+            map.insert(
+                VERSION_KEY.to_string(),
+                ConfigNode::String(version.to_owned()),
+            );
+        }
+        if let Some(resolution) = self.resolution {
+            map.insert(RESOLUTION_KEY.to_string(), ConfigNode::Int2(resolution));
+        }
+        if let Some(features) = &self.features {
+            let features = features
+                .iter()
+                .map(|(k, v)| (k.to_owned(), ConfigNode::Bool(*v)))
+                .collect();
+            map.insert(FEATURES_KEY.to_string(), ConfigNode::Map(features));
+        }
+        map.extend(self.settings.clone());
+
+        let mut placed = 0;
+        for key in &self.key_order {
+            if let Some(from) = map.get_index_of(key) {
+                map.move_index(from, placed);
+                placed += 1;
+            }
+        }
+        map
+    }
+
+    pub fn to_config_file(&self) -> ConfigFile {
+        ConfigFile {
+            v: self.v,
+            store_file_position: self.store_file_position,
+            root: ConfigNode::Map(self.to_map()),
+            positions: self.positions.clone(),
+        }
+    }
+}
+
+impl Parsable for GameProperties {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map_res(h_config_file, GameProperties::try_from)(i)
+    }
+}
+
+impl Writable for GameProperties {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let file = self.to_config_file();
+        Box::new(move |w| wh_config_file(&file)(w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::hpk::write_to_vec,
+    };
+
+    #[test]
+    fn test_game_properties_round_trip() {
+        let root = ConfigNodeMap::from([
+            ("fullscreen".to_string(), ConfigNode::Bool(true)),
+            (NAME_KEY.to_string(), ConfigNode::String("Game".to_string())),
+            (RESOLUTION_KEY.to_string(), ConfigNode::Int2((1280, 720))),
+            // Not all flags, so it stays in the settings.
+            (
+                FEATURES_KEY.to_string(),
+                ConfigNode::Map(ConfigNodeMap::from([
+                    ("net".to_string(), ConfigNode::Bool(false)),
+                    ("level".to_string(), ConfigNode::Int(3)),
+                ])),
+            ),
+            (
+                VERSION_KEY.to_string(),
+                ConfigNode::String("1.2".to_string()),
+            ),
+        ]);
+        let file = ConfigFile {
+            v: 3,
+            store_file_position: false,
+            root: ConfigNode::Map(root),
+            positions: None,
+        };
+        let bytes = write_to_vec(&file).unwrap();
+
+        let (rest, parsed) = GameProperties::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.resolution, Some((1280, 720)));
+        assert!(parsed.features.is_none());
+        assert!(!parsed.key_order.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: GameProperties = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
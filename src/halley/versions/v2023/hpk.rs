@@ -7,22 +7,45 @@ use super::{
         },
     },
     animation::Animation,
+    audio_object::AudioObject,
+    font::Font,
+    game_properties::GameProperties,
+    material::MaterialDefinition,
+    navmesh::NavmeshSet,
+    prefab::{Prefab, Scene},
+    render_graph::RenderGraphDefinition,
+    script_graph::ScriptGraph,
     spritesheet::{SpriteResource, SpriteSheet},
+    ui_definition::UiDefinition,
 };
 use crate::halley::{
     assets::{
+        audio::{get_audio_clip_properties, get_audio_ext_from_path, sniff_audio_format},
+        gltf::{mesh_from_gltf, mesh_to_gltf},
         property_file,
-        serialization::{get_format_from_ext, get_serialization_ext_from_path},
+        schema::strip_schema_reference,
+        serialization::{
+            deserialize, get_format_from_ext, get_serialization_ext_from_path, Format,
+        },
+        unpack::UnpackOptions,
+        utils::get_companion_dir,
     },
     versions::common::{
-        config::ConfigFile,
+        config::{ConfigFile, ConfigNodeMap},
         hpk::{
-            make_asset_type, pack_transform, unpack_transform, HalleyPackData,
+            make_asset_type, pack_transform, unpack_transform, write_to_vec, HalleyPackData,
             HalleyPackParseError, Writable,
         },
         primitives::{wh_pos_size, wh_string},
     },
+    versions::v2020::{
+        mesh::{Mesh, MeshManifest, MESH_GLTF_FILE},
+        mesh_animation::MeshAnimation,
+        shader::{ShaderFile, ShaderManifest},
+        variable_table::VariableTable,
+    },
 };
+use anyhow::anyhow;
 use cookie_factory::{
     bytes::{le_i32 as w_le_i32, le_u32 as w_le_u32},
     multi::all as wh_all,
@@ -37,8 +60,12 @@ use nom::{
     IResult,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::{schema::RootSchema, schema_for};
 use std::path::Path;
 
+/// Version of the config files in V2023 packs, which flag whether they store node positions.
+pub const CONFIG_VERSION: i32 = 3;
+
 pub struct HalleyPackV2023 {}
 
 impl HalleyPackV2023 {
@@ -86,11 +113,10 @@ where
 }
 
 impl HpkSection for HpkSectionV2023 {
-    fn new(section_index: i32) -> Result<Self, anyhow::Error> {
-        let asset_type = make_asset_type(section_index)?;
+    fn new(asset_type: i32) -> Result<Self, anyhow::Error> {
         Ok(HpkSectionV2023 {
-            asset_type,
-            section_index,
+            asset_type: make_asset_type(asset_type)?,
+            section_index: 0,
             assets: vec![],
         })
     }
@@ -99,6 +125,18 @@ impl HpkSection for HpkSectionV2023 {
         num_traits::ToPrimitive::to_i32(&self.asset_type).unwrap()
     }
 
+    fn asset_type_name(&self) -> String {
+        format!("{:?}", self.asset_type)
+    }
+
+    fn section_index(&self) -> Option<i32> {
+        Some(self.section_index)
+    }
+
+    fn set_section_index(&mut self, section_index: i32) {
+        self.section_index = section_index;
+    }
+
     fn assets(&self) -> Vec<Box<&dyn HpkAsset>> {
         self.assets
             .iter()
@@ -106,16 +144,28 @@ impl HpkSection for HpkSectionV2023 {
             .collect()
     }
 
+    fn assets_mut(&mut self) -> Vec<&mut dyn HpkAsset> {
+        self.assets
+            .iter_mut()
+            .map(|a| a as &mut dyn HpkAsset)
+            .collect()
+    }
+
     fn add_asset(
         &mut self,
         pack: &mut dyn HalleyPack,
         path: &Path,
         relative_path: &Path,
     ) -> Result<(), anyhow::Error> {
-        let (config, data) = property_file::read_with_file_data::<ConfigNode>(path)?;
+        let (mut config, data) = property_file::read_with_file_data::<ConfigNode>(path)?;
 
-        let serialization_ext = get_serialization_ext_from_path(path);
-        let data = self.modify_data_on_repack(&data, serialization_ext)?;
+        let serialization_ext = self.get_data_ext_from_path(path);
+        let companion_dir = get_companion_dir(path, serialization_ext);
+        let data = strip_schema_reference(&data, serialization_ext);
+        let data = self.modify_data_on_repack(&data, serialization_ext, &companion_dir)?;
+        if let ConfigNode::Map(map) = &mut config {
+            map.shift_remove(property_file::DERIVED_KEY);
+        }
 
         let name = self.get_asset_name(relative_path, serialization_ext);
 
@@ -143,6 +193,18 @@ impl HpkSectionUnpackable for HpkSectionV2023 {
             AssetTypeV2023::SPRITE => ".sprite",
             AssetTypeV2023::ANIMATION => ".anim",
             AssetTypeV2023::CONFIG => ".config",
+            AssetTypeV2023::MATERIAL => ".material",
+            AssetTypeV2023::FONT => ".font",
+            AssetTypeV2023::MESH => ".mesh",
+            AssetTypeV2023::MESHANIMATION => ".meshanim",
+            AssetTypeV2023::VARIABLETABLE => ".variables",
+            AssetTypeV2023::RENDERGHRAPHDEFINITION => ".rendergraph",
+            AssetTypeV2023::SCRIPTGHRAPH => ".scriptgraph",
+            AssetTypeV2023::NAVMESHSET => ".navmesh",
+            AssetTypeV2023::PREFAB => ".prefab",
+            AssetTypeV2023::SCENE => ".scene",
+            AssetTypeV2023::UIDDEFINITION => ".ui",
+            AssetTypeV2023::SHADER => ".shader",
             AssetTypeV2023::GAMEPROPERTIES => ".game",
             AssetTypeV2023::AUDIOOBJECT => ".audioobject",
             AssetTypeV2023::AUDIOEVENT => ".audioevent",
@@ -150,14 +212,67 @@ impl HpkSectionUnpackable for HpkSectionV2023 {
         }
     }
 
-    fn modify_data_on_unpack(&self, i: &[u8]) -> Result<(Vec<u8>, &'static str), anyhow::Error> {
+    fn modify_data_on_unpack(
+        &self,
+        i: &[u8],
+        format: Option<Format>,
+    ) -> Result<(Vec<u8>, &'static str), anyhow::Error> {
         match self.asset_type {
-            AssetTypeV2023::SPRITESHEET => unpack_transform::<SpriteSheet, SpriteSheet>(i, None),
-            AssetTypeV2023::SPRITE => unpack_transform::<SpriteResource, SpriteResource>(i, None),
-            AssetTypeV2023::ANIMATION => unpack_transform::<Animation, Animation>(i, None),
+            AssetTypeV2023::SPRITESHEET => {
+                unpack_transform::<SpriteSheet, SpriteSheet>(i, None, format)
+            }
+            AssetTypeV2023::SPRITE => {
+                unpack_transform::<SpriteResource, SpriteResource>(i, None, format)
+            }
+            AssetTypeV2023::ANIMATION => unpack_transform::<Animation, Animation>(i, None, format),
             AssetTypeV2023::CONFIG => {
-                unpack_transform::<ConfigFile, ConfigNode>(i, Some(|c| c.root))
+                unpack_transform::<ConfigFile, ConfigNode>(i, Some(|c| c.root), format)
+            }
+            AssetTypeV2023::MATERIAL => {
+                unpack_transform::<MaterialDefinition, MaterialDefinition>(i, None, format)
+            }
+            AssetTypeV2023::FONT => unpack_transform::<Font, Font>(i, None, format),
+            AssetTypeV2023::MESH => {
+                unpack_transform::<Mesh, MeshManifest>(i, Some(MeshManifest::from), format)
             }
+            AssetTypeV2023::MESHANIMATION => {
+                unpack_transform::<MeshAnimation, MeshAnimation>(i, None, format)
+            }
+            AssetTypeV2023::VARIABLETABLE => {
+                unpack_transform::<VariableTable, VariableTable>(i, None, format)
+            }
+            AssetTypeV2023::RENDERGHRAPHDEFINITION => {
+                unpack_transform::<RenderGraphDefinition, RenderGraphDefinition>(i, None, format)
+            }
+            AssetTypeV2023::SCRIPTGHRAPH => {
+                unpack_transform::<ScriptGraph, ScriptGraph>(i, None, format)
+            }
+            AssetTypeV2023::NAVMESHSET => {
+                unpack_transform::<NavmeshSet, NavmeshSet>(i, None, format)
+            }
+            AssetTypeV2023::PREFAB => {
+                unpack_transform::<Prefab, Prefab>(i, None, format.or(Some(Format::Yaml)))
+            }
+            AssetTypeV2023::SCENE => {
+                unpack_transform::<Scene, Scene>(i, None, format.or(Some(Format::Yaml)))
+            }
+            AssetTypeV2023::UIDDEFINITION => unpack_transform::<UiDefinition, UiDefinition>(
+                i,
+                None,
+                format.or(Some(Format::Yaml)),
+            ),
+            AssetTypeV2023::SHADER => unpack_transform::<ShaderFile, ShaderManifest>(
+                i,
+                Some(ShaderManifest::from),
+                format,
+            ),
+            AssetTypeV2023::GAMEPROPERTIES => {
+                unpack_transform::<GameProperties, GameProperties>(i, None, format)
+            }
+            AssetTypeV2023::AUDIOOBJECT => {
+                unpack_transform::<AudioObject, AudioObject>(i, None, format)
+            }
+            AssetTypeV2023::AUDIOCLIP => Ok((i.into(), sniff_audio_format(i).ext())),
             // AssetTypeV2023::TEXTURE => {
             //     //let compression = self.get
             // },
@@ -165,28 +280,146 @@ impl HpkSectionUnpackable for HpkSectionV2023 {
         }
     }
 
-    fn modify_data_on_repack(&self, i: &[u8], ext: &str) -> Result<Vec<u8>, anyhow::Error> {
+    fn get_schema(&self) -> Option<RootSchema> {
+        let schema = match self.asset_type {
+            AssetTypeV2023::SPRITESHEET => schema_for!(SpriteSheet),
+            AssetTypeV2023::SPRITE => schema_for!(SpriteResource),
+            AssetTypeV2023::ANIMATION => schema_for!(Animation),
+            AssetTypeV2023::CONFIG => schema_for!(ConfigNode),
+            AssetTypeV2023::MATERIAL => schema_for!(MaterialDefinition),
+            AssetTypeV2023::FONT => schema_for!(Font),
+            AssetTypeV2023::MESH => schema_for!(MeshManifest),
+            AssetTypeV2023::MESHANIMATION => schema_for!(MeshAnimation),
+            AssetTypeV2023::VARIABLETABLE => schema_for!(VariableTable),
+            AssetTypeV2023::RENDERGHRAPHDEFINITION => schema_for!(RenderGraphDefinition),
+            AssetTypeV2023::SCRIPTGHRAPH => schema_for!(ScriptGraph),
+            AssetTypeV2023::NAVMESHSET => schema_for!(NavmeshSet),
+            AssetTypeV2023::PREFAB => schema_for!(Prefab),
+            AssetTypeV2023::SCENE => schema_for!(Scene),
+            AssetTypeV2023::UIDDEFINITION => schema_for!(UiDefinition),
+            AssetTypeV2023::SHADER => schema_for!(ShaderManifest),
+            AssetTypeV2023::GAMEPROPERTIES => schema_for!(GameProperties),
+            AssetTypeV2023::AUDIOOBJECT => schema_for!(AudioObject),
+            _ => return None,
+        };
+        Some(schema)
+    }
+
+    fn get_properties_schema(&self) -> RootSchema {
+        schema_for!(ConfigNode)
+    }
+
+    fn get_companion_files(
+        &self,
+        i: &[u8],
+        options: &UnpackOptions,
+    ) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        match self.asset_type {
+            AssetTypeV2023::CONFIG if options.config_positions => {
+                let (_, file) = ConfigFile::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                file.get_position_files()
+            }
+            AssetTypeV2023::SHADER => {
+                let (_, shader) = ShaderFile::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                shader.get_stage_files()
+            }
+            AssetTypeV2023::MESH => {
+                let (_, mesh) = Mesh::parse(i).map_err(|err| anyhow!(err.to_string()))?;
+                Ok(vec![(MESH_GLTF_FILE.to_string(), mesh_to_gltf(&mesh)?)])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn modify_data_on_repack(
+        &self,
+        i: &[u8],
+        ext: &str,
+        companion_dir: &Path,
+    ) -> Result<Vec<u8>, anyhow::Error> {
         let format = get_format_from_ext(ext);
         match self.asset_type {
+            AssetTypeV2023::SHADER => {
+                let manifest: ShaderManifest = deserialize(std::str::from_utf8(i)?, format)?;
+                let shader = ShaderFile::from_manifest(manifest, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&shader)
+            }
+            AssetTypeV2023::MESH => {
+                let manifest: MeshManifest = deserialize(std::str::from_utf8(i)?, format)?;
+                let mesh = mesh_from_gltf(manifest, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&mesh)
+            }
             AssetTypeV2023::SPRITESHEET => {
-                pack_transform::<SpriteSheet, SpriteSheet>(i, format, None)
+                // Fields the sheet's version needs are filled in, and those it can't have are
+                // dropped, so that editing `v` is enough to change the version.
+                let sheet: SpriteSheet = deserialize(std::str::from_utf8(i)?, format)?;
+                let v = sheet.v;
+                let (sheet, warnings) = sheet.migrate(v)?;
+                for warning in warnings {
+                    eprintln!("Sprite sheet {}: {}", sheet.name, warning);
+                }
+                write_to_vec(&sheet)
             }
             AssetTypeV2023::SPRITE => {
                 pack_transform::<SpriteResource, SpriteResource>(i, format, None)
             }
             AssetTypeV2023::ANIMATION => pack_transform::<Animation, Animation>(i, format, None),
-            AssetTypeV2023::CONFIG => pack_transform::<ConfigFile, ConfigNode>(
-                i,
-                format,
-                Some(|t| ConfigFile {
-                    v: 3,
-                    store_file_position: true,
-                    root: t,
-                }),
-            ),
+            AssetTypeV2023::CONFIG => {
+                let root: ConfigNode = deserialize(std::str::from_utf8(i)?, format)?;
+                let file = ConfigFile::from_unpacked(CONFIG_VERSION, root, |file| {
+                    Ok(std::fs::read(companion_dir.join(file))?)
+                })?;
+                write_to_vec(&file)
+            }
+            AssetTypeV2023::MATERIAL => {
+                pack_transform::<MaterialDefinition, MaterialDefinition>(i, format, None)
+            }
+            AssetTypeV2023::FONT => pack_transform::<Font, Font>(i, format, None),
+            AssetTypeV2023::MESHANIMATION => {
+                pack_transform::<MeshAnimation, MeshAnimation>(i, format, None)
+            }
+            AssetTypeV2023::VARIABLETABLE => {
+                pack_transform::<VariableTable, VariableTable>(i, format, None)
+            }
+            AssetTypeV2023::RENDERGHRAPHDEFINITION => {
+                pack_transform::<RenderGraphDefinition, RenderGraphDefinition>(i, format, None)
+            }
+            AssetTypeV2023::SCRIPTGHRAPH => {
+                pack_transform::<ScriptGraph, ScriptGraph>(i, format, None)
+            }
+            AssetTypeV2023::NAVMESHSET => pack_transform::<NavmeshSet, NavmeshSet>(i, format, None),
+            AssetTypeV2023::PREFAB => pack_transform::<Prefab, Prefab>(i, format, None),
+            AssetTypeV2023::SCENE => pack_transform::<Scene, Scene>(i, format, None),
+            AssetTypeV2023::UIDDEFINITION => {
+                pack_transform::<UiDefinition, UiDefinition>(i, format, None)
+            }
+            AssetTypeV2023::GAMEPROPERTIES => {
+                pack_transform::<GameProperties, GameProperties>(i, format, None)
+            }
+            AssetTypeV2023::AUDIOOBJECT => {
+                pack_transform::<AudioObject, AudioObject>(i, format, None)
+            }
             _ => Ok(i.into()),
         }
     }
+
+    fn get_derived_properties(&self, i: &[u8]) -> ConfigNodeMap {
+        match self.asset_type {
+            AssetTypeV2023::AUDIOCLIP => get_audio_clip_properties(i),
+            _ => ConfigNodeMap::new(),
+        }
+    }
+
+    fn get_data_ext_from_path(&self, path: &Path) -> &'static str {
+        match self.asset_type {
+            AssetTypeV2023::AUDIOCLIP => get_audio_ext_from_path(path),
+            _ => get_serialization_ext_from_path(path),
+        }
+    }
 }
 
 impl Parsable for HpkSectionV2023 {
@@ -244,8 +477,23 @@ impl HpkAsset for HpkAssetV2023 {
         self.size = size;
     }
 
-    fn serialize_properties(&self, filename: &std::path::Path) -> Result<(), anyhow::Error> {
-        super::super::super::assets::property_file::write(filename, &self.config)
+    fn serialize_properties(
+        &self,
+        filename: &std::path::Path,
+        derived: &ConfigNodeMap,
+        format: Option<Format>,
+    ) -> Result<(), anyhow::Error> {
+        let mut config = self.config.clone();
+        // Only shown next to a map, properties of any other kind are written as they are.
+        if let ConfigNode::Map(map) = &mut config {
+            if !derived.is_empty() {
+                map.insert(
+                    property_file::DERIVED_KEY.to_string(),
+                    ConfigNode::Map(derived.clone()),
+                );
+            }
+        }
+        super::super::super::assets::property_file::write(filename, &config, format)
     }
 
     fn get_asset_compression(&self) -> Option<String> {
@@ -261,6 +509,10 @@ impl HpkAsset for HpkAssetV2023 {
     fn get_compression(&self) -> Option<String> {
         get_compression(&self.config)
     }
+
+    fn get_properties(&self) -> ConfigNode {
+        self.config.clone()
+    }
 }
 
 impl Parsable for HpkAssetV2023 {
@@ -297,3 +549,39 @@ fn get_compression(config: &ConfigNode) -> Option<String> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::serialize,
+        versions::v2023::spritesheet::{SpriteIdx, DEFAULT_SPRITESHEET_MATERIAL},
+    };
+    use indexmap::IndexMap;
+
+    #[test]
+    fn test_repack_fills_spritesheet_fields_of_its_version() {
+        let sheet = SpriteSheet {
+            v: 1,
+            name: "sheet".to_string(),
+            sprites: vec![],
+            sprite_idx: SpriteIdx(IndexMap::new()),
+            frame_tags: vec![],
+            def_material_name: None,
+            palette_name: Some("pal".to_string()),
+        };
+        let yaml = serialize(&sheet, Some(Format::Yaml)).unwrap();
+        let section = HpkSectionV2023::new(AssetTypeV2023::SPRITESHEET as i32).unwrap();
+        let data = section
+            .modify_data_on_repack(yaml.as_bytes(), ".yaml", Path::new(""))
+            .unwrap();
+
+        let (_, repacked) = SpriteSheet::parse(&data).unwrap();
+        assert_eq!(repacked.v, 1);
+        assert_eq!(
+            repacked.def_material_name.as_deref(),
+            Some(DEFAULT_SPRITESHEET_MATERIAL)
+        );
+        assert_eq!(repacked.palette_name, None);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::halley::versions::{
+    common::{
+        config::{h_confignode, wh_confignode, ConfigNode},
+        hpk::{Parsable, Writable},
+        primitives::{h_bool, h_enum, h_option, h_string, wh_bool, wh_enum, wh_option, wh_string},
+    },
+    v2020::material::{MaterialAttribute, MaterialPass, ShaderParameterType},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u32 as w_le_u32},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialDefinition {
+    pub name: String,
+    pub passes: Vec<MaterialPass>,
+    pub uniform_blocks: Vec<MaterialUniformBlock>,
+    pub attributes: Vec<MaterialAttribute>,
+    pub vertex_size: i32,
+    pub vertex_pos_offset: i32,
+    pub textures: Vec<MaterialTexture>,
+    pub tags: Vec<String>,
+}
+
+impl MaterialDefinition {
+    pub fn shader_references(&self) -> Vec<&String> {
+        self.passes.iter().map(|p| &p.shader_asset_id).collect()
+    }
+
+    pub fn texture_references(&self) -> Vec<&String> {
+        self.textures
+            .iter()
+            .map(|t| &t.default_texture_name)
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+}
+
+impl Parsable for MaterialDefinition {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                length_count(le_u32, MaterialPass::parse),
+                length_count(le_u32, MaterialUniformBlock::parse),
+                length_count(le_u32, MaterialAttribute::parse),
+                le_i32,
+                le_i32,
+                length_count(le_u32, MaterialTexture::parse),
+                length_count(le_u32, h_string),
+            )),
+            |(
+                name,
+                passes,
+                uniform_blocks,
+                attributes,
+                vertex_size,
+                vertex_pos_offset,
+                textures,
+                tags,
+            )| MaterialDefinition {
+                name,
+                passes,
+                uniform_blocks,
+                attributes,
+                vertex_size,
+                vertex_pos_offset,
+                textures,
+                tags,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialDefinition {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_u32(self.passes.len() as u32),
+            wh_all(self.passes.iter().map(|p| p.write())),
+            w_le_u32(self.uniform_blocks.len() as u32),
+            wh_all(self.uniform_blocks.iter().map(|b| b.write())),
+            w_le_u32(self.attributes.len() as u32),
+            wh_all(self.attributes.iter().map(|a| a.write())),
+            w_le_i32(self.vertex_size),
+            w_le_i32(self.vertex_pos_offset),
+            w_le_u32(self.textures.len() as u32),
+            wh_all(self.textures.iter().map(|t| t.write())),
+            w_le_u32(self.tags.len() as u32),
+            wh_all(self.tags.iter().map(wh_string)),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialUniformBlock {
+    pub name: String,
+    pub uniforms: Vec<MaterialUniform>,
+}
+
+impl Parsable for MaterialUniformBlock {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, length_count(le_u32, MaterialUniform::parse))),
+            |(name, uniforms)| MaterialUniformBlock { name, uniforms },
+        )(i)
+    }
+}
+
+impl Writable for MaterialUniformBlock {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            w_le_u32(self.uniforms.len() as u32),
+            wh_all(self.uniforms.iter().map(|u| u.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialUniform {
+    pub name: String,
+    pub uniform_type: ShaderParameterType,
+    pub default_value: ConfigNode,
+    pub range: Option<(f32, f32)>,
+    pub granularity: Option<f32>,
+    pub editable: bool,
+    pub auto_variable: String,
+}
+
+impl Parsable for MaterialUniform {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                h_enum,
+                h_confignode,
+                h_option(tuple((le_f32, le_f32))),
+                h_option(le_f32),
+                h_bool,
+                h_string,
+            )),
+            |(name, uniform_type, default_value, range, granularity, editable, auto_variable)| {
+                MaterialUniform {
+                    name,
+                    uniform_type,
+                    default_value,
+                    range,
+                    granularity,
+                    editable,
+                    auto_variable,
+                }
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialUniform {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_enum(&self.uniform_type),
+            wh_confignode(&self.default_value),
+            wh_option(&self.range, |(start, end)| {
+                Box::new(wh_tuple((w_le_f32(*start), w_le_f32(*end))))
+            }),
+            wh_option(&self.granularity, |g| Box::new(w_le_f32(*g))),
+            wh_bool(self.editable),
+            wh_string(&self.auto_variable),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum TextureSamplerType {
+    Invalid = 0,
+    Texture2D = 1,
+    Depth,
+    Stencil,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MaterialTexture {
+    pub name: String,
+    pub default_texture_name: String,
+    pub sampler_type: TextureSamplerType,
+}
+
+impl Parsable for MaterialTexture {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, h_string, h_enum)),
+            |(name, default_texture_name, sampler_type)| MaterialTexture {
+                name,
+                default_texture_name,
+                sampler_type,
+            },
+        )(i)
+    }
+}
+
+impl Writable for MaterialTexture {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_string(&self.default_texture_name),
+            wh_enum(&self.sampler_type),
+        ));
+        Box::new(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::{
+            common::hpk::write_to_vec,
+            v2020::material::{
+                BlendType, CullingMode, DepthStencilComparisonFunction, MaterialDepthStencil,
+                StencilWriteOperation,
+            },
+        },
+    };
+
+    #[test]
+    fn test_material_round_trip() {
+        let material = MaterialDefinition {
+            name: "sprite".to_string(),
+            passes: vec![MaterialPass {
+                blend: BlendType::Add,
+                shader_asset_id: "shaders/sprite".to_string(),
+                cull: CullingMode::None,
+                depth_stencil: MaterialDepthStencil {
+                    enable_depth_test: false,
+                    enable_depth_write: true,
+                    enable_stencil_test: false,
+                    stencil_reference: 7,
+                    stencil_write_mask: 0xff,
+                    stencil_read_mask: 0x01,
+                    depth_comparison: DepthStencilComparisonFunction::Less,
+                    stencil_comparison: DepthStencilComparisonFunction::NotEqual,
+                    stencil_op_pass: StencilWriteOperation::IncrementWrap,
+                    stencil_op_depth_fail: StencilWriteOperation::Zero,
+                    stencil_op_stencil_fail: StencilWriteOperation::Keep,
+                },
+            }],
+            uniform_blocks: vec![MaterialUniformBlock {
+                name: "MaterialBlock".to_string(),
+                uniforms: vec![
+                    MaterialUniform {
+                        name: "u_intensity".to_string(),
+                        uniform_type: ShaderParameterType::Float,
+                        default_value: ConfigNode::Float(0.1),
+                        range: Some((0.0, 2.5)),
+                        granularity: Some(0.05),
+                        editable: true,
+                        auto_variable: "".to_string(),
+                    },
+                    MaterialUniform {
+                        name: "u_time".to_string(),
+                        uniform_type: ShaderParameterType::Float,
+                        default_value: ConfigNode::Undefined,
+                        range: None,
+                        granularity: None,
+                        editable: false,
+                        auto_variable: "time".to_string(),
+                    },
+                ],
+            }],
+            attributes: vec![MaterialAttribute {
+                name: "a_position".to_string(),
+                attribute_type: ShaderParameterType::Float4,
+                location: 1,
+                offset: 0,
+                semantic: "POSITION".to_string(),
+                semantic_index: 0,
+            }],
+            vertex_size: 32,
+            vertex_pos_offset: 0,
+            textures: vec![
+                MaterialTexture {
+                    name: "tex0".to_string(),
+                    default_texture_name: "white".to_string(),
+                    sampler_type: TextureSamplerType::Texture2D,
+                },
+                MaterialTexture {
+                    name: "depth".to_string(),
+                    default_texture_name: "".to_string(),
+                    sampler_type: TextureSamplerType::Depth,
+                },
+            ],
+            tags: vec!["sprite".to_string(), "ui".to_string()],
+        };
+        assert_eq!(material.texture_references(), vec!["white"]);
+
+        let bytes = write_to_vec(&material).unwrap();
+        let (rest, parsed) = MaterialDefinition::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: MaterialDefinition = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
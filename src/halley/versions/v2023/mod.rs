@@ -1,6 +1,15 @@
 // version from 2023-05-24
 pub mod animation;
 pub mod audio_event;
+pub mod audio_object;
+pub mod font;
+pub mod game_properties;
 pub mod hlif;
 pub mod hpk;
+pub mod material;
+pub mod navmesh;
+pub mod prefab;
+pub mod render_graph;
+pub mod script_graph;
 pub mod spritesheet;
+pub mod ui_definition;
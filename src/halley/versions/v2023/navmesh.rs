@@ -0,0 +1,379 @@
+use crate::halley::versions::common::{
+    hpk::{Parsable, Writable},
+    primitives::{h_option, h_string, wh_option, wh_string},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u16 as w_le_u16, le_u32 as w_le_u32},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u16, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Navigation meshes of a world, one per region. Vertices are in world coordinates.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct NavmeshSet {
+    pub navmeshes: Vec<Navmesh>,
+    pub sub_world_portals: Vec<SubWorldPortal>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Navmesh {
+    pub region: i32,
+    pub region_name: String,
+    pub sub_world: i32,
+    pub origin: (f32, f32),
+    pub polygons: Vec<NavmeshPolygon>,
+    pub portals: Vec<NavmeshPortal>,
+}
+
+/// Convex polygon of a navmesh. `connections` has one entry per edge, holding the polygon on
+/// the other side of that edge, if any. Unpacked, edges without one hold -1, since TOML arrays
+/// can't hold an empty value.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct NavmeshPolygon {
+    pub vertices: Vec<(f32, f32)>,
+    pub weight: f32,
+    #[serde(with = "connections")]
+    #[schemars(with = "Vec<i32>")]
+    pub connections: Vec<Option<u16>>,
+}
+
+mod connections {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    static NONE: i32 = -1;
+
+    pub fn serialize<S: Serializer>(c: &[Option<u16>], serializer: S) -> Result<S::Ok, S::Error> {
+        c.iter()
+            .map(|c| c.map_or(NONE, i32::from))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Option<u16>>, D::Error> {
+        Vec::<i32>::deserialize(deserializer)?
+            .into_iter()
+            .map(|c| match c == NONE {
+                true => Ok(None),
+                false => u16::try_from(c).map(Some).map_err(D::Error::custom),
+            })
+            .collect()
+    }
+}
+
+/// Edge shared with the navmeshes of neighbouring regions.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct NavmeshPortal {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+    pub polygon: u16,
+    pub links: Vec<NavmeshPortalLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct NavmeshPortalLink {
+    pub region: i32,
+    pub portal: u32,
+}
+
+/// Link between polygons on different sub worlds, such as stairs between floors.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SubWorldPortal {
+    pub from_region: i32,
+    pub from_polygon: u16,
+    pub to_region: i32,
+    pub to_polygon: u16,
+}
+
+fn h_vertex(i: &[u8]) -> IResult<&[u8], (f32, f32)> {
+    tuple((le_f32, le_f32))(i)
+}
+
+fn wh_vertex<'a>(v: &(f32, f32)) -> impl SerializeFn<Vec<u8>> + 'a {
+    wh_tuple((w_le_f32(v.0), w_le_f32(v.1)))
+}
+
+impl Parsable for NavmeshSet {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                length_count(le_u32, Navmesh::parse),
+                length_count(le_u32, SubWorldPortal::parse),
+            )),
+            |(navmeshes, sub_world_portals)| NavmeshSet {
+                navmeshes,
+                sub_world_portals,
+            },
+        )(i)
+    }
+}
+
+impl Writable for NavmeshSet {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.navmeshes.len() as u32),
+            wh_all(self.navmeshes.iter().map(|n| n.write())),
+            w_le_u32(self.sub_world_portals.len() as u32),
+            wh_all(self.sub_world_portals.iter().map(|p| p.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for Navmesh {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                le_i32,
+                h_string,
+                le_i32,
+                h_vertex,
+                length_count(le_u32, NavmeshPolygon::parse),
+                length_count(le_u32, NavmeshPortal::parse),
+            )),
+            |(region, region_name, sub_world, origin, polygons, portals)| Navmesh {
+                region,
+                region_name,
+                sub_world,
+                origin,
+                polygons,
+                portals,
+            },
+        )(i)
+    }
+}
+
+impl Writable for Navmesh {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_i32(self.region),
+            wh_string(&self.region_name),
+            w_le_i32(self.sub_world),
+            wh_vertex(&self.origin),
+            w_le_u32(self.polygons.len() as u32),
+            wh_all(self.polygons.iter().map(|p| p.write())),
+            w_le_u32(self.portals.len() as u32),
+            wh_all(self.portals.iter().map(|p| p.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for NavmeshPolygon {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                length_count(le_u32, h_vertex),
+                le_f32,
+                length_count(le_u32, h_option(le_u16)),
+            )),
+            |(vertices, weight, connections)| NavmeshPolygon {
+                vertices,
+                weight,
+                connections,
+            },
+        )(i)
+    }
+}
+
+impl Writable for NavmeshPolygon {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.vertices.len() as u32),
+            wh_all(self.vertices.iter().map(wh_vertex)),
+            w_le_f32(self.weight),
+            w_le_u32(self.connections.len() as u32),
+            wh_all(
+                self.connections
+                    .iter()
+                    .map(|c| wh_option(c, |c| Box::new(w_le_u16(*c)))),
+            ),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for NavmeshPortal {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_vertex,
+                h_vertex,
+                le_u16,
+                length_count(le_u32, NavmeshPortalLink::parse),
+            )),
+            |(from, to, polygon, links)| NavmeshPortal {
+                from,
+                to,
+                polygon,
+                links,
+            },
+        )(i)
+    }
+}
+
+impl Writable for NavmeshPortal {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_vertex(&self.from),
+            wh_vertex(&self.to),
+            w_le_u16(self.polygon),
+            w_le_u32(self.links.len() as u32),
+            wh_all(self.links.iter().map(|l| l.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for NavmeshPortalLink {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((le_i32, le_u32)), |(region, portal)| {
+            NavmeshPortalLink { region, portal }
+        })(i)
+    }
+}
+
+impl Writable for NavmeshPortalLink {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((w_le_i32(self.region), w_le_u32(self.portal)));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for SubWorldPortal {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((le_i32, le_u16, le_i32, le_u16)),
+            |(from_region, from_polygon, to_region, to_polygon)| SubWorldPortal {
+                from_region,
+                from_polygon,
+                to_region,
+                to_polygon,
+            },
+        )(i)
+    }
+}
+
+impl Writable for SubWorldPortal {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_i32(self.from_region),
+            w_le_u16(self.from_polygon),
+            w_le_i32(self.to_region),
+            w_le_u16(self.to_polygon),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl NavmeshSet {
+    pub fn find_navmesh(&self, region: i32) -> Option<&Navmesh> {
+        self.navmeshes.iter().find(|n| n.region == region)
+    }
+
+    /// Minimum and maximum of all polygon vertices.
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        self.navmeshes
+            .iter()
+            .flat_map(|n| n.polygons.iter())
+            .flat_map(|p| p.vertices.iter())
+            .fold(
+                ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+                |(min, max), v| {
+                    (
+                        (min.0.min(v.0), min.1.min(v.1)),
+                        (max.0.max(v.0), max.1.max(v.1)),
+                    )
+                },
+            )
+    }
+}
+
+impl NavmeshPolygon {
+    pub fn centre(&self) -> (f32, f32) {
+        let n = self.vertices.len().max(1) as f32;
+        let (x, y) = self
+            .vertices
+            .iter()
+            .fold((0.0, 0.0), |(x, y), v| (x + v.0, y + v.1));
+        (x / n, y / n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::hpk::write_to_vec,
+    };
+
+    #[test]
+    fn test_navmesh_round_trip() {
+        let square = |x: f32, connections| NavmeshPolygon {
+            vertices: vec![(x, 0.0), (x + 1.5, 0.0), (x + 1.5, 0.1), (x, 0.1)],
+            weight: 1.0,
+            connections,
+        };
+        let set = NavmeshSet {
+            navmeshes: vec![
+                Navmesh {
+                    region: 3,
+                    region_name: "hall".to_string(),
+                    sub_world: 0,
+                    origin: (-10.0, 2.5),
+                    polygons: vec![
+                        square(0.0, vec![None, Some(1), None, None]),
+                        square(1.5, vec![None, None, None, Some(0)]),
+                    ],
+                    portals: vec![NavmeshPortal {
+                        from: (3.0, 0.0),
+                        to: (3.0, 0.1),
+                        polygon: 1,
+                        links: vec![NavmeshPortalLink {
+                            region: 4,
+                            portal: 0,
+                        }],
+                    }],
+                },
+                Navmesh {
+                    region: 4,
+                    region_name: "".to_string(),
+                    sub_world: 1,
+                    origin: (0.0, 0.0),
+                    polygons: vec![],
+                    portals: vec![],
+                },
+            ],
+            sub_world_portals: vec![SubWorldPortal {
+                from_region: 3,
+                from_polygon: 0,
+                to_region: 4,
+                to_polygon: u16::MAX,
+            }],
+        };
+        assert_eq!(set.bounds(), ((0.0, 0.0), (3.0, 0.1)));
+
+        let bytes = write_to_vec(&set).unwrap();
+        let (rest, parsed) = NavmeshSet::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: NavmeshSet = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
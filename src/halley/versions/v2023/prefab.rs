@@ -0,0 +1,239 @@
+use crate::halley::versions::common::{
+    config::{h_confignode, wh_confignode, ConfigNodeMap},
+    hpk::{Parsable, Writable},
+    primitives::{h_string, wh_string},
+    uuid::{h_uuid, wh_uuid, Uuid},
+};
+use cookie_factory::{
+    bytes::{le_u32 as w_le_u32, le_u8 as w_le_u8},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_u32, u8},
+    sequence::tuple,
+    IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Prefab with a single root entity, laid out like the engine's `.prefab` source files.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Prefab {
+    pub entity: EntityData,
+}
+
+/// Scene with any number of root entities, laid out like the engine's `.scene` source files.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Scene {
+    pub entities: Vec<EntityData>,
+}
+
+/// Entity of a prefab or scene. Each entry of `components` is a map from the component name
+/// to its fields. Entities instancing a prefab name it in `prefab`, and only hold the
+/// components and children that override it.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct EntityData {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prefab: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub icon: String,
+    #[serde(default)]
+    pub uuid: Uuid,
+    #[serde(rename = "prefabUUID", default, skip_serializing_if = "Uuid::is_nil")]
+    pub prefab_uuid: Uuid,
+    #[serde(rename = "parentUUID", default, skip_serializing_if = "Uuid::is_nil")]
+    pub parent_uuid: Uuid,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub flags: u8,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<ConfigNodeMap>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<EntityData>,
+}
+
+fn is_zero(v: &u8) -> bool {
+    *v == 0
+}
+
+impl Parsable for Prefab {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(EntityData::parse, |entity| Prefab { entity })(i)
+    }
+}
+
+impl Writable for Prefab {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        self.entity.write()
+    }
+}
+
+impl Parsable for Scene {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(length_count(le_u32, EntityData::parse), |entities| Scene {
+            entities,
+        })(i)
+    }
+}
+
+impl Writable for Scene {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.entities.len() as u32),
+            wh_all(self.entities.iter().map(|e| e.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for EntityData {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                tuple((h_string, h_string, h_string)),
+                tuple((h_uuid, h_uuid, h_uuid)),
+                u8,
+                length_count(le_u32, tuple((h_string, h_confignode))),
+                length_count(le_u32, EntityData::parse),
+            )),
+            |(
+                (name, prefab, icon),
+                (uuid, prefab_uuid, parent_uuid),
+                flags,
+                components,
+                children,
+            )| EntityData {
+                name,
+                prefab,
+                icon,
+                uuid,
+                prefab_uuid,
+                parent_uuid,
+                flags,
+                components: components
+                    .into_iter()
+                    .map(|(name, data)| ConfigNodeMap::from([(name, data)]))
+                    .collect(),
+                children,
+            },
+        )(i)
+    }
+}
+
+impl Writable for EntityData {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let components: Vec<_> = self.components.iter().flatten().collect();
+        let writer = wh_tuple((
+            wh_string(&self.name),
+            wh_string(&self.prefab),
+            wh_string(&self.icon),
+            wh_uuid(&self.uuid),
+            wh_uuid(&self.prefab_uuid),
+            wh_uuid(&self.parent_uuid),
+            w_le_u8(self.flags),
+            w_le_u32(components.len() as u32),
+            wh_all(
+                components
+                    .into_iter()
+                    .map(|(name, data)| wh_tuple((wh_string(name), wh_confignode(data)))),
+            ),
+            w_le_u32(self.children.len() as u32),
+            wh_all(self.children.iter().map(|c| c.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl EntityData {
+    /// Names of the prefabs instanced by this entity and its descendants.
+    pub fn prefab_references(&self) -> Vec<&str> {
+        std::iter::once(self.prefab.as_str())
+            .filter(|p| !p.is_empty())
+            .chain(self.children.iter().flat_map(|c| c.prefab_references()))
+            .collect()
+    }
+}
+
+impl Scene {
+    pub fn prefab_references(&self) -> Vec<&str> {
+        self.entities
+            .iter()
+            .flat_map(|e| e.prefab_references())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::{config::ConfigNode, hpk::write_to_vec},
+    };
+
+    fn entity(name: &str, prefab: &str, uuid: u8, children: Vec<EntityData>) -> EntityData {
+        let transform = ConfigNodeMap::from([
+            ("position".to_string(), ConfigNode::Float2((0.5, -2.0))),
+            ("layer".to_string(), ConfigNode::Int(2)),
+        ]);
+        EntityData {
+            name: name.to_string(),
+            prefab: prefab.to_string(),
+            icon: "".to_string(),
+            uuid: Uuid([uuid; 16]),
+            prefab_uuid: Uuid::default(),
+            parent_uuid: Uuid([uuid + 1; 16]),
+            flags: uuid % 2,
+            components: vec![
+                ConfigNodeMap::from([("Transform2D".to_string(), ConfigNode::Map(transform))]),
+                ConfigNodeMap::from([("Marker".to_string(), ConfigNode::Undefined)]),
+            ],
+            children,
+        }
+    }
+
+    #[test]
+    fn test_prefab_and_scene_round_trip() {
+        let prefab = Prefab {
+            entity: entity(
+                "door",
+                "",
+                1,
+                vec![entity("handle", "props/handle", 3, vec![])],
+            ),
+        };
+        let scene = Scene {
+            entities: vec![
+                entity("room", "", 5, vec![]),
+                entity("", "props/door", 7, vec![]),
+            ],
+        };
+        assert_eq!(prefab.entity.prefab_references(), vec!["props/handle"]);
+        assert_eq!(scene.prefab_references(), vec!["props/door"]);
+
+        let bytes = write_to_vec(&prefab).unwrap();
+        let (rest, parsed) = Prefab::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: Prefab = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+
+        let bytes = write_to_vec(&scene).unwrap();
+        let (rest, parsed) = Scene::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: Scene = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::halley::{
+    assets::graph::{GraphConvertible, GraphEdge, GraphNode},
+    versions::common::{
+        config::{h_confignode, wh_confignode, ConfigNode},
+        hpk::{Parsable, Writable},
+        primitives::{h_enum, h_string, wh_enum, wh_string},
+    },
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_u32 as w_le_u32, le_u8 as w_le_u8},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_u32, u8},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+static MATERIAL_KEYS: &[&str] = &["material"];
+static TEXTURE_KEYS: &[&str] = &["texture", "textures"];
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RenderGraphDefinition {
+    pub nodes: Vec<RenderGraphNode>,
+    pub connections: Vec<RenderGraphConnection>,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum RenderGraphMethod {
+    None = 0,
+    Paint = 1,
+    Overlay,
+    Output,
+    ImageOutput,
+    RenderToTexture,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum RenderGraphPinType {
+    Unknown = 0,
+    ColourBuffer = 1,
+    DepthStencilBuffer,
+    Texture,
+    Dependency,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RenderGraphNode {
+    pub id: String,
+    pub method: RenderGraphMethod,
+    pub method_parameters: ConfigNode,
+    pub position: (f32, f32),
+    pub input_pins: Vec<RenderGraphPinType>,
+    pub output_pins: Vec<RenderGraphPinType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RenderGraphConnection {
+    pub from: String,
+    pub to: String,
+    pub from_pin: u8,
+    pub to_pin: u8,
+}
+
+impl Parsable for RenderGraphDefinition {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                length_count(le_u32, RenderGraphNode::parse),
+                length_count(le_u32, RenderGraphConnection::parse),
+            )),
+            |(nodes, connections)| RenderGraphDefinition { nodes, connections },
+        )(i)
+    }
+}
+
+impl Writable for RenderGraphDefinition {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.nodes.len() as u32),
+            wh_all(self.nodes.iter().map(|n| n.write())),
+            w_le_u32(self.connections.len() as u32),
+            wh_all(self.connections.iter().map(|c| c.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for RenderGraphNode {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                h_enum,
+                h_confignode,
+                tuple((le_f32, le_f32)),
+                length_count(le_u32, h_enum),
+                length_count(le_u32, h_enum),
+            )),
+            |(id, method, method_parameters, position, input_pins, output_pins)| RenderGraphNode {
+                id,
+                method,
+                method_parameters,
+                position,
+                input_pins,
+                output_pins,
+            },
+        )(i)
+    }
+}
+
+impl Writable for RenderGraphNode {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.id),
+            wh_enum(&self.method),
+            wh_confignode(&self.method_parameters),
+            w_le_f32(self.position.0),
+            w_le_f32(self.position.1),
+            w_le_u32(self.input_pins.len() as u32),
+            wh_all(self.input_pins.iter().map(wh_enum)),
+            w_le_u32(self.output_pins.len() as u32),
+            wh_all(self.output_pins.iter().map(wh_enum)),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for RenderGraphConnection {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_string, h_string, u8, u8)),
+            |(from, to, from_pin, to_pin)| RenderGraphConnection {
+                from,
+                to,
+                from_pin,
+                to_pin,
+            },
+        )(i)
+    }
+}
+
+impl Writable for RenderGraphConnection {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.from),
+            wh_string(&self.to),
+            w_le_u8(self.from_pin),
+            w_le_u8(self.to_pin),
+        ));
+        Box::new(writer)
+    }
+}
+
+fn get_parameter_strings<'a>(parameters: &'a ConfigNode, keys: &[&str]) -> Vec<&'a String> {
+    let ConfigNode::Map(map) = parameters else {
+        return vec![];
+    };
+    keys.iter()
+        .filter_map(|k| map.get(*k))
+        .flat_map(|v| match v {
+            ConfigNode::String(s) => vec![s],
+            ConfigNode::Sequence(seq) => seq
+                .iter()
+                .filter_map(|s| match s {
+                    ConfigNode::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        })
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl RenderGraphDefinition {
+    pub fn material_references(&self) -> Vec<&String> {
+        self.nodes
+            .iter()
+            .flat_map(|n| get_parameter_strings(&n.method_parameters, MATERIAL_KEYS))
+            .collect()
+    }
+
+    pub fn texture_references(&self) -> Vec<&String> {
+        self.nodes
+            .iter()
+            .flat_map(|n| get_parameter_strings(&n.method_parameters, TEXTURE_KEYS))
+            .collect()
+    }
+}
+
+impl GraphConvertible for RenderGraphDefinition {
+    fn to_graph(&self) -> (Vec<GraphNode>, Vec<GraphEdge>) {
+        let pins = |pins: &[RenderGraphPinType]| pins.iter().map(|p| format!("{:?}", p)).collect();
+        let nodes: Vec<GraphNode> = self
+            .nodes
+            .iter()
+            .map(|n| {
+                let mut label = format!("{}\n{:?}", n.id, n.method);
+                for material in get_parameter_strings(&n.method_parameters, MATERIAL_KEYS) {
+                    label.push_str(&format!("\n{}", material));
+                }
+                GraphNode {
+                    id: n.id.clone(),
+                    label,
+                    inputs: pins(&n.input_pins),
+                    outputs: pins(&n.output_pins),
+                }
+            })
+            .collect();
+        let edges: Vec<GraphEdge> = self
+            .connections
+            .iter()
+            .map(|c| GraphEdge {
+                from: c.from.clone(),
+                from_pin: Some(c.from_pin as usize),
+                to: c.to.clone(),
+                to_pin: Some(c.to_pin as usize),
+                label: None,
+            })
+            .collect();
+        (nodes, edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::{config::ConfigNodeMap, hpk::write_to_vec},
+    };
+
+    #[test]
+    fn test_render_graph_round_trip() {
+        let parameters = ConfigNodeMap::from([
+            (
+                "material".to_string(),
+                ConfigNode::String("materials/blur".to_string()),
+            ),
+            (
+                "textures".to_string(),
+                ConfigNode::Sequence(vec![
+                    ConfigNode::String("noise".to_string()),
+                    ConfigNode::String("".to_string()),
+                ]),
+            ),
+            ("scale".to_string(), ConfigNode::Float(0.1)),
+        ]);
+        let graph = RenderGraphDefinition {
+            nodes: vec![
+                RenderGraphNode {
+                    id: "paint".to_string(),
+                    method: RenderGraphMethod::Paint,
+                    method_parameters: ConfigNode::Undefined,
+                    position: (-120.5, 1.0 / 3.0),
+                    input_pins: vec![],
+                    output_pins: vec![
+                        RenderGraphPinType::ColourBuffer,
+                        RenderGraphPinType::DepthStencilBuffer,
+                    ],
+                },
+                RenderGraphNode {
+                    id: "blur".to_string(),
+                    method: RenderGraphMethod::Overlay,
+                    method_parameters: ConfigNode::Map(parameters),
+                    position: (80.0, 0.0),
+                    input_pins: vec![RenderGraphPinType::Texture],
+                    output_pins: vec![RenderGraphPinType::ColourBuffer],
+                },
+            ],
+            connections: vec![RenderGraphConnection {
+                from: "paint".to_string(),
+                to: "blur".to_string(),
+                from_pin: 0,
+                to_pin: 255,
+            }],
+        };
+        assert_eq!(graph.material_references(), vec!["materials/blur"]);
+        assert_eq!(graph.texture_references(), vec!["noise"]);
+
+        let bytes = write_to_vec(&graph).unwrap();
+        let (rest, parsed) = RenderGraphDefinition::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: RenderGraphDefinition = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::halley::{
+    assets::graph::{GraphConvertible, GraphEdge, GraphNode},
+    versions::common::{
+        config::{h_confignode, wh_confignode, ConfigNode},
+        hpk::{Parsable, Writable},
+        primitives::{h_option, h_string, wh_option, wh_string},
+    },
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_u32 as w_le_u32, le_u8 as w_le_u8},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_u32, u8},
+    sequence::tuple,
+    IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Nodes of a script graph. Connections refer to other nodes by their index in `nodes`, and are
+/// stored on both of the pins they link.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ScriptGraph {
+    pub nodes: Vec<ScriptGraphNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ScriptGraphNode {
+    pub node_type: String,
+    pub position: (f32, f32),
+    pub settings: ConfigNode,
+    pub parent_node: Option<u32>,
+    pub pins: Vec<ScriptGraphPin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ScriptGraphPin {
+    pub connections: Vec<ScriptGraphConnection>,
+}
+
+/// Link from a pin to a pin of another node, or to an entity of the scene running the script.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ScriptGraphConnection {
+    pub dst_node: Option<u32>,
+    pub dst_pin: u8,
+    pub entity_idx: Option<u32>,
+}
+
+impl Parsable for ScriptGraph {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(length_count(le_u32, ScriptGraphNode::parse), |nodes| {
+            ScriptGraph { nodes }
+        })(i)
+    }
+}
+
+impl Writable for ScriptGraph {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.nodes.len() as u32),
+            wh_all(self.nodes.iter().map(|n| n.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for ScriptGraphNode {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                h_string,
+                tuple((le_f32, le_f32)),
+                h_confignode,
+                h_option(le_u32),
+                length_count(le_u32, ScriptGraphPin::parse),
+            )),
+            |(node_type, position, settings, parent_node, pins)| ScriptGraphNode {
+                node_type,
+                position,
+                settings,
+                parent_node,
+                pins,
+            },
+        )(i)
+    }
+}
+
+impl Writable for ScriptGraphNode {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.node_type),
+            w_le_f32(self.position.0),
+            w_le_f32(self.position.1),
+            wh_confignode(&self.settings),
+            wh_option(&self.parent_node, |p| Box::new(w_le_u32(*p))),
+            w_le_u32(self.pins.len() as u32),
+            wh_all(self.pins.iter().map(|p| p.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for ScriptGraphPin {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            length_count(le_u32, ScriptGraphConnection::parse),
+            |connections| ScriptGraphPin { connections },
+        )(i)
+    }
+}
+
+impl Writable for ScriptGraphPin {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            w_le_u32(self.connections.len() as u32),
+            wh_all(self.connections.iter().map(|c| c.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for ScriptGraphConnection {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_option(le_u32), u8, h_option(le_u32))),
+            |(dst_node, dst_pin, entity_idx)| ScriptGraphConnection {
+                dst_node,
+                dst_pin,
+                entity_idx,
+            },
+        )(i)
+    }
+}
+
+impl Writable for ScriptGraphConnection {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_option(&self.dst_node, |n| Box::new(w_le_u32(*n))),
+            w_le_u8(self.dst_pin),
+            wh_option(&self.entity_idx, |e| Box::new(w_le_u32(*e))),
+        ));
+        Box::new(writer)
+    }
+}
+
+fn get_setting_lines(settings: &ConfigNode) -> Vec<String> {
+    let ConfigNode::Map(map) = settings else {
+        return vec![];
+    };
+    map.iter()
+        .filter_map(|(k, v)| match v {
+            ConfigNode::String(s) => Some(format!("{} = {}", k, s)),
+            ConfigNode::Int(n) => Some(format!("{} = {}", k, n)),
+            ConfigNode::Int64(n) => Some(format!("{} = {}", k, n)),
+            ConfigNode::Float(n) => Some(format!("{} = {}", k, n)),
+            ConfigNode::Bool(b) => Some(format!("{} = {}", k, b)),
+            _ => None,
+        })
+        .collect()
+}
+
+impl GraphConvertible for ScriptGraph {
+    /// Each link is drawn once, from the pin with the lowest node and pin index. Pin directions
+    /// come from the node type and are not stored in the graph, so pins are not drawn as ports.
+    fn to_graph(&self) -> (Vec<GraphNode>, Vec<GraphEdge>) {
+        let mut nodes = vec![];
+        let mut edges = vec![];
+
+        for (n, node) in self.nodes.iter().enumerate() {
+            let mut label = format!("#{} {}", n, node.node_type);
+            for line in get_setting_lines(&node.settings) {
+                label.push_str(&format!("\n{}", line));
+            }
+
+            for (p, pin) in node.pins.iter().enumerate() {
+                for connection in &pin.connections {
+                    if let Some(entity) = connection.entity_idx {
+                        label.push_str(&format!("\npin {} → entity {}", p, entity));
+                    }
+                    let Some(dst) = connection.dst_node else {
+                        continue;
+                    };
+                    if (dst as usize, connection.dst_pin as usize) < (n, p)
+                        && self.is_linked(dst, connection.dst_pin, n, p)
+                    {
+                        continue;
+                    }
+                    edges.push(GraphEdge {
+                        from: n.to_string(),
+                        from_pin: None,
+                        to: dst.to_string(),
+                        to_pin: None,
+                        label: Some(format!("{} → {}", p, connection.dst_pin)),
+                    });
+                }
+            }
+
+            if let Some(parent) = node.parent_node {
+                edges.push(GraphEdge {
+                    from: parent.to_string(),
+                    from_pin: None,
+                    to: n.to_string(),
+                    to_pin: None,
+                    label: Some("child".to_string()),
+                });
+            }
+
+            nodes.push(GraphNode {
+                id: n.to_string(),
+                label,
+                inputs: vec![],
+                outputs: vec![],
+            });
+        }
+        (nodes, edges)
+    }
+}
+
+impl ScriptGraph {
+    fn is_linked(&self, node: u32, pin: u8, dst_node: usize, dst_pin: usize) -> bool {
+        self.nodes
+            .get(node as usize)
+            .and_then(|n| n.pins.get(pin as usize))
+            .is_some_and(|p| {
+                p.connections
+                    .iter()
+                    .any(|c| c.dst_node == Some(dst_node as u32) && c.dst_pin as usize == dst_pin)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::{config::ConfigNodeMap, hpk::write_to_vec},
+    };
+
+    #[test]
+    fn test_script_graph_round_trip() {
+        let connection = |dst_node, dst_pin, entity_idx| ScriptGraphConnection {
+            dst_node,
+            dst_pin,
+            entity_idx,
+        };
+        let graph = ScriptGraph {
+            nodes: vec![
+                ScriptGraphNode {
+                    node_type: "start".to_string(),
+                    position: (0.0, -32.25),
+                    settings: ConfigNode::Undefined,
+                    parent_node: None,
+                    pins: vec![ScriptGraphPin {
+                        connections: vec![connection(Some(1), 0, None)],
+                    }],
+                },
+                ScriptGraphNode {
+                    node_type: "wait".to_string(),
+                    position: (160.0, 0.1),
+                    settings: ConfigNode::Map(ConfigNodeMap::from([(
+                        "time".to_string(),
+                        ConfigNode::Float(1.5),
+                    )])),
+                    parent_node: Some(0),
+                    pins: vec![
+                        ScriptGraphPin {
+                            connections: vec![connection(Some(0), 0, None)],
+                        },
+                        ScriptGraphPin {
+                            connections: vec![connection(None, 2, Some(7))],
+                        },
+                        ScriptGraphPin {
+                            connections: vec![],
+                        },
+                    ],
+                },
+            ],
+        };
+
+        let bytes = write_to_vec(&graph).unwrap();
+        let (rest, parsed) = ScriptGraph::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: ScriptGraph = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
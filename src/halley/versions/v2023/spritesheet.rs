@@ -9,19 +9,67 @@ use cookie_factory::{
     combinator::cond as wh_cond,
     multi::all as wh_all,
     sequence::tuple as wh_tuple,
-    SerializeFn,
+    GenError, SerializeFn,
 };
 use indexmap::IndexMap;
 use nom::{
-    combinator::{cond, flat_map, map, peek},
+    combinator::{cond, flat_map, map, success},
     multi::length_count,
     number::complete::{le_f32, u8},
     sequence::tuple,
     IResult,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Latest sprite sheet version. Version 1 added `def_material_name`, version 2 `palette_name`.
+pub const SPRITESHEET_VERSION: u8 = 2;
+/// Material the engine uses for sprite sheets that don't name one.
+pub static DEFAULT_SPRITESHEET_MATERIAL: &str = "Halley/Sprite";
+
+/// Field added by a sprite sheet version, with what migrating across that version does to it.
+struct Migration {
+    since: u8,
+    field: &'static str,
+    /// What the field holds, as named in the warning when a downgrade drops it.
+    label: &'static str,
+    /// Value the engine assumes for sheets from before `since`.
+    default: &'static str,
+    get: fn(&SpriteSheet) -> &Option<String>,
+    get_mut: fn(&mut SpriteSheet) -> &mut Option<String>,
+}
+
+static MIGRATIONS: [Migration; 2] = [
+    Migration {
+        since: 1,
+        field: "def_material_name",
+        label: "default material",
+        default: DEFAULT_SPRITESHEET_MATERIAL,
+        get: |sheet| &sheet.def_material_name,
+        get_mut: |sheet| &mut sheet.def_material_name,
+    },
+    Migration {
+        since: 2,
+        field: "palette_name",
+        label: "palette",
+        default: "",
+        get: |sheet| &sheet.palette_name,
+        get_mut: |sheet| &mut sheet.palette_name,
+    },
+];
+
+#[derive(Error, Debug)]
+pub enum SpriteSheetError {
+    #[error("Unknown sprite sheet version {0}")]
+    UnknownVersion(u8),
+    #[error("Sprite sheet version {0} needs a {1}")]
+    MissingField(u8, &'static str),
+    #[error("Sprite sheet version {0} can't have a {1}")]
+    UnexpectedField(u8, &'static str),
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct SpriteSheet {
     pub v: u8,
     pub name: String,
@@ -34,9 +82,9 @@ pub struct SpriteSheet {
 
 impl Parsable for SpriteSheet {
     fn parse(i: &[u8]) -> IResult<&[u8], Self> {
-        let versioned = flat_map(peek(u8), |v| {
+        let versioned = flat_map(u8, |v| {
             tuple((
-                cond(v as i32 <= 255, u8),
+                success(v),
                 h_var_string,
                 length_count(h_var_u, Sprite::parse),
                 SpriteIdx::parse,
@@ -50,7 +98,7 @@ impl Parsable for SpriteSheet {
             versioned,
             |(v, name, sprites, sprite_idx, frame_tags, def_material_name, palette_name)| {
                 SpriteSheet {
-                    v: v.unwrap_or(0),
+                    v,
                     name,
                     sprites,
                     sprite_idx,
@@ -63,10 +111,66 @@ impl Parsable for SpriteSheet {
     }
 }
 
+impl SpriteSheet {
+    /// Checks that the optional fields are present exactly when `v` has them.
+    pub fn validate(&self) -> Result<(), SpriteSheetError> {
+        if self.v > SPRITESHEET_VERSION {
+            return Err(SpriteSheetError::UnknownVersion(self.v));
+        }
+        for migration in &MIGRATIONS {
+            let field = migration.field;
+            match (self.v >= migration.since, (migration.get)(self).is_some()) {
+                (true, false) => return Err(SpriteSheetError::MissingField(self.v, field)),
+                (false, true) => return Err(SpriteSheetError::UnexpectedField(self.v, field)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Migrates the sheet to version `v`. Upgrading fills the new fields with the engine's
+    /// defaults; downgrading drops them, and returns a warning for each one that had a value.
+    /// Packing migrates each sheet to its own `v`, and converting to the other pack version's.
+    pub fn migrate(mut self, v: u8) -> Result<(Self, Vec<String>), SpriteSheetError> {
+        if v > SPRITESHEET_VERSION {
+            return Err(SpriteSheetError::UnknownVersion(v));
+        }
+        let mut warnings = vec![];
+        for migration in &MIGRATIONS {
+            let field = (migration.get_mut)(&mut self);
+            if v >= migration.since {
+                field.get_or_insert_with(|| migration.default.to_string());
+            } else if let Some(value) = field.take().filter(|f| !f.is_empty()) {
+                warnings.push(format!(
+                    "{} {} was dropped, sprite sheet version {} doesn't have it",
+                    migration.label, value, v
+                ));
+            }
+        }
+        self.v = v;
+        Ok((self, warnings))
+    }
+
+    /// Migrates the sheet to the latest version.
+    pub fn upgrade(self) -> Self {
+        let (sheet, _) = self.migrate(SPRITESHEET_VERSION).unwrap();
+        sheet
+    }
+}
+
 impl Writable for SpriteSheet {
     fn write<'a>(&'a self) -> Box<dyn cookie_factory::SerializeFn<Vec<u8>> + 'a> {
+        if let Err(err) = self.validate() {
+            let message = err.to_string();
+            return Box::new(move |_| {
+                Err(GenError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    message.clone(),
+                )))
+            });
+        }
         let writer = wh_tuple((
-            w_le_u8(self.v), //wh_cond(self.v as i32 <= 255, w_le_u8(self.v)),
+            w_le_u8(self.v),
             wh_var_string(&self.name),
             wh_var_u(self.sprites.len() as u64),
             wh_all(self.sprites.iter().map(|s| s.write())),
@@ -75,18 +179,18 @@ impl Writable for SpriteSheet {
             wh_all(self.frame_tags.iter().map(|t| t.write())),
             wh_cond(
                 self.v >= 1,
-                wh_var_string(&self.def_material_name.clone().unwrap()),
+                wh_var_string(&self.def_material_name.clone().unwrap_or_default()),
             ),
             wh_cond(
                 self.v >= 2,
-                wh_var_string(&self.palette_name.clone().unwrap()),
+                wh_var_string(&self.palette_name.clone().unwrap_or_default()),
             ),
         ));
         Box::new(writer)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Sprite {
     pub pivot: (f32, f32),
     pub orig_pivot: (i32, i32),
@@ -165,8 +269,8 @@ impl Writable for Sprite {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SpriteIdx(IndexMap<String, i32>);
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SpriteIdx(pub IndexMap<String, i32>);
 
 impl Parsable for SpriteIdx {
     fn parse(i: &[u8]) -> IResult<&[u8], Self> {
@@ -197,7 +301,7 @@ impl Writable for SpriteIdx {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct FrameTag {
     pub name: String,
     pub to: i32,
@@ -228,7 +332,7 @@ impl Writable for FrameTag {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct SpriteResource {
     pub name: String,
     pub idx: u64,
@@ -248,3 +352,59 @@ impl Writable for SpriteResource {
         Box::new(writer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::versions::common::hpk::write_to_vec;
+
+    fn sheet(v: u8, def_material_name: Option<&str>, palette_name: Option<&str>) -> SpriteSheet {
+        SpriteSheet {
+            v,
+            name: "sheet".to_string(),
+            sprites: vec![],
+            sprite_idx: SpriteIdx(IndexMap::new()),
+            frame_tags: vec![],
+            def_material_name: def_material_name.map(String::from),
+            palette_name: palette_name.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_writer_rejects_inconsistent_fields() {
+        let err = write_to_vec(&sheet(2, Some("mat"), None)).unwrap_err();
+        assert!(err.to_string().contains("palette_name"));
+        assert!(write_to_vec(&sheet(0, Some("mat"), None)).is_err());
+        assert!(write_to_vec(&sheet(3, Some("mat"), Some(""))).is_err());
+
+        let data = write_to_vec(&sheet(1, Some("mat"), None)).unwrap();
+        let (_, parsed) = SpriteSheet::parse(&data).unwrap();
+        assert_eq!(parsed.v, 1);
+        assert_eq!(parsed.def_material_name.as_deref(), Some("mat"));
+    }
+
+    #[test]
+    fn test_migrations() {
+        let upgraded = sheet(0, None, None).upgrade();
+        assert_eq!(upgraded.v, SPRITESHEET_VERSION);
+        assert_eq!(
+            upgraded.def_material_name.as_deref(),
+            Some(DEFAULT_SPRITESHEET_MATERIAL)
+        );
+        assert!(upgraded.validate().is_ok());
+
+        let (downgraded, warnings) = sheet(2, Some("mat"), Some("")).migrate(0).unwrap();
+        assert_eq!(downgraded.v, 0);
+        assert!(downgraded.validate().is_ok());
+        assert_eq!(
+            warnings,
+            vec!["default material mat was dropped, sprite sheet version 0 doesn't have it"]
+        );
+
+        let (_, warnings) = sheet(2, Some(""), Some("pal")).migrate(1).unwrap();
+        assert_eq!(
+            warnings,
+            vec!["palette pal was dropped, sprite sheet version 1 doesn't have it"]
+        );
+    }
+}
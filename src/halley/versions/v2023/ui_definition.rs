@@ -0,0 +1,267 @@
+use crate::halley::versions::common::{
+    config::{h_confignode, wh_confignode, ConfigNode},
+    hpk::{Parsable, Writable},
+    primitives::{h_enum, h_option, h_string, wh_enum, wh_option, wh_string},
+};
+use cookie_factory::{
+    bytes::{le_f32 as w_le_f32, le_i32 as w_le_i32, le_u32 as w_le_u32},
+    multi::all as wh_all,
+    sequence::tuple as wh_tuple,
+    SerializeFn,
+};
+use nom::{
+    combinator::map,
+    multi::length_count,
+    number::complete::{le_f32, le_i32, le_u32},
+    sequence::tuple,
+    IResult,
+};
+use num_derive::{FromPrimitive, ToPrimitive};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UiDefinition {
+    pub widget: UiNode,
+}
+
+/// Node of the widget tree. Nodes with an empty `class` are plain sizers that only lay out
+/// their children. `properties` holds the widget specific fields, such as `text` for labels.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UiNode {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub class: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub style: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sizer: Option<UiSizer>,
+    pub proportion: f32,
+    pub border: (f32, f32, f32, f32),
+    pub fill: UiFill,
+    pub properties: ConfigNode,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bindings: Vec<UiBinding>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<UiNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UiSizer {
+    #[serde(rename = "type")]
+    pub sizer_type: UiSizerType,
+    pub gap: f32,
+    pub columns: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub column_proportions: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum UiSizerType {
+    Horizontal = 0,
+    Vertical = 1,
+    Grid,
+    Free,
+}
+
+#[derive(Serialize, Deserialize, Debug, FromPrimitive, ToPrimitive, JsonSchema)]
+pub enum UiFill {
+    None = 0,
+    Fill = 1,
+    FillHorizontal,
+    FillVertical,
+    Centre,
+    CentreHorizontal,
+    CentreVertical,
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// Binds a widget property to a value of the game's UI data, for example a label's `text` to
+/// `player.name`.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UiBinding {
+    pub property: String,
+    pub source: String,
+}
+
+impl Parsable for UiDefinition {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(UiNode::parse, |widget| UiDefinition { widget })(i)
+    }
+}
+
+impl Writable for UiDefinition {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        self.widget.write()
+    }
+}
+
+impl Parsable for UiNode {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                tuple((h_string, h_string, h_string)),
+                h_option(UiSizer::parse),
+                le_f32,
+                tuple((le_f32, le_f32, le_f32, le_f32)),
+                h_enum,
+                h_confignode,
+                length_count(le_u32, UiBinding::parse),
+                length_count(le_u32, UiNode::parse),
+            )),
+            |(
+                (id, class, style),
+                sizer,
+                proportion,
+                border,
+                fill,
+                properties,
+                bindings,
+                children,
+            )| UiNode {
+                id,
+                class,
+                style,
+                sizer,
+                proportion,
+                border,
+                fill,
+                properties,
+                bindings,
+                children,
+            },
+        )(i)
+    }
+}
+
+impl Writable for UiNode {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_string(&self.id),
+            wh_string(&self.class),
+            wh_string(&self.style),
+            wh_option(&self.sizer, |s| s.write()),
+            w_le_f32(self.proportion),
+            wh_tuple((
+                w_le_f32(self.border.0),
+                w_le_f32(self.border.1),
+                w_le_f32(self.border.2),
+                w_le_f32(self.border.3),
+            )),
+            wh_enum(&self.fill),
+            wh_confignode(&self.properties),
+            w_le_u32(self.bindings.len() as u32),
+            wh_all(self.bindings.iter().map(|b| b.write())),
+            w_le_u32(self.children.len() as u32),
+            wh_all(self.children.iter().map(|c| c.write())),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for UiSizer {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((h_enum, le_f32, le_i32, length_count(le_u32, le_f32))),
+            |(sizer_type, gap, columns, column_proportions)| UiSizer {
+                sizer_type,
+                gap,
+                columns,
+                column_proportions,
+            },
+        )(i)
+    }
+}
+
+impl Writable for UiSizer {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((
+            wh_enum(&self.sizer_type),
+            w_le_f32(self.gap),
+            w_le_i32(self.columns),
+            w_le_u32(self.column_proportions.len() as u32),
+            wh_all(self.column_proportions.iter().map(|p| w_le_f32(*p))),
+        ));
+        Box::new(writer)
+    }
+}
+
+impl Parsable for UiBinding {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(tuple((h_string, h_string)), |(property, source)| {
+            UiBinding { property, source }
+        })(i)
+    }
+}
+
+impl Writable for UiBinding {
+    fn write<'a>(&'a self) -> Box<dyn SerializeFn<Vec<u8>> + 'a> {
+        let writer = wh_tuple((wh_string(&self.property), wh_string(&self.source)));
+        Box::new(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::halley::{
+        assets::serialization::{deserialize, serialize, Format},
+        versions::common::{config::ConfigNodeMap, hpk::write_to_vec},
+    };
+
+    #[test]
+    fn test_ui_definition_round_trip() {
+        let label = UiNode {
+            id: "name".to_string(),
+            class: "label".to_string(),
+            style: "labelLight".to_string(),
+            sizer: None,
+            proportion: 0.0,
+            border: (2.0, 0.0, 2.0, 0.5),
+            fill: UiFill::Centre,
+            properties: ConfigNode::Map(ConfigNodeMap::from([(
+                "text".to_string(),
+                ConfigNode::String("Player".to_string()),
+            )])),
+            bindings: vec![UiBinding {
+                property: "text".to_string(),
+                source: "player.name".to_string(),
+            }],
+            children: vec![],
+        };
+        let ui = UiDefinition {
+            widget: UiNode {
+                id: "".to_string(),
+                class: "".to_string(),
+                style: "".to_string(),
+                sizer: Some(UiSizer {
+                    sizer_type: UiSizerType::Grid,
+                    gap: 4.0,
+                    columns: 2,
+                    column_proportions: vec![1.0, 2.5],
+                }),
+                proportion: 1.0,
+                border: (0.0, 0.0, 0.0, 0.0),
+                fill: UiFill::Fill,
+                properties: ConfigNode::Undefined,
+                bindings: vec![],
+                children: vec![label],
+            },
+        };
+
+        let bytes = write_to_vec(&ui).unwrap();
+        let (rest, parsed) = UiDefinition::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(bytes, write_to_vec(&parsed).unwrap());
+
+        for format in [Format::Toml5, Format::Yaml, Format::Json5] {
+            let str = serialize(&parsed, Some(format)).unwrap();
+            let t: UiDefinition = deserialize(&str, Some(format)).unwrap();
+            assert_eq!(bytes, write_to_vec(&t).unwrap());
+        }
+    }
+}
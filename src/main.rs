@@ -1,10 +1,20 @@
 use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use halleypack::halley::{
-    assets::unpack::unpack_halley_pk, pack_asset, read_pack,
-    versions::common::hsave::load_save_data, write_pack, PackVersion,
+    assets::{
+        round_trip::unpack_halley_pk_lossless,
+        serialization::Format,
+        unpack::{unpack_halley_pk, UnpackOptions},
+    },
+    convert_pack, export_font, export_graph, export_mesh, export_mesh_animation, export_navmesh,
+    export_schemas, import_font, pack_asset, read_pack,
+    versions::{
+        common::hsave::load_save_data,
+        format::{PackFormat, PackFormatRegistry},
+    },
+    write_pack,
 };
 
 //static SECRET_X: &str = "+Ohzep4z06NuKguNbFRz3w==";
@@ -21,8 +31,8 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     Unpack {
-        #[arg(short = 'p', long)]
-        pack_version: PackVersion,
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
 
         #[arg(short = 'i', long)]
         asset: PathBuf,
@@ -32,10 +42,30 @@ enum Commands {
 
         #[arg(short = 's', long)]
         secret: Option<String>,
+
+        /// Also write a manifest, so that packing the folder gives back the same file
+        #[arg(long)]
+        lossless: bool,
+
+        /// Format of the unpacked assets and property files, instead of each type's default
+        #[arg(short = 'f', long)]
+        format: Option<Format>,
+
+        /// Format for one asset type, as TYPE=FORMAT, for example config=yaml
+        #[arg(long, value_parser = parse_type_format)]
+        type_format: Vec<(String, Format)>,
+
+        /// Write the JSON Schemas of the asset types and refer to them from each document
+        #[arg(long)]
+        schemas: bool,
+
+        /// Keep the source positions of config nodes in a companion file of each config
+        #[arg(long)]
+        config_positions: bool,
     },
     Repack {
-        #[arg(short = 'p', long)]
-        pack_version: PackVersion,
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
 
         #[arg(short = 'i', long)]
         asset: PathBuf,
@@ -47,8 +77,8 @@ enum Commands {
         secret: Option<String>,
     },
     Pack {
-        #[arg(short = 'p', long)]
-        pack_version: PackVersion,
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
 
         #[arg(short = 'i', long)]
         pack_dir: PathBuf,
@@ -59,6 +89,126 @@ enum Commands {
         #[arg(short = 's', long)]
         secret: Option<String>,
     },
+    /// Write the JSON Schemas of the unpacked asset documents
+    Schema {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'o', long)]
+        out_dir: PathBuf,
+    },
+    Convert {
+        #[arg(short = 'f', long, value_parser = parse_pack_version)]
+        from: &'static dyn PackFormat,
+
+        #[arg(short = 't', long, value_parser = parse_pack_version)]
+        to: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'o', long)]
+        out_file: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
+    ExportFont {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'f', long)]
+        font: String,
+
+        #[arg(short = 'o', long)]
+        out_dir: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
+    ImportFont {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        font_file: PathBuf,
+
+        #[arg(short = 'b', long)]
+        bmfont_file: PathBuf,
+
+        #[arg(long)]
+        image_file: Option<PathBuf>,
+    },
+    ExportMesh {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'm', long)]
+        mesh: String,
+
+        #[arg(short = 'o', long)]
+        out_file: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
+    ExportMeshAnimation {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'a', long)]
+        animation: String,
+
+        #[arg(short = 'm', long)]
+        mesh: Option<String>,
+
+        #[arg(short = 'o', long)]
+        out_file: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
+    ExportGraph {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'g', long)]
+        graph: String,
+
+        #[arg(short = 'o', long)]
+        out_file: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
+    ExportNavmesh {
+        #[arg(short = 'p', long, value_parser = parse_pack_version)]
+        pack_version: &'static dyn PackFormat,
+
+        #[arg(short = 'i', long)]
+        asset: PathBuf,
+
+        #[arg(short = 'n', long)]
+        navmesh: String,
+
+        #[arg(short = 'o', long)]
+        out_file: PathBuf,
+
+        #[arg(short = 's', long)]
+        secret: Option<String>,
+    },
     ReadSave {
         #[arg(short = 'i', long)]
         save_file: PathBuf,
@@ -71,6 +221,44 @@ enum Commands {
     },
 }
 
+fn parse_pack_version(s: &str) -> Result<&'static dyn PackFormat, String> {
+    let registry = PackFormatRegistry::builtin();
+    registry
+        .get(s)
+        .map_err(|err| format!("{}, expected one of {}", err, registry.names().join(", ")))
+}
+
+fn parse_type_format(s: &str) -> Result<(String, Format), String> {
+    let (asset_type, format) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected TYPE=FORMAT, got {}", s))?;
+    Ok((asset_type.to_owned(), Format::from_str(format, true)?))
+}
+
+fn unpack_options(
+    pack_format: &dyn PackFormat,
+    format: Option<Format>,
+    type_formats: &[(String, Format)],
+    schemas: bool,
+    config_positions: bool,
+) -> Result<UnpackOptions, anyhow::Error> {
+    let type_formats = type_formats
+        .iter()
+        .map(|(name, format)| {
+            let asset_type = pack_format
+                .asset_type(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown asset type {}", name))?;
+            Ok((asset_type, *format))
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+    Ok(UnpackOptions {
+        format,
+        type_formats,
+        schemas,
+        config_positions,
+    })
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -80,9 +268,26 @@ fn main() {
             out_dir,
             pack_version,
             secret,
+            lossless,
+            format,
+            type_format,
+            schemas,
+            config_positions,
         } => {
+            let options = unpack_options(
+                pack_version,
+                format,
+                &type_format,
+                schemas,
+                config_positions,
+            )
+            .unwrap();
             let pack = read_pack(&asset, pack_version, secret.as_deref());
-            unpack_halley_pk(&*pack, Path::new(&out_dir)).unwrap();
+            if lossless {
+                unpack_halley_pk_lossless(&*pack, Path::new(&out_dir), &options).unwrap();
+            } else {
+                unpack_halley_pk(&*pack, Path::new(&out_dir), &options).unwrap();
+            }
         }
         Commands::Repack {
             asset,
@@ -102,6 +307,98 @@ fn main() {
             let pack = pack_asset(&pack_dir, pack_version);
             write_pack(pack, &out_file, secret.as_deref())
         }
+        Commands::Schema {
+            pack_version,
+            out_dir,
+        } => export_schemas(&out_dir, pack_version).unwrap(),
+        Commands::Convert {
+            from,
+            to,
+            asset,
+            out_file,
+            secret,
+        } => {
+            let issues = convert_pack(
+                &asset,
+                &out_file,
+                from,
+                to,
+                PackFormatRegistry::builtin(),
+                secret.as_deref(),
+            )
+            .unwrap();
+            for issue in &issues {
+                eprintln!("{}", issue);
+            }
+        }
+        Commands::ExportFont {
+            asset,
+            font,
+            out_dir,
+            pack_version,
+            secret,
+        } => {
+            export_font(&asset, &out_dir, &font, pack_version, secret.as_deref()).unwrap();
+        }
+        Commands::ImportFont {
+            font_file,
+            bmfont_file,
+            image_file,
+            pack_version,
+        } => {
+            import_font(
+                &font_file,
+                &bmfont_file,
+                image_file.as_deref(),
+                pack_version,
+            )
+            .unwrap();
+        }
+        Commands::ExportMesh {
+            asset,
+            mesh,
+            out_file,
+            pack_version,
+            secret,
+        } => {
+            export_mesh(&asset, &out_file, &mesh, pack_version, secret.as_deref()).unwrap();
+        }
+        Commands::ExportMeshAnimation {
+            asset,
+            animation,
+            mesh,
+            out_file,
+            pack_version,
+            secret,
+        } => {
+            export_mesh_animation(
+                &asset,
+                &out_file,
+                &animation,
+                mesh.as_deref(),
+                pack_version,
+                secret.as_deref(),
+            )
+            .unwrap();
+        }
+        Commands::ExportGraph {
+            asset,
+            graph,
+            out_file,
+            pack_version,
+            secret,
+        } => {
+            export_graph(&asset, &out_file, &graph, pack_version, secret.as_deref()).unwrap();
+        }
+        Commands::ExportNavmesh {
+            asset,
+            navmesh,
+            out_file,
+            pack_version,
+            secret,
+        } => {
+            export_navmesh(&asset, &out_file, &navmesh, pack_version, secret.as_deref()).unwrap();
+        }
         Commands::ReadSave {
             save_file,
             out_file,